@@ -0,0 +1,189 @@
+use clap::{Parser, Subcommand};
+use config::FileFormat;
+
+use ssh_build_server::util::settings::{format_from_name, format_names};
+
+/// A bridge between an SSH server and your computer for compiling source code.
+#[derive(Debug, Parser)]
+#[command(name = "sbs", version, about)]
+pub struct Args {
+    /// The path to the config file, or `-` to read it from stdin.
+    #[arg(value_name = "CONFIG", conflicts_with = "config")]
+    pub config_path: Option<String>,
+    /// The path to the config file, or `-` to read it from stdin, like the positional argument.
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub config: Option<String>,
+    /// The `[[servers]]` entry of the config to build on.
+    #[arg(short, long, global = true, value_name = "NAME")]
+    pub server: Option<String>,
+    /// The format of a config read from stdin: toml (default), json, yaml, ini, ron or json5.
+    #[arg(long, global = true, value_name = "FORMAT", value_parser = parse_format)]
+    pub config_format: Option<FileFormat>,
+    /// Skip the confirmation prompt of operations that delete remote files: clean_remote, cleanup_remote_after and --changed-since.
+    #[arg(long = "yes", global = true)]
+    pub assume_yes: bool,
+    /// Only run the commands with these (comma-separated) names.
+    #[arg(long, global = true, value_name = "NAMES", value_delimiter = ',')]
+    pub only: Vec<String>,
+    /// Skip the commands with these (comma-separated) names.
+    #[arg(long, global = true, value_name = "NAMES", value_delimiter = ',')]
+    pub skip: Vec<String>,
+    /// Only upload the files that changed since this git reference.
+    #[arg(long, global = true, value_name = "REF")]
+    pub changed_since: Option<String>,
+    /// Compare the uploaded tree with the local one after uploading.
+    #[arg(long, global = true)]
+    pub verify_upload: bool,
+    /// Compare hashes as well when verifying the upload, implies --verify-upload.
+    #[arg(long, global = true)]
+    pub verify_hashes: bool,
+    /// Log details like every file that's transferred or skipped.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Only log warnings and errors.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Print the files and commands of the run instead of connecting, uploading, running or downloading anything.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Print the resolved config, the defaults merged with the config file and server profile, instead of running.
+    #[arg(long, global = true)]
+    pub print_config: bool,
+    /// Run again whenever a file of the local project root changes, over the same connection.
+    #[arg(short, long, global = true, conflicts_with = "dry_run")]
+    pub watch: bool,
+    /// The same as the `explain` subcommand.
+    #[arg(long, hide = true, conflicts_with_all = ["benchmark", "init_remote"])]
+    pub explain: bool,
+    /// The same as the `benchmark` subcommand.
+    #[arg(long, hide = true, conflicts_with = "init_remote")]
+    pub benchmark: bool,
+    /// The same as the `init-remote` subcommand.
+    #[arg(long, hide = true)]
+    pub init_remote: bool,
+    /// What to do, the full pipeline if not given.
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+impl Args {
+    /// Gets what to do, falling back to the full pipeline.
+    ///
+    /// The `--explain`, `--benchmark` and `--init-remote` flags stand in for their subcommands.
+    pub fn action(&self) -> &Action {
+        match (&self.action, self.explain, self.benchmark, self.init_remote) {
+            (Some(action), ..) => action,
+            (None, true, ..) => &Action::Explain,
+            (None, _, true, _) => &Action::Benchmark,
+            (None, _, _, true) => &Action::InitRemote,
+            (None, ..) => &Action::Run,
+        }
+    }
+
+    /// Gets the path to the config file, given either positionally or with `--config`.
+    pub fn config_path(&self) -> Option<&str> {
+        self.config_path.as_deref().or(self.config.as_deref())
+    }
+}
+
+/// A subcommand of the program.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum Action {
+    /// Upload, build, download and run the post-compilation commands (the default).
+    Run,
+    /// Only upload the project.
+    Upload,
+    /// Only run the build commands.
+    Build,
+    /// Only download the output folder.
+    Download,
+    /// Only run the post-compilation commands.
+    Post,
+    /// Describe what the pipeline will do with the given config, without connecting.
+    Explain,
+    /// Measure the connection and print recommended tuning settings.
+    Benchmark,
+    /// Run the provisioning commands.
+    InitRemote,
+    /// Manage the known-hosts file of the tool.
+    Hostkey {
+        #[command(subcommand)]
+        command: HostKeyCommand,
+    },
+}
+
+impl Action {
+    /// Checks whether this subcommand runs the given stage of the pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - One of `Upload`, `Build`, `Download` or `Post`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert!(Action::Run.runs(Action::Upload));
+    /// assert!(!Action::Build.runs(Action::Upload));
+    /// ```
+    pub fn runs(&self, stage: Action) -> bool {
+        *self == Action::Run || *self == stage
+    }
+}
+
+/// A `sbs hostkey` subcommand, managing the known-hosts file of the tool.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum HostKeyCommand {
+    /// Connect to a host and trust its key.
+    Add {
+        host: String,
+    },
+    /// List the trusted hosts.
+    List,
+    /// Stop trusting a host.
+    Remove {
+        host: String,
+    },
+}
+
+/// Parses the name of a config format.
+fn parse_format(name: &str) -> Result<FileFormat, String> {
+    format_from_name(name).ok_or_else(|| format!("Unknown config format '{}', use {}!", name, format_names("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_work_before_and_after_the_subcommand() {
+        let args = Args::try_parse_from(["sbs", "--config", "build.toml", "build", "--only", "lint,test", "--dry-run"]).unwrap();
+
+        assert_eq!(args.config.as_deref(), Some("build.toml"));
+        assert_eq!(args.only, vec!["lint", "test"]);
+        assert!(args.dry_run);
+        assert!(args.action().runs(Action::Build));
+        assert!(!args.action().runs(Action::Upload));
+        assert_eq!(Args::try_parse_from(["sbs"]).unwrap().action(), &Action::Run);
+    }
+
+    #[test]
+    fn the_config_can_be_given_positionally() {
+        let stdin = Args::try_parse_from(["sbs", "-"]).unwrap();
+        let with_action = Args::try_parse_from(["sbs", "build.toml", "upload"]).unwrap();
+
+        assert_eq!(stdin.config_path(), Some("-"));
+        assert_eq!(stdin.action(), &Action::Run);
+        assert_eq!(with_action.config_path(), Some("build.toml"));
+        assert_eq!(with_action.action(), &Action::Upload);
+        assert_eq!(Args::try_parse_from(["sbs", "upload"]).unwrap().config_path(), None);
+        assert!(Args::try_parse_from(["sbs", "a.toml", "--config", "b.toml"]).is_err());
+    }
+
+    #[test]
+    fn flags_stand_in_for_their_subcommands() {
+        assert_eq!(Args::try_parse_from(["sbs", "build.toml", "--explain"]).unwrap().action(), &Action::Explain);
+        assert_eq!(Args::try_parse_from(["sbs", "--benchmark"]).unwrap().action(), &Action::Benchmark);
+        assert_eq!(Args::try_parse_from(["sbs", "--init-remote"]).unwrap().action(), &Action::InitRemote);
+        assert!(Args::try_parse_from(["sbs", "--explain", "--benchmark"]).is_err());
+    }
+}
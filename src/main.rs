@@ -1,113 +1,786 @@
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use clap::Parser;
+use config::FileFormat;
+use env_logger::Env;
+use log::{debug, error, info, warn, Level};
 use ssh2::Session;
 
-use crate::util::settings::Settings;
-use crate::util::ssh::Sbs;
+use ssh_build_server::util::{benchmark, encoding, explain, hostkeys, identity, local, lock, metadata, notify, store, verify, walk};
+use ssh_build_server::util::compression::FileCompression;
+use ssh_build_server::util::connection::Credentials;
+use ssh_build_server::util::docker::Docker;
+use ssh_build_server::util::error::SbsError;
+use ssh_build_server::util::guard::format_size;
+use ssh_build_server::util::ignore::IgnoreRules;
+use ssh_build_server::util::report::RunReport;
+use ssh_build_server::util::settings::{CommandFilter, Settings, Transport};
+use ssh_build_server::util::ssh::{DEFAULT_REMOTE_DIR_MODE, OutputStream, Sbs};
+use ssh_build_server::util::tunnel::JumpHost;
+use ssh_build_server::util::watch::ProjectWatcher;
 
-mod util;
+use crate::cli::{Action, Args, HostKeyCommand};
+
+mod cli;
+
+/// The known-hosts file the server's key is checked against when `ssh.known_hosts_path` isn't set.
+const DEFAULT_KNOWN_HOSTS_PATH: &str = "~/.ssh/known_hosts";
 
 fn main() {
-    // Get the arguments passed to the program.
-    let args: Vec<String> = std::env::args().collect();
+    // Parse the arguments passed to the program, exiting with the usage on errors.
+    let args = Args::parse();
+    init_logger(&args);
 
-    // The first user-supplied argument is the path to the config file.
-    let config_path = match args.get(1) {
+    let config_path = match args.config_path() {
         Some(path) => path,
         None => {
-            eprintln!("No config file path was supplied, using default...");
+            warn!("No config file path was supplied, using default...");
 
             ""
         }
     };
 
     // Load the config.
-    println!("Loading config...");
-    let settings = match Settings::new(config_path) {
+    info!("Loading config...");
+    let config_format = args.config_format.unwrap_or(FileFormat::Toml);
+    let mut settings = match Settings::load(config_path, config_format, args.server.as_deref()) {
         Ok(settings) => settings,
         Err(err) => {
-            eprintln!("Failed to load config: {}", err);
+            error!("Failed to load config: {}", err);
 
             return;
         }
     };
 
+    // Show what the config resolved to, even if it's invalid, since that's usually why it's being looked at.
+    if args.print_config {
+        println!("{:#?}", settings);
+
+        return;
+    }
+
+    // Report every problem of the config at once, before anything runs. Managing host keys only needs a few settings.
+    if !matches!(args.action(), Action::Hostkey { .. }) {
+        if let Err(problems) = settings.validate() {
+            error!("Invalid config:");
+            for problem in problems {
+                error!("  {}", problem);
+            }
+
+            std::process::exit(1);
+        }
+    }
+
+    let command_filter = CommandFilter {
+        only: args.only.clone(),
+        skip: args.skip.clone(),
+    };
+
+    match args.action() {
+        // Manage the known-hosts file instead of building.
+        Action::Hostkey { command } => {
+            if let Err(err) = run_hostkey(command, &settings) {
+                error!("{}", err);
+
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        // Describe the pipeline instead of running it.
+        Action::Explain => {
+            print!("{}", explain::explain(&settings, &command_filter));
+
+            return;
+        }
+        // Benchmark the connection instead of building.
+        Action::Benchmark => {
+            if let Err(err) = run_benchmark(&args, &mut settings) {
+                error!("{}", err);
+
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        // Provision the server instead of building.
+        Action::InitRemote => {
+            if let Err(err) = run_provision(&args, &mut settings) {
+                error!("{}", err);
+
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Action::Run | Action::Upload | Action::Build | Action::Download | Action::Post => {}
+    }
+
+    // Run the pipeline, then report how it went.
+    let mut report = RunReport::new(&settings);
+    let result = run(&args, &mut settings, &mut report);
+    finish_report(&settings, &mut report, result);
+
+    if !report.success {
+        std::process::exit(1);
+    }
+}
+
+/// Logs the error a run ended with, finishes its report and sends the notifications.
+fn finish_report(settings: &Settings, report: &mut RunReport, result: Result<(), String>) {
+    if let Err(err) = &result {
+        error!("{}", err);
+    }
+
+    report.finish(result.err());
+    notify::notify(&settings.notifications, report);
+}
+
+/// Logs the progress by default, only warnings and errors with `--quiet` and every transferred file with `--verbose`.
+///
+/// `RUST_LOG` overrides the flags, e.g. `RUST_LOG=debug` also shows the logs of the dependencies.
+fn init_logger(args: &Args) {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => "warn",
+        (false, true) => "debug",
+        (false, false) => "info",
+    };
+
+    env_logger::Builder::from_env(Env::default().default_filter_or(format!("ssh_build_server={}", level)))
+        .format(|buf, record| {
+            let prefix = match record.level() {
+                Level::Info => return writeln!(buf, "{}", record.args()),
+                Level::Error => "Error",
+                Level::Warn => "Warning",
+                Level::Debug => "Debug",
+                Level::Trace => "Trace",
+            };
+
+            writeln!(buf, "{}: {}", prefix, record.args())
+        })
+        .init();
+}
+
+/// Creates an SBS instance from the settings, connects it to the SSH server and resolves the remote project root.
+///
+/// A dry run doesn't touch the server, so its instance stays unconnected and the configured remote project root is used.
+fn connect(args: &Args, settings: &mut Settings) -> Result<Sbs, String> {
+    let mut sbs = if args.dry_run {
+        configure(args, settings)?
+    } else {
+        open_connection(args, settings)?
+    };
+
+    // Let the remote decide where the project goes.
+    if let Some(command) = &settings.compilation.remote_project_root_command {
+        if args.dry_run {
+            println!("Would resolve the remote project root with `{}`, using '{}' for now.", command, settings.compilation.remote_project_root);
+        } else {
+            info!("Resolving remote project root...");
+            match sbs.resolve_remote_path(command) {
+                Ok(path) => settings.compilation.remote_project_root = path,
+                Err(err) => return Err(format!("Failed to resolve remote project root: {}", err)),
+            }
+        }
+    }
+    sbs.working_directory = Some(settings.compilation.get_remote_upload_directory());
+
+    Ok(sbs)
+}
+
+/// Creates an SBS instance from the settings and connects it to the SSH server.
+fn open_connection(args: &Args, settings: &Settings) -> Result<Sbs, String> {
+    let mut sbs = configure(args, settings)?;
+
     // Connect to the local SSH.
-    println!("Connecting to SSH...");
-    let mut sbs = Sbs::new(Session::new().unwrap());
-    match sbs.connect(
+    info!("Connecting to SSH...");
+    let private_keys = identity::candidates(&settings.ssh.host, settings.ssh.private_key_path.as_deref().map(Path::new));
+    let password = settings.ssh.resolve_password().map_err(|err| format!("Failed to get the SSH password: {}", err))?;
+    let credentials = Credentials {
+        password: password.as_deref(),
+        private_keys: &private_keys,
+        passphrase: settings.ssh.passphrase.as_deref(),
+    };
+    match sbs.connect_with(
         &settings.ssh.host,
         &settings.ssh.port,
         &settings.ssh.username,
-        &settings.ssh.password,
+        credentials,
+        &settings.ssh.auth_order,
     ) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to connect to SSH: {}", err);
+        Ok(method) => info!("Authenticated using {}.", method),
+        Err(err) => return Err(format!("Failed to connect to SSH: {}", err)),
+    }
 
-            return;
-        }
+    Ok(sbs)
+}
+
+/// Creates an unconnected SBS instance from the settings.
+fn configure(args: &Args, settings: &Settings) -> Result<Sbs, String> {
+    let mut sbs = Sbs::new(Session::new().unwrap());
+    sbs.assume_yes = args.assume_yes;
+    sbs.dry_run = args.dry_run;
+    sbs.command_filter = CommandFilter {
+        only: args.only.clone(),
+        skip: args.skip.clone(),
+    };
+    sbs.preserve_xattrs = settings.compilation.preserve_xattrs;
+    sbs.symlinks = settings.compilation.symlinks;
+    sbs.deterministic_order = settings.compilation.deterministic_order;
+    sbs.output_buffer_size = settings.compilation.output_buffer_size;
+    sbs.login_shell = settings.ssh.login_shell;
+    sbs.compression = settings.ssh.compression;
+    sbs.address_family = settings.ssh.address_family;
+    sbs.keepalive = (settings.ssh.keepalive_secs > 0).then(|| Duration::from_secs(settings.ssh.keepalive_secs));
+    sbs.incremental_download = settings.compilation.incremental_download;
+    sbs.incremental_upload = settings.compilation.incremental_upload;
+    sbs.transfer_mode = settings.compilation.transfer_mode;
+    sbs.parallel_transfers = settings.compilation.parallel_transfers.max(1);
+    sbs.remote_dir_mode = settings.compilation.remote_dir_mode as i32;
+    sbs.remote_file_mode = settings.compilation.remote_file_mode.map(|mode| mode as i32);
+    sbs.measure_resources = settings.compilation.measure_resources;
+    sbs.merge_stderr = settings.compilation.merge_stderr;
+    sbs.command_timeout = (settings.compilation.command_timeout_secs > 0).then(|| Duration::from_secs(settings.compilation.command_timeout_secs));
+    sbs.connect_timeout = (settings.ssh.connect_timeout_secs > 0).then(|| Duration::from_secs(settings.ssh.connect_timeout_secs));
+    sbs.max_retries = settings.ssh.max_retries;
+    sbs.retry_backoff = Duration::from_millis(settings.ssh.retry_backoff_ms);
+    sbs.jump_host = settings.ssh.jump_host.as_deref().map(JumpHost::parse).transpose()?;
+    sbs.ignore = load_ignore(settings)?;
+    if settings.compilation.compress_files {
+        sbs.file_compression = Some(FileCompression {
+            min_size: settings.compilation.compress_min_size,
+            incompressible_extensions: settings.compilation.incompressible_extensions.clone(),
+        });
     }
+    sbs.known_hosts_path = Some(identity::expand_user_path(settings.ssh.known_hosts_path.as_deref().unwrap_or(DEFAULT_KNOWN_HOSTS_PATH)));
+    sbs.host_key_checking = settings.ssh.strict_host_key_checking;
+    sbs.output_encoding = encoding::from_label(&settings.compilation.output_encoding).map_err(|err| err.to_string())?;
+    sbs.environment.extend(local::forwarded_environment(&settings.compilation.send_env));
+    if settings.compilation.build_metadata {
+        sbs.environment.extend(metadata::collect(Path::new(&settings.compilation.local_project_root)));
+    }
+    Ok(sbs)
+}
 
-    // Clone the directory to the local SSH.
-    println!("Copying project to remote... ({} -> {})",
-             settings.compilation.local_project_root,
-             settings.compilation.remote_project_root
-    );
-    match sbs.send_directory(
-        Path::new(&settings.compilation.local_project_root),
-        Path::new(&settings.compilation.remote_project_root),
-    ) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to copy project: {}", err);
+/// Loads the patterns of the local files that aren't uploaded.
+fn load_ignore(settings: &Settings) -> Result<IgnoreRules, String> {
+    let compilation = &settings.compilation;
 
-            return;
+    IgnoreRules::load(Path::new(&compilation.local_project_root), &compilation.ignore, compilation.use_gitignore)
+        .map_err(|err| format!("Failed to read the .gitignore: {}", err))
+}
+
+/// Adds, lists or removes entries of the known-hosts file.
+fn run_hostkey(command: &HostKeyCommand, settings: &Settings) -> Result<(), String> {
+    let known_hosts_path = settings.ssh.known_hosts_path.as_deref()
+        .map(identity::expand_user_path)
+        .ok_or("Set ssh.known_hosts_path to manage host keys!")?;
+    let port = settings.ssh.port;
+
+    match command {
+        HostKeyCommand::Add { host } => {
+            let fingerprint = hostkeys::add(&known_hosts_path, host, port)
+                .map_err(|err| format!("Failed to add the host key of '{}': {}", host, err))?;
+
+            println!("Added '{}' ({}).", hostkeys::entry_name(host, port), fingerprint);
+        }
+        HostKeyCommand::List => {
+            let hosts = hostkeys::list(&known_hosts_path)
+                .map_err(|err| format!("Failed to list host keys: {}", err))?;
+
+            for (host, key) in hosts {
+                println!("{} {}", host, key);
+            }
+        }
+        HostKeyCommand::Remove { host } => {
+            let removed = hostkeys::remove(&known_hosts_path, host, port)
+                .map_err(|err| format!("Failed to remove the host key of '{}': {}", host, err))?;
+
+            println!("Removed {} entry(s) for '{}'.", removed, hostkeys::entry_name(host, port));
         }
     }
 
-    // Make the SSH server execute the commands.
-    println!("Compiling code...");
-    match sbs.execute_commands(&settings.commands.to_vec(), false) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to compile code: {}", err);
+    Ok(())
+}
 
-            return;
+/// Measures the connection and prints recommended tuning settings.
+fn run_benchmark(args: &Args, settings: &mut Settings) -> Result<(), String> {
+    // There's nothing to measure without touching the server.
+    if args.dry_run {
+        return Err("--dry-run isn't supported by benchmark!".to_string());
+    }
+
+    let mut sbs = connect(args, settings)?;
+
+    info!("Benchmarking connection...");
+    let result = benchmark::run(&sbs);
+
+    if let Err(err) = sbs.disconnect(None, "", None) {
+        warn!("Failed to disconnect from SSH: {}", err);
+    }
+
+    let results = result.map_err(|err| format!("Failed to benchmark connection: {}", err))?;
+    println!("Command round-trip: {:.1} ms", results.round_trip.as_secs_f64() * 1000.0);
+    println!("Small files: {:.1} files/s", results.small_files_per_second);
+    println!("Throughput: {}/s", format_size(results.throughput as u64));
+
+    println!("Recommendations:");
+    for recommendation in results.recommendations() {
+        println!("  {}", recommendation);
+    }
+
+    Ok(())
+}
+
+/// Runs the provisioning commands on the SSH server.
+fn run_provision(args: &Args, settings: &mut Settings) -> Result<(), String> {
+    let mut sbs = connect(args, settings)?;
+
+    info!("Provisioning remote...");
+    let result = sbs.provision(&settings.provision.commands);
+
+    if let Err(err) = sbs.disconnect(None, "", None) {
+        warn!("Failed to disconnect from SSH: {}", err);
+    }
+
+    let output = result.map_err(|err| format!("Failed to provision remote: {}", err))?;
+    print!("{}", output.stdout);
+
+    Ok(())
+}
+
+/// Runs the upload, build, download and post-compilation pipeline in a local Docker container, or the stage picked by the subcommand.
+fn run_docker(args: &Args, settings: &mut Settings, report: &mut RunReport) -> Result<(), String> {
+    let action = args.action();
+    let container = settings.ssh.container.as_deref()
+        .ok_or("The docker transport needs ssh.container to be set!")?;
+
+    // Ignoring it would run the build for real.
+    if args.dry_run {
+        return Err("--dry-run isn't supported by the docker transport!".to_string());
+    }
+    if args.watch {
+        return Err("--watch isn't supported by the docker transport!".to_string());
+    }
+
+    let compilation = &settings.compilation;
+    let has_timeouts = compilation.command_timeout_secs > 0 || settings.commands.iter().any(|command| command.timeout_secs.is_some());
+    let has_remote_modes = compilation.remote_dir_mode != DEFAULT_REMOTE_DIR_MODE as u32 || compilation.remote_file_mode.is_some();
+
+    // Ignoring these would quietly drop a protection that was asked for.
+    let unsupported = [
+        ("--verify-upload", args.verify_upload || args.verify_hashes),
+        ("lock", compilation.lock),
+        ("command timeouts", has_timeouts),
+        ("clean_remote", compilation.clean_remote),
+        ("verify_checksums", compilation.verify_checksums),
+        ("cleanup_remote_after", compilation.cleanup_remote_after),
+        ("remote_dir_mode and remote_file_mode", has_remote_modes),
+    ].into_iter().filter(|(_, used)| *used).map(|(name, _)| name).collect::<Vec<_>>();
+    if !unsupported.is_empty() {
+        return Err(format!("The docker transport doesn't support {}!", unsupported.join(", ")));
+    }
+
+    if args.changed_since.is_some() || compilation.concurrent_download.is_some() || compilation.sequential_commands || compilation.incremental_upload || !compilation.download_patterns.is_empty() {
+        warn!("--changed-since, concurrent_download, sequential_commands, incremental_upload and download_patterns aren't supported by the docker transport, ignoring them.");
+    }
+
+    let mut docker = Docker::new(container);
+    docker.command_filter = CommandFilter {
+        only: args.only.clone(),
+        skip: args.skip.clone(),
+    };
+    docker.login_shell = settings.ssh.login_shell;
+    docker.output_encoding = encoding::from_label(&settings.compilation.output_encoding).map_err(|err| err.to_string())?;
+    docker.ignore = load_ignore(settings)?;
+    docker.environment.extend(local::forwarded_environment(&settings.compilation.send_env));
+    if settings.compilation.build_metadata {
+        docker.environment.extend(metadata::collect(Path::new(&settings.compilation.local_project_root)));
+    }
+
+    info!("Connecting to container '{}'...", container);
+    report.stage("connect", || docker.check_running())
+        .map_err(|err| format!("Failed to connect to container: {}", err))?;
+
+    // Expand `~`, which `docker cp` doesn't understand.
+    let remote_project_root = docker.resolve_path(&settings.compilation.remote_project_root)
+        .map_err(|err| format!("Failed to resolve remote project root: {}", err))?;
+    settings.compilation.remote_project_root = remote_project_root;
+    docker.working_directory = Some(settings.compilation.get_remote_upload_directory());
+    report.remote_project_root = settings.compilation.remote_project_root.clone();
+
+    // Politely skip the run if the container isn't ready for it.
+    if let Some(precondition_command) = &settings.compilation.precondition_command {
+        info!("Checking precondition...");
+        let (exit_code, output) = report.stage("precondition", || docker.run_remote_command(precondition_command))
+            .map_err(|err| format!("Failed to check precondition: {}", err))?;
+
+        if exit_code != 0 {
+            info!("Skipping run, the precondition exited with code {}.", exit_code);
+            print!("{}", output);
+            report.skipped = Some(format!("The precondition exited with code {}.", exit_code));
+
+            return Ok(());
         }
     }
 
-    // Download the output folder from the SSH server.
-    println!("Downloading output folder...");
-    match sbs.receive_directory(
-        Path::new(&settings.compilation.get_local_output_directory()),
-        Path::new(&settings.compilation.get_remote_output_directory()),
-    ) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to download output folder: {}", err);
+    if action.runs(Action::Upload) {
+        run_local_commands(args, settings, report)?;
 
-            return;
+        info!("Copying project to container... ({} -> {})",
+              settings.compilation.local_project_root,
+              settings.compilation.get_remote_upload_directory()
+        );
+        report.stage("upload", || docker.send_directory(
+            Path::new(&settings.compilation.local_project_root),
+            &settings.compilation.get_remote_upload_directory(),
+        )).map_err(|err| format!("Failed to copy project: {}", err))?;
+    }
+
+    if action.runs(Action::Build) {
+        info!("Compiling code...");
+        report.stage("build", || docker.execute_commands(&settings.commands, false))
+            .map_err(|err| format!("Failed to compile code: {}", err))?;
+    }
+
+    if action.runs(Action::Download) {
+        info!("Downloading output folder...");
+        report.stage("download", || docker.receive_directory(
+            Path::new(&settings.compilation.get_local_output_directory()),
+            &settings.compilation.get_remote_output_directory(),
+        )).map_err(|err| format!("Failed to download output folder: {}", err))?;
+
+        if let Some(verify_command) = &settings.compilation.verify_command {
+            info!("Verifying output...");
+            let output_directory = settings.compilation.get_local_output_directory();
+            report.stage("verify", || local::run_checked(verify_command, &[&output_directory], &[("SBS_OUTPUT_DIR", &output_directory)]))
+                .map_err(|err| format!("Failed to verify output: {}", err))?;
         }
     }
 
-    // Execute post-compilation commands.
-    println!("Executing post-compilation commands...");
-    match sbs.execute_commands(&settings.commands.to_vec(), true) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to execute post-compilation commands: {}", err);
+    if action.runs(Action::Post) {
+        info!("Executing post-compilation commands...");
+        report.stage("post-compilation", || docker.execute_commands(&settings.commands, true))
+            .map_err(|err| format!("Failed to execute post-compilation commands: {}", err))?;
+    }
 
-            return;
+    Ok(())
+}
+
+/// Runs the upload, build, download and post-compilation pipeline, or the stage picked by the subcommand.
+fn run(args: &Args, settings: &mut Settings, report: &mut RunReport) -> Result<(), String> {
+    let action = args.action();
+
+    // Make sure the project root isn't pointing at something huge.
+    if action.runs(Action::Upload) {
+        walk::check_file_limit(Path::new(&settings.compilation.local_project_root), &load_ignore(settings)?, settings.compilation.max_files)
+            .map_err(|err| format!("Failed to copy project: {}", err))?;
+    }
+
+    if settings.ssh.transport == Transport::Docker {
+        return run_docker(args, settings, report);
+    }
+
+    let mut sbs = report.stage("connect", || connect(args, settings))?;
+    report.remote_project_root = settings.compilation.remote_project_root.clone();
+    report.local_address = sbs.connection.local_address().map(|address| address.to_string());
+    report.remote_address = sbs.connection.peer_address().map(|address| address.to_string());
+
+    // A dry run doesn't even connect, everything is printed instead of done.
+    if args.dry_run {
+        info!("Dry run, nothing is uploaded, run or downloaded...");
+    }
+
+    // Politely skip the run if the server isn't ready for it.
+    if let Some(precondition_command) = settings.compilation.precondition_command.as_ref().filter(|_| !args.dry_run) {
+        info!("Checking precondition...");
+        let (exit_code, output) = report.stage("precondition", || sbs.run_remote_command(precondition_command))
+            .map_err(|err| format!("Failed to check precondition: {}", err))?;
+
+        if exit_code != 0 {
+            info!("Skipping run, the precondition exited with code {}.", exit_code);
+            print!("{}", output);
+            report.skipped = Some(format!("The precondition exited with code {}.", exit_code));
+
+            if let Err(err) = sbs.disconnect(None, "", None) {
+                warn!("Failed to disconnect from SSH: {}", err);
+            }
+
+            return Ok(());
         }
     }
 
-    // Disconnect from the SSH server.
-    println!("Disconnecting from SSH...");
+    // Make sure no other run is using the same remote directory.
+    let remote_lock = if settings.compilation.lock && !args.dry_run {
+        info!("Locking remote project root...");
+        let remote_project_root = Path::new(&settings.compilation.remote_project_root);
+        let timeout = Duration::from_secs(settings.compilation.lock_timeout_secs);
+
+        Some(report.stage("lock", || lock::acquire(&sbs, remote_project_root, timeout))
+            .map_err(|err| format!("Failed to lock remote project root: {}", err))?)
+    } else {
+        None
+    };
+
+    // Watch from before the first run on, so changes made during it trigger the next one.
+    let watcher = if args.watch {
+        let compilation = &settings.compilation;
+        let mut excluded = vec![PathBuf::from(compilation.get_local_output_directory())];
+        excluded.extend(compilation.concurrent_download.iter().map(|download| PathBuf::from(&download.local_directory)));
+
+        Some(ProjectWatcher::new(
+            Path::new(&compilation.local_project_root),
+            load_ignore(settings)?,
+            &excluded,
+            Duration::from_millis(compilation.watch_debounce_ms),
+        ).map_err(|err| format!("Failed to watch the project: {}", err))?)
+    } else {
+        None
+    };
+
+    let mut result = run_stages(args, settings, &sbs, report);
+
+    // Run again on every change, over the same connection, until watching fails or the program is stopped.
+    if let Some(watcher) = &watcher {
+        result = watch(args, settings, &sbs, report, watcher, result);
+    }
+    result?;
+
+    // Release the lock before the session goes away.
+    drop(remote_lock);
+
+    // Disconnect from the SSH server, which a dry run never connected to.
+    if args.dry_run {
+        return Ok(());
+    }
+
+    info!("Disconnecting from SSH...");
     match sbs.disconnect(None, "", None) {
         Ok(_) => {}
         Err(err) => {
-            eprintln!("Failed to disconnect from SSH: {}", err);
+            warn!("Failed to disconnect from SSH: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-runs the stages whenever the local project changes.
+///
+/// Every run is reported on its own, so the report left when watching fails only holds why it did.
+fn watch(args: &Args, settings: &Settings, sbs: &Sbs, report: &mut RunReport, watcher: &ProjectWatcher, mut result: Result<(), String>) -> Result<(), String> {
+    loop {
+        let mut next_report = RunReport::new(settings);
+        next_report.remote_project_root = report.remote_project_root.clone();
+        next_report.local_address = report.local_address.clone();
+        next_report.remote_address = report.remote_address.clone();
+        finish_report(settings, &mut std::mem::replace(report, next_report), result);
+
+        info!("Watching '{}' for changes...", settings.compilation.local_project_root);
+        let changed = watcher.wait_for_changes().map_err(|err| format!("Failed to watch the project: {}", err))?;
+        for path in &changed {
+            debug!("'{}' changed.", path.display());
+        }
+
+        info!("{} path(s) changed, running again...", changed.len());
+        result = run_stages(args, settings, sbs, report);
+    }
+}
+
+/// Runs the local commands that prepare the project before it's uploaded, stopping at the first failure.
+fn run_local_commands(args: &Args, settings: &Settings, report: &mut RunReport) -> Result<(), String> {
+    if settings.local_commands.is_empty() {
+        return Ok(());
+    }
+
+    info!("Running local commands...");
+    report.stage("local-commands", || {
+        for command in &settings.local_commands {
+            if args.dry_run {
+                println!("Would run `{}` locally ({}).", command.command, command.description);
+
+                continue;
+            }
+
+            info!("Running `{}` locally ({})...", command.command, command.description);
+            let envs = command.env.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+            local::run_logged(&command.command, &command.resolve_working_dir(&settings.compilation.local_project_root), &envs)?;
+        }
+
+        Ok::<_, io::Error>(())
+    }).map_err(|err| format!("Failed to run local commands: {}", err))
+}
+
+/// Runs the upload, build, download and post-compilation stages picked by the subcommand.
+fn run_stages(args: &Args, settings: &Settings, sbs: &Sbs, report: &mut RunReport) -> Result<(), String> {
+    let action = args.action();
+
+    // Clone the directory to the local SSH.
+    if action.runs(Action::Upload) {
+        run_local_commands(args, settings, report)?;
+
+        info!("Copying project to remote... ({} -> {})",
+              settings.compilation.local_project_root,
+              settings.compilation.get_remote_upload_directory()
+        );
+
+        // Start from a pristine tree.
+        if settings.compilation.clean_remote {
+            let upload_directory = settings.compilation.get_remote_upload_directory();
+            report.stage("clean", || sbs.wipe_directory(Path::new(&upload_directory)))
+                .map_err(|err| format!("Failed to clean the remote project: {}", err))?;
+        }
+
+        report.stage("upload", || match (&args.changed_since, &settings.compilation.concurrent_download) {
+            (Some(reference), _) => {
+                info!("Only sending files changed since '{}'...", reference);
+
+                sbs.send_changed_since(
+                    Path::new(&settings.compilation.local_project_root),
+                    Path::new(&settings.compilation.get_remote_upload_directory()),
+                    reference,
+                )
+            }
+            // Without a connection, the plain transfers print what they would do.
+            (None, download) if args.dry_run => sbs.send_directory(
+                Path::new(&settings.compilation.local_project_root),
+                Path::new(&settings.compilation.get_remote_upload_directory()),
+            ).and_then(|_| match download {
+                Some(download) => sbs.receive_directory(Path::new(&download.local_directory), Path::new(&download.remote_directory)).map(|_| ()),
+                None => Ok(()),
+            }),
+            (None, Some(download)) => {
+                info!("Downloading concurrently over a second connection... ({} -> {})", download.remote_directory, download.local_directory);
+
+                let mut receiver = open_connection(args, settings).map_err(|err| SbsError::Connect(io::Error::other(err)))?;
+                let result = sbs.send_and_receive_directories(
+                    &receiver,
+                    Path::new(&settings.compilation.local_project_root),
+                    Path::new(&settings.compilation.get_remote_upload_directory()),
+                    Path::new(&download.local_directory),
+                    Path::new(&download.remote_directory),
+                );
+
+                if let Err(err) = receiver.disconnect(None, "", None) {
+                    warn!("Failed to disconnect the download connection: {}", err);
+                }
+
+                result
+            }
+            (None, None) => match &settings.compilation.content_store_dir {
+                Some(content_store_dir) => store::upload(
+                    sbs,
+                    Path::new(&settings.compilation.local_project_root),
+                    Path::new(&settings.compilation.get_remote_upload_directory()),
+                    Path::new(content_store_dir),
+                ).map(|upload| info!("Sent {} file(s), reused {} from the content store.", upload.sent, upload.reused)).map_err(SbsError::Transfer),
+                None => sbs.send_directory(
+                    Path::new(&settings.compilation.local_project_root),
+                    Path::new(&settings.compilation.get_remote_upload_directory()),
+                ).map(|summary| info!("Sent {}.", summary)),
+            },
+        }).map_err(|err| format!("Failed to copy project: {}", err))?;
+
+        // Make sure the remote mirrors the local tree.
+        if (args.verify_upload || args.verify_hashes) && !args.dry_run {
+            info!("Verifying upload...");
+            let difference = report.stage("verify-upload", || verify::verify_upload(
+                sbs,
+                Path::new(&settings.compilation.local_project_root),
+                Path::new(&settings.compilation.get_remote_upload_directory()),
+                args.verify_hashes,
+            )).map_err(|err| format!("Failed to verify upload: {}", err))?;
+
+            println!("{}", difference);
+            if !difference.is_empty() {
+                return Err("Failed to verify upload: The remote tree doesn't match the local one!".to_string());
+            }
+        }
+    }
+
+    // Show the output of the commands while they run, or once they're done when they run one by one.
+    let execute_commands = |is_after_compilation| {
+        if settings.compilation.sequential_commands {
+            let output = sbs.execute_commands_sequential(&settings.commands, is_after_compilation)?;
+            print!("{}", output.stdout);
+            eprint!("{}", output.stderr);
+
+            return Ok::<_, SbsError>(output);
+        }
+
+        sbs.execute_commands_streaming(&settings.commands, is_after_compilation, |stream, lines| {
+            let mut writer: Box<dyn Write> = match stream {
+                OutputStream::Stdout => Box::new(io::stdout()),
+                OutputStream::Stderr => Box::new(io::stderr()),
+            };
+            let _ = writer.write_all(lines);
+            let _ = writer.flush();
+        })
+    };
+
+    // Make the SSH server execute the commands.
+    if action.runs(Action::Build) {
+        info!("Compiling code...");
+        let build = report.stage("build", || execute_commands(false));
+        report.resource_usage = sbs.resource_usage();
+        build.map_err(|err| format!("Failed to compile code: {}", err))?;
+    }
+
+    // Download the output folder from the SSH server.
+    if action.runs(Action::Download) {
+        info!("Downloading output folder...");
+        let local_output_directory = settings.compilation.get_local_output_directory();
+        let remote_output_directory = settings.compilation.get_remote_output_directory();
+        let download_patterns = &settings.compilation.download_patterns;
+        let summary = report.stage("download", || if download_patterns.is_empty() {
+            sbs.receive_directory(Path::new(&local_output_directory), Path::new(&remote_output_directory))
+        } else {
+            sbs.receive_matching(Path::new(&local_output_directory), Path::new(&remote_output_directory), download_patterns)
+        }).map_err(|err| format!("Failed to download output folder: {}", err))?;
+
+        // A dry run only printed what it would receive.
+        if !args.dry_run {
+            info!("Received {}.", summary);
+        }
+
+        // Make sure nothing was truncated or corrupted on the way.
+        if settings.compilation.verify_checksums && !args.dry_run {
+            info!("Verifying checksums...");
+            report.stage("verify-checksums", || verify::verify_download(
+                sbs,
+                Path::new(&local_output_directory),
+                Path::new(&remote_output_directory),
+                download_patterns,
+            )).map_err(|err| format!("Failed to verify the download: {}", err))?;
+        }
+
+        // Verify the downloaded output locally.
+        if let Some(verify_command) = settings.compilation.verify_command.as_ref().filter(|_| !args.dry_run) {
+            info!("Verifying output...");
+            let output_directory = settings.compilation.get_local_output_directory();
+            report.stage("verify", || local::run_checked(verify_command, &[&output_directory], &[("SBS_OUTPUT_DIR", &output_directory)]))
+                .map_err(|err| format!("Failed to verify output: {}", err))?;
         }
     }
+
+    // Execute post-compilation commands.
+    if action.runs(Action::Post) {
+        info!("Executing post-compilation commands...");
+        report.stage("post-compilation", || execute_commands(true))
+            .map_err(|err| format!("Failed to execute post-compilation commands: {}", err))?;
+    }
+
+    // Free space on the server, last, so the post-compilation commands still find the output. Any failure before returned already,
+    // leaving everything in place for debugging.
+    if action.runs(Action::Download) && settings.compilation.cleanup_remote_after {
+        let cleanup_directory = settings.compilation.get_remote_cleanup_directory();
+
+        // Previewed and confirmed like any deletion, unless --yes was passed.
+        report.stage("cleanup", || sbs.wipe_directory(Path::new(&cleanup_directory)))
+            .map_err(|err| format!("Failed to clean up the remote: {}", err))?;
+    }
+
+    Ok(())
 }
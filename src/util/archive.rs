@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+
+use tar::{Builder, EntryType, Header};
+
+/// Writes a tar archive of a directory tree, with the paths relative to its root.
+///
+/// Files are archived with their modification time and their mode, unless a file mode is given.
+/// Directories are archived with the directory mode, and symlinks point at their targets unchanged.
+///
+/// # Arguments
+///
+/// * `archive_path` - The local path of the archive to write.
+/// * `directories` - The directories, by local path and path in the archive.
+/// * `files` - The files, by local path and path in the archive.
+/// * `links` - The symlinks, by target and path in the archive.
+/// * `dir_mode` - The mode of the directories.
+/// * `file_mode` - The mode of the files, or `None` to keep theirs.
+///
+/// # Examples
+///
+/// ```ignore
+/// write(Path::new("/tmp/upload.tar"), &[], &[(PathBuf::from("/project/main.rs"), PathBuf::from("main.rs"))], &[], 0o755, None).unwrap();
+/// ```
+pub fn write(
+    archive_path: &Path,
+    directories: &[(PathBuf, PathBuf)],
+    files: &[(PathBuf, PathBuf)],
+    links: &[(PathBuf, PathBuf)],
+    dir_mode: u32,
+    file_mode: Option<u32>,
+) -> Result<(), Error> {
+    let mut builder = Builder::new(BufWriter::new(File::create(archive_path)?));
+
+    for (local_path, archived_path) in directories {
+        let mut header = Header::new_gnu();
+        header.set_metadata(&local_path.metadata()?);
+        header.set_mode(dir_mode);
+
+        builder.append_data(&mut header, archived_path, io::empty())?;
+    }
+
+    for (local_path, archived_path) in files {
+        // Symlinks among the files are followed, like `symlinks = "follow"` does.
+        match file_mode {
+            Some(file_mode) => {
+                let file = File::open(local_path)?;
+                let mut header = Header::new_gnu();
+                header.set_metadata(&file.metadata()?);
+                header.set_mode(file_mode);
+
+                builder.append_data(&mut header, archived_path, file)?;
+            }
+            None => builder.append_path_with_name(local_path, archived_path)?,
+        }
+    }
+
+    for (target, archived_path) in links {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_size(0);
+
+        builder.append_link(&mut header, archived_path, target)?;
+    }
+
+    builder.into_inner()?.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use tar::Archive;
+
+    use super::*;
+
+    #[test]
+    fn archive_holds_the_tree_under_relative_paths() {
+        let directory = std::env::temp_dir().join(format!("sbs-archive-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("src")).unwrap();
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let archive_path = directory.join("upload.tar");
+        let written = write(
+            &archive_path,
+            &[(directory.join("src"), PathBuf::from("src"))],
+            &[(directory.join("src/main.rs"), PathBuf::from("src/main.rs"))],
+            &[(PathBuf::from("src/main.rs"), PathBuf::from("main.rs"))],
+            0o700,
+            Some(0o600),
+        );
+        let entries = written.and_then(|_| {
+            Archive::new(File::open(&archive_path)?).entries()?
+                .map(|entry| {
+                    let entry = entry?;
+
+                    Ok((entry.path()?.into_owned(), entry.header().entry_type(), entry.header().mode()?, entry.size()))
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        });
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(entries.unwrap(), vec![
+            (PathBuf::from("src"), EntryType::Directory, 0o700, 0),
+            (PathBuf::from("src/main.rs"), EntryType::Regular, 0o600, 12),
+            (PathBuf::from("main.rs"), EntryType::Symlink, 0o777, 0),
+        ]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::util::error::SbsError;
+use crate::util::settings::Command;
+use crate::util::ssh::{CommandOutput, Sbs};
+use crate::util::transfer::TransferSummary;
+
+/// An SBS instance for async code, running every blocking operation on tokio's blocking thread pool.
+///
+/// An SSH session does one thing at a time, so operations on the same instance still run one after the other.
+/// Separate instances (e.g. one per build server) run concurrently.
+#[derive(Clone)]
+pub struct AsyncSbs {
+    sbs: Arc<Mutex<Sbs>>,
+}
+
+impl AsyncSbs {
+    /// Wraps an SBS instance, configured like a blocking one.
+    ///
+    /// # Arguments
+    ///
+    /// * `sbs` - The SBS instance.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut sbs = Sbs::new(Session::new().unwrap());
+    /// sbs.parallel_transfers = 4;
+    ///
+    /// let sbs = AsyncSbs::new(sbs);
+    /// ```
+    pub fn new(sbs: Sbs) -> Self {
+        Self {
+            sbs: Arc::new(Mutex::new(sbs)),
+        }
+    }
+
+    /// Runs a blocking operation on the SBS instance, for everything without an async variant of its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - The operation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// let remote_path = sbs.run(|sbs| sbs.resolve_remote_path("echo $HOME")).await.unwrap();
+    /// ```
+    pub async fn run<T, F>(&self, operation: F) -> Result<T, SbsError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Sbs) -> Result<T, SbsError> + Send + 'static,
+    {
+        let sbs = Arc::clone(&self.sbs);
+
+        tokio::task::spawn_blocking(move || {
+            // A panicked operation leaves nothing half-changed that later operations would trip over.
+            let mut sbs = sbs.lock().unwrap_or_else(|err| err.into_inner());
+
+            operation(&mut sbs)
+        })
+            .await
+            .map_err(|err| SbsError::Io(Error::other(format!("A blocking SBS operation failed to finish: {}", err))))?
+    }
+
+    /// Connects to the SSH server, see `Sbs::connect`.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `port` - The port.
+    /// * `username` - The username.
+    /// * `password` - The password.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(Sbs::new(session));
+    ///
+    /// sbs.connect("localhost", 22, "username", "password").await.unwrap();
+    /// ```
+    pub async fn connect(&self, host: &str, port: u16, username: &str, password: &str) -> Result<(), SbsError> {
+        let (host, username, password) = (host.to_string(), username.to_string(), password.to_string());
+
+        self.run(move |sbs| sbs.connect(&host, &port, &username, &password)).await
+    }
+
+    /// Connects to the SSH server, authenticating with the running SSH agent, see `Sbs::connect_with_agent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `port` - The port.
+    /// * `username` - The username.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(Sbs::new(session));
+    ///
+    /// sbs.connect_with_agent("localhost", 22, "username").await.unwrap();
+    /// ```
+    pub async fn connect_with_agent(&self, host: &str, port: u16, username: &str) -> Result<(), SbsError> {
+        let (host, username) = (host.to_string(), username.to_string());
+
+        self.run(move |sbs| sbs.connect_with_agent(&host, &port, &username)).await
+    }
+
+    /// Disconnects from the SSH server.
+    ///
+    /// # Arguments
+    ///
+    /// * `description` - The reason given to the server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// sbs.disconnect("Done.").await.unwrap();
+    /// ```
+    pub async fn disconnect(&self, description: &str) -> Result<(), SbsError> {
+        let description = description.to_string();
+
+        self.run(move |sbs| sbs.disconnect(None, &description, None)).await
+    }
+
+    /// Sends a list of commands to the SSH server, see `Sbs::execute_commands`.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - The commands.
+    /// * `is_after_compilation` - Whether this function is called before or after program compilation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// let output = sbs.execute_commands(settings.commands.clone(), false).await.unwrap();
+    /// ```
+    pub async fn execute_commands(&self, commands: Vec<Command>, is_after_compilation: bool) -> Result<CommandOutput, SbsError> {
+        self.run(move |sbs| sbs.execute_commands(&commands, is_after_compilation)).await
+    }
+
+    /// Runs a single shell command on the SSH server, see `Sbs::run_remote_command`.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// let (exit_code, output) = sbs.run_remote_command("uname -a").await.unwrap();
+    /// ```
+    pub async fn run_remote_command(&self, command: &str) -> Result<(i32, String), SbsError> {
+        let command = command.to_string();
+
+        self.run(move |sbs| sbs.run_remote_command(&command)).await
+    }
+
+    /// Sends a directory recursively, see `Sbs::send_directory`.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// sbs.send_directory("/path/to/local_dir", "/path/to/remote_dir").await.unwrap();
+    /// ```
+    pub async fn send_directory(&self, local_path: impl Into<PathBuf>, remote_path: impl Into<PathBuf>) -> Result<TransferSummary, SbsError> {
+        let (local_path, remote_path) = (local_path.into(), remote_path.into());
+
+        self.run(move |sbs| sbs.send_directory(&local_path, &remote_path)).await
+    }
+
+    /// Receives a directory recursively, see `Sbs::receive_directory`.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sbs = AsyncSbs::new(sbs); // Your async SBS instance.
+    ///
+    /// sbs.receive_directory("/path/to/local_dir", "/path/to/remote_dir").await.unwrap();
+    /// ```
+    pub async fn receive_directory(&self, local_path: impl Into<PathBuf>, remote_path: impl Into<PathBuf>) -> Result<TransferSummary, SbsError> {
+        let (local_path, remote_path) = (local_path.into(), remote_path.into());
+
+        self.run(move |sbs| sbs.receive_directory(&local_path, &remote_path)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh2::Session;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn operations_run_on_the_wrapped_instance() {
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.parallel_transfers = 4;
+
+        let sbs = AsyncSbs::new(sbs);
+        assert_eq!(sbs.run(|sbs| Ok(sbs.parallel_transfers)).await.unwrap(), 4);
+
+        // Without a connection, the error comes back through the blocking task.
+        assert!(sbs.run_remote_command("true").await.is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::Error;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::util::guard::format_size;
+use crate::util::{shell, temp};
+use crate::util::ssh::Sbs;
+
+/// How many command round-trips are averaged.
+const ROUND_TRIPS: u32 = 5;
+/// How many small files are uploaded, and how large each one is.
+const SMALL_FILES: u32 = 20;
+const SMALL_FILE_SIZE: usize = 1024;
+/// The size of the file used to measure throughput.
+const LARGE_FILE_SIZE: usize = 8 * 1024 * 1024;
+
+/// The measurements taken by a benchmark run.
+#[derive(Debug, Clone)]
+pub struct BenchmarkResults {
+    /// The average time it takes to run a trivial command.
+    pub round_trip: Duration,
+    /// How many small files can be uploaded per second.
+    pub small_files_per_second: f64,
+    /// How many bytes per second a single large upload achieves.
+    pub throughput: f64,
+}
+
+impl BenchmarkResults {
+    /// Turns the measurements into suggestions for the transfer tuning options.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for recommendation in results.recommendations() {
+    ///     println!("{}", recommendation);
+    /// }
+    /// ```
+    pub fn recommendations(&self) -> Vec<String> {
+        let latency = self.round_trip.as_secs_f64();
+
+        // Keep enough data in flight to cover the bandwidth-delay product.
+        let buffer_size = ((self.throughput * latency) as u64)
+            .clamp(32 * 1024, 4 * 1024 * 1024)
+            .next_power_of_two();
+
+        // Hide the per-file round-trip behind more workers the higher the latency is.
+        let parallelism = ((latency * 1000.0) / 5.0).ceil().clamp(1.0, 16.0) as u32;
+
+        let transfer_method = if self.small_files_per_second < 50.0 {
+            "tar (per-file uploads are latency-bound, archive the project into a single transfer)"
+        } else {
+            "scp (per-file uploads are fast enough on this link)"
+        };
+
+        vec![
+            format!("Buffer size: {}", format_size(buffer_size)),
+            format!("Parallel transfers: {}", parallelism),
+            format!("Transfer method: {}", transfer_method),
+        ]
+    }
+}
+
+/// Measures the latency and throughput of the connection to the SSH server.
+///
+/// The test files are written to a uniquely named scratch directory in `/tmp`, which is removed afterwards.
+///
+/// # Arguments
+///
+/// * `sbs` - A connected SBS instance.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sbs = Sbs::new(session); // Your connected SBS instance.
+///
+/// let results = run(&sbs).unwrap();
+/// ```
+pub fn run(sbs: &Sbs) -> Result<BenchmarkResults, Error> {
+    // Command round-trip latency.
+    let start = Instant::now();
+    for _ in 0..ROUND_TRIPS {
+        sbs.run_remote_command("true")?;
+    }
+    let round_trip = start.elapsed() / ROUND_TRIPS;
+
+    let scratch_directory = temp::remote_temp_path("benchmark");
+    sbs.run_remote_command(&format!("mkdir -p {}", shell::quote(&scratch_directory.to_string_lossy())))?;
+
+    let results = measure_transfers(sbs, &scratch_directory);
+
+    // Always clean up, even if a transfer failed.
+    sbs.run_remote_command(&format!("rm -rf {}", shell::quote(&scratch_directory.to_string_lossy())))?;
+
+    let (small_files_per_second, throughput) = results?;
+
+    Ok(BenchmarkResults {
+        round_trip,
+        small_files_per_second,
+        throughput,
+    })
+}
+
+/// Measures the small-file upload rate and the large-file throughput.
+fn measure_transfers(sbs: &Sbs, scratch_directory: &Path) -> Result<(f64, f64), Error> {
+    let small_file = vec![0u8; SMALL_FILE_SIZE];
+    let start = Instant::now();
+    for i in 0..SMALL_FILES {
+        sbs.send_bytes(&small_file, &scratch_directory.join(format!("small-{}", i)), 0o644)?;
+    }
+    let small_files_per_second = SMALL_FILES as f64 / start.elapsed().as_secs_f64();
+
+    let large_file = vec![0u8; LARGE_FILE_SIZE];
+    let start = Instant::now();
+    sbs.send_bytes(&large_file, &scratch_directory.join("large"), 0o644)?;
+    let throughput = LARGE_FILE_SIZE as f64 / start.elapsed().as_secs_f64();
+
+    Ok((small_files_per_second, throughput))
+}
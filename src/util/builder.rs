@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::info;
+use ssh2::Session;
+
+use crate::util::connection::Credentials;
+use crate::util::error::SbsError;
+use crate::util::identity;
+use crate::util::settings::{AddressFamily, AuthMethod, HostKeyChecking};
+use crate::util::ssh::Sbs;
+use crate::util::tunnel::JumpHost;
+
+/// Configures an SBS instance step by step and connects it, instead of setting its fields and calling `connect_with`.
+///
+/// Everything that isn't set keeps the default of `Sbs::new`, authentication tries the agent, the keys and the password in turn.
+pub struct SbsBuilder {
+    host: String,
+    port: u16,
+    username: String,
+    password: Option<String>,
+    private_key: Option<PathBuf>,
+    passphrase: Option<String>,
+    auth_order: Vec<AuthMethod>,
+    sbs: Sbs,
+}
+
+impl SbsBuilder {
+    /// Starts configuring a connection to a host, on port 22.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `username` - The username.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = SbsBuilder::new("build-server", "builder").unwrap()
+    ///     .port(2222)
+    ///     .private_key("/home/user/.ssh/id_ed25519")
+    ///     .connect_timeout(Duration::from_secs(10))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn new(host: &str, username: &str) -> Result<Self, SbsError> {
+        Ok(Self {
+            host: host.to_string(),
+            port: 22,
+            username: username.to_string(),
+            password: None,
+            private_key: None,
+            passphrase: None,
+            auth_order: vec![AuthMethod::Agent, AuthMethod::Key, AuthMethod::Password],
+            sbs: Sbs::new(Session::new()?),
+        })
+    }
+
+    /// Sets the port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets the password, for the `password` authentication method.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Sets the private key tried first by the `key` authentication method, before the ones from `~/.ssh/config` and the default keys.
+    pub fn private_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.private_key = Some(path.into());
+        self
+    }
+
+    /// Sets the passphrase of the private keys.
+    pub fn passphrase(mut self, passphrase: &str) -> Self {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// Sets the authentication methods to try, in order.
+    pub fn auth_order(mut self, auth_order: &[AuthMethod]) -> Self {
+        self.auth_order = auth_order.to_vec();
+        self
+    }
+
+    /// Sets how long connecting (including the handshake and authentication) may take.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.sbs.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how often a failed connection is retried, and how long to wait before the first retry.
+    pub fn retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.sbs.max_retries = max_retries;
+        self.sbs.retry_backoff = backoff;
+        self
+    }
+
+    /// Sets how often a keepalive is sent while commands are silent.
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.sbs.keepalive = Some(interval);
+        self
+    }
+
+    /// Sets how long a command may run unless it sets its own `timeout_secs`.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.sbs.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Checks the server's key against a known-hosts file.
+    pub fn known_hosts(mut self, path: impl Into<PathBuf>, checking: HostKeyChecking) -> Self {
+        self.sbs.known_hosts_path = Some(path.into());
+        self.sbs.host_key_checking = checking;
+        self
+    }
+
+    /// Connects through a jump host.
+    pub fn jump_host(mut self, jump_host: JumpHost) -> Self {
+        self.sbs.jump_host = Some(jump_host);
+        self
+    }
+
+    /// Restricts which IP versions the host names may resolve to.
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.sbs.address_family = family;
+        self
+    }
+
+    /// Sets whether the SSH session is compressed.
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.sbs.compression = compression;
+        self
+    }
+
+    /// Configures anything else on the SBS instance, e.g. its transfer options.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let builder = SbsBuilder::new("build-server", "builder").unwrap().configure(|sbs| sbs.parallel_transfers = 4);
+    /// ```
+    pub fn configure(mut self, configure: impl FnOnce(&mut Sbs)) -> Self {
+        configure(&mut self.sbs);
+        self
+    }
+
+    /// Connects and authenticates, returning the connected SBS instance.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = SbsBuilder::new("build-server", "builder").unwrap().password("password").build().unwrap();
+    /// ```
+    pub fn build(mut self) -> Result<Sbs, SbsError> {
+        let private_keys = identity::candidates(&self.host, self.private_key.as_deref());
+        let credentials = Credentials {
+            password: self.password.as_deref(),
+            private_keys: &private_keys,
+            passphrase: self.passphrase.as_deref(),
+        };
+
+        let method = self.sbs.connect_with(&self.host, &self.port, &self.username, credentials, &self.auth_order)?;
+        info!("Authenticated using {}.", method);
+
+        Ok(self.sbs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn build_fails_with_a_connect_error_when_nothing_listens() {
+        // Grab a free port, then let it go.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let result = SbsBuilder::new("127.0.0.1", "builder").unwrap()
+            .port(port)
+            .retries(0, Duration::ZERO)
+            .connect_timeout(Duration::from_secs(5))
+            .build();
+
+        assert!(matches!(result, Err(SbsError::Connect(_))));
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use config::{Config, ConfigError};
+
+use crate::util::settings::{self, Command};
+
+/// Loads the build commands of a `commands_file`.
+///
+/// A file with the extension of a config format, see [`settings::format_from_name`], lists them like the config does,
+/// as `[[commands]]` entries.
+/// Any other file, like a shell script, holds one command per line, see [`parse_lines`].
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+///
+/// # Examples
+///
+/// ```ignore
+/// let commands = load(Path::new("/path/to/project/build.sbs")).unwrap();
+/// ```
+pub fn load(path: &Path) -> Result<Vec<Command>, ConfigError> {
+    let structured = path.extension()
+        .and_then(|extension| settings::format_from_name(&extension.to_string_lossy()));
+    if let Some(format) = structured {
+        return Config::builder()
+            .add_source(config::File::from(path).format(format))
+            .build()?
+            .get("commands");
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| ConfigError::Message(format!("Failed to read the commands file '{}': {}", path.display(), err)))?;
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+    Ok(parse_lines(&contents, &file_name))
+}
+
+/// Parses build commands written one per line.
+///
+/// Lines ending with a backslash continue on the next one. Comment lines (`#`) right above a command describe it,
+/// and a command without one is described by where it's from. Blank lines and a shebang are skipped.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the file.
+/// * `file_name` - The name of the file, for the descriptions.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::commands_file::parse_lines;
+/// let commands = parse_lines("# Build the project.\ncargo build --release\n", "build.sbs");
+///
+/// assert_eq!(commands[0].description, "Build the project.");
+/// ```
+pub fn parse_lines(contents: &str, file_name: &str) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut description = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+
+        if let Some((start, mut command)) = pending.take() {
+            command.push('\n');
+            command.push_str(line);
+
+            if line.ends_with('\\') {
+                pending = Some((start, command));
+            } else {
+                commands.push(command_from(command, &mut description, start, file_name));
+            }
+
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            description.clear();
+        } else if index == 0 && trimmed.starts_with("#!") {
+            continue;
+        } else if let Some(comment) = trimmed.strip_prefix('#') {
+            description.push(comment.trim().to_string());
+        } else if line.ends_with('\\') {
+            pending = Some((index + 1, trimmed.to_string()));
+        } else {
+            commands.push(command_from(trimmed.to_string(), &mut description, index + 1, file_name));
+        }
+    }
+
+    // A continuation at the very end has nothing left to continue with.
+    if let Some((start, command)) = pending {
+        commands.push(command_from(command, &mut description, start, file_name));
+    }
+
+    commands
+}
+
+/// Makes a build command, taking the comment lines above it as its description.
+fn command_from(command: String, description: &mut Vec<String>, line: usize, file_name: &str) -> Command {
+    let description = match description.drain(..).filter(|line| !line.is_empty()).collect::<Vec<_>>() {
+        lines if lines.is_empty() => format!("Line {} of {}.", line, file_name),
+        lines => lines.join(" "),
+    };
+
+    Command::new(&command, &description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_become_commands_described_by_their_comments() {
+        let contents = "#!/bin/sh\n\n# Fetch the dependencies.\ncargo fetch\n\ncargo build \\\n    --release\n# Run the tests,\n# all of them.\ncargo test\n";
+
+        let commands = parse_lines(contents, "build.sbs")
+            .into_iter()
+            .map(|command| (command.command, command.description))
+            .collect::<Vec<_>>();
+
+        assert_eq!(commands, vec![
+            ("cargo fetch".to_string(), "Fetch the dependencies.".to_string()),
+            ("cargo build \\\n    --release".to_string(), "Line 6 of build.sbs.".to_string()),
+            ("cargo test".to_string(), "Run the tests, all of them.".to_string()),
+        ]);
+    }
+}
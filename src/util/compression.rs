@@ -0,0 +1,103 @@
+use std::io::{Error, Write};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+/// Per-file compression for uploads, for servers that don't negotiate SSH compression.
+#[derive(Debug, Clone, Default)]
+pub struct FileCompression {
+    /// Files smaller than this many bytes are sent as-is, since compressing them isn't worth the overhead.
+    pub min_size: u64,
+    /// The extensions of files that are already compressed.
+    pub incompressible_extensions: Vec<String>,
+}
+
+impl FileCompression {
+    /// Checks whether a file should be compressed before it's sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `size` - The size of the file.
+    pub fn applies_to(&self, path: &Path, size: u64) -> bool {
+        size >= self.min_size && is_compressible(path, &self.incompressible_extensions)
+    }
+}
+
+/// Checks whether a file is worth compressing, based on its extension.
+///
+/// Extensions are compared case-insensitively and may be given with or without a leading dot.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+/// * `incompressible_extensions` - The extensions of files that are already compressed.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::Path;
+/// # use ssh_build_server::util::compression::is_compressible;
+/// let extensions = vec!["png".to_string(), ".gz".to_string()];
+///
+/// assert!(is_compressible(Path::new("src/main.rs"), &extensions));
+/// assert!(!is_compressible(Path::new("assets/logo.PNG"), &extensions));
+/// ```
+pub fn is_compressible(path: &Path, incompressible_extensions: &[String]) -> bool {
+    let extension = match path.extension() {
+        Some(extension) => extension.to_string_lossy(),
+        None => return true,
+    };
+
+    !incompressible_extensions.iter()
+        .any(|incompressible| incompressible.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+}
+
+/// Compresses data with gzip.
+///
+/// # Arguments
+///
+/// * `data` - The data.
+///
+/// # Examples
+///
+/// ```ignore
+/// let compressed = gzip(b"hello hello hello hello").unwrap();
+/// ```
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn gzip_round_trips() {
+        let data = "fn main() {}\n".repeat(100);
+
+        let compressed = gzip(data.as_bytes()).unwrap();
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed).unwrap();
+
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn small_and_compressed_files_are_skipped() {
+        let compression = FileCompression { min_size: 1024, incompressible_extensions: vec!["png".to_string()] };
+
+        assert!(compression.applies_to(Path::new("src/main.rs"), 4096));
+        assert!(!compression.applies_to(Path::new("src/main.rs"), 100));
+        assert!(!compression.applies_to(Path::new("logo.png"), 4096));
+    }
+}
@@ -0,0 +1,620 @@
+use std::cell::{Cell, RefCell};
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use ssh2::{Channel, DisconnectCode, ErrorCode, ScpFileStat, Session, Sftp};
+
+use crate::util::settings::{AddressFamily, AuthMethod};
+use crate::util::tunnel::Tunnel;
+
+/// How many times an SFTP handle may re-open its channel before giving up.
+const MAX_SFTP_REOPENS: u32 = 3;
+
+/// The libssh2 errors that mean the SFTP channel died, while the session itself may still be fine.
+const SFTP_CHANNEL_FAILURES: [i32; 5] = [
+    -21, // LIBSSH2_ERROR_CHANNEL_FAILURE
+    -26, // LIBSSH2_ERROR_CHANNEL_CLOSED
+    -27, // LIBSSH2_ERROR_CHANNEL_EOF_SENT
+    -30, // LIBSSH2_ERROR_SOCKET_TIMEOUT
+    -31, // LIBSSH2_ERROR_SFTP_PROTOCOL
+];
+
+/// How long disconnecting may take, so a half-open socket can't hang the end of a run.
+const DISCONNECT_TIMEOUT_MILLIS: u32 = 2_000;
+
+/// The libssh2 errors that mean the session was already gone (or unreachable) when disconnecting.
+const DEAD_SESSION_ERRORS: [i32; 5] = [
+    -7,  // LIBSSH2_ERROR_SOCKET_SEND
+    -9,  // LIBSSH2_ERROR_TIMEOUT
+    -13, // LIBSSH2_ERROR_SOCKET_DISCONNECT
+    -30, // LIBSSH2_ERROR_SOCKET_TIMEOUT
+    -43, // LIBSSH2_ERROR_SOCKET_RECV
+];
+
+/// The lifecycle state of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No TCP stream has been established yet.
+    Disconnected,
+    /// The SSH handshake succeeded, but the user isn't authenticated yet.
+    Connected,
+    /// The user is authenticated and channels can be opened.
+    Authenticated,
+    /// The session has been disconnected and can't be used anymore.
+    Closed,
+}
+
+/// The secrets available for authentication.
+#[derive(Clone, Copy)]
+pub struct Credentials<'a> {
+    /// The password, if one is configured.
+    pub password: Option<&'a str>,
+    /// The private keys to try, in order.
+    pub private_keys: &'a [PathBuf],
+    pub passphrase: Option<&'a str>,
+}
+
+/// Joins a host and a port into an address, putting IPv6 addresses in brackets (e.g. `[::1]:22`).
+///
+/// A host that's already in brackets is left as is.
+///
+/// # Arguments
+///
+/// * `host` - The host name or IP address.
+/// * `port` - The port.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::connection::socket_address;
+/// assert_eq!(socket_address("::1", 22), "[::1]:22");
+/// ```
+pub fn socket_address(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// An SSH session together with its state.
+///
+/// Channels and SFTP sessions are only handed out once the connection is authenticated.
+pub struct Connection {
+    session: Session,
+    state: ConnectionState,
+    sftp_available: Mutex<Option<bool>>,
+    /// The SFTP session shared by every operation, opened on first use.
+    sftp_session: Mutex<Option<Arc<Sftp>>>,
+    local_address: Option<SocketAddr>,
+    peer_address: Option<SocketAddr>,
+    /// The tunnel through the jump host the session runs over, if any. Dropped after the session.
+    tunnel: Option<Tunnel>,
+}
+
+impl Connection {
+    /// Creates a new, disconnected connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The SSH session.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let session = Session::new().unwrap(); // Your SSH session.
+    ///
+    /// let connection = Connection::new(session);
+    /// ```
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            state: ConnectionState::Disconnected,
+            sftp_available: Mutex::new(None),
+            sftp_session: Mutex::new(None),
+            local_address: None,
+            peer_address: None,
+            tunnel: None,
+        }
+    }
+
+    /// Gets the underlying SSH session.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Gets the current state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Gets the local socket address the connection was made from, once connected.
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.local_address
+    }
+
+    /// Gets the resolved address of the server, once connected.
+    pub fn peer_address(&self) -> Option<SocketAddr> {
+        self.peer_address
+    }
+
+    /// Opens a TCP stream to the address and performs the SSH handshake.
+    ///
+    /// With a timeout, both opening the stream and every blocking session operation give up after it,
+    /// failing with `ErrorKind::TimedOut`. The session keeps the timeout until it's changed with `Session::set_timeout`.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address, in `host:port` form, see `socket_address`.
+    /// * `family` - The IP versions the host may resolve to.
+    /// * `timeout` - How long to wait, or `None` to wait indefinitely.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// connection.connect("localhost:22", AddressFamily::Any, Some(Duration::from_secs(30))).unwrap();
+    /// ```
+    pub fn connect(&mut self, address: &str, family: AddressFamily, timeout: Option<Duration>) -> Result<(), Error> {
+        let stream = Self::open_stream(address, family, timeout)?;
+        self.local_address = stream.local_addr().ok();
+        self.peer_address = stream.peer_addr().ok();
+
+        self.handshake(stream, timeout)
+    }
+
+    /// Performs the SSH handshake over a tunnel through a jump host, see `connect`.
+    ///
+    /// The addresses of the connection aren't known, since only the jump host connects to the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `tunnel` - The tunnel, which the connection keeps open until it's dropped.
+    /// * `timeout` - How long to wait, or `None` to wait indefinitely.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// connection.connect_through(Tunnel::open(jump, "build-server", 22).unwrap(), None).unwrap();
+    /// ```
+    pub fn connect_through(&mut self, mut tunnel: Tunnel, timeout: Option<Duration>) -> Result<(), Error> {
+        let stream = tunnel.take_stream().ok_or_else(|| Error::new(ErrorKind::NotConnected, "The tunnel is already in use!"))?;
+        self.tunnel = Some(tunnel);
+
+        self.handshake(stream, timeout)
+    }
+
+    /// Performs the SSH handshake over the stream, keeping the timeout on the session.
+    fn handshake(&mut self, stream: TcpStream, timeout: Option<Duration>) -> Result<(), Error> {
+        self.session.set_tcp_stream(stream);
+        if let Some(timeout) = timeout {
+            self.session.set_timeout(u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX));
+        }
+        self.session.handshake()?;
+
+        self.state = ConnectionState::Connected;
+
+        Ok(())
+    }
+
+    /// Replaces the session with a fresh one, so connecting can be tried again after a failure.
+    ///
+    /// Settings made on the old session (like compression) have to be made again.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// if connection.connect("localhost:22", AddressFamily::Any, None).is_err() {
+    ///     connection.reset().unwrap();
+    ///     connection.connect("localhost:22", AddressFamily::Any, None).unwrap();
+    /// }
+    /// ```
+    pub fn reset(&mut self) -> Result<(), Error> {
+        *self = Self::new(Session::new()?);
+
+        Ok(())
+    }
+
+    /// Opens a TCP stream to the first address of the family the host resolves to that accepts it (within the timeout).
+    fn open_stream(address: &str, family: AddressFamily, timeout: Option<Duration>) -> Result<TcpStream, Error> {
+        let mut last_error = None;
+
+        for socket_address in address.to_socket_addrs()?.filter(|socket_address| family.allows(socket_address)) {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&socket_address, timeout),
+                None => TcpStream::connect(socket_address),
+            };
+
+            match stream {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| Error::new(ErrorKind::NotFound, format!("The address '{}' didn't resolve to any {} address!", address, family))))
+    }
+
+    /// Authenticates with a username and password.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username.
+    /// * `password` - The password.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// connection.connect("localhost:22", AddressFamily::Any, None).unwrap();
+    /// connection.authenticate_password("username", "password").unwrap();
+    /// ```
+    pub fn authenticate_password(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.require(ConnectionState::Connected)?;
+
+        self.session.userauth_password(username, password)?;
+        self.state = ConnectionState::Authenticated;
+
+        Ok(())
+    }
+
+    /// Authenticates with the keys loaded into the running SSH agent.
+    ///
+    /// If the agent can't authenticate on its own, each of its identities is offered to the server in turn,
+    /// and the error lists the identities that were tried.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// connection.connect("localhost:22", AddressFamily::Any, None).unwrap();
+    /// connection.authenticate_agent("username").unwrap();
+    /// ```
+    pub fn authenticate_agent(&mut self, username: &str) -> Result<(), Error> {
+        self.require(ConnectionState::Connected)?;
+
+        let mut failures = Vec::new();
+        if self.record_attempt("agent".to_string(), self.session.userauth_agent(username), &mut failures) {
+            return Ok(());
+        }
+
+        let mut agent = self.session.agent()?;
+        agent.connect().map_err(|err| Error::new(ErrorKind::PermissionDenied, format!("Failed to connect to the SSH agent, is SSH_AUTH_SOCK set? ({})", err)))?;
+        agent.list_identities()?;
+
+        let identities = agent.identities()?;
+        if identities.is_empty() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "The SSH agent has no identities loaded, add one with ssh-add!"));
+        }
+
+        for identity in &identities {
+            let result = agent.userauth(username, identity);
+            if self.record_attempt(format!("agent identity '{}'", identity.comment()), result, &mut failures) {
+                info!("Authenticated with the agent identity '{}'.", identity.comment());
+
+                return Ok(());
+            }
+        }
+
+        Err(Error::new(ErrorKind::PermissionDenied, format!("Agent authentication failed ({})!", failures.join(", "))))
+    }
+
+    /// Authenticates by trying the given methods in order, stopping at the first that succeeds.
+    ///
+    /// Only the listed methods are attempted. A method whose credentials aren't configured
+    /// (e.g. `key` without any private keys) is skipped. For `key`, every private key is tried in turn.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username.
+    /// * `credentials` - The available secrets.
+    /// * `auth_order` - The methods to try, in order.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    /// let credentials = Credentials { password: Some("password"), private_keys: &[], passphrase: None };
+    ///
+    /// connection.connect("localhost:22", AddressFamily::Any, None).unwrap();
+    /// let method = connection.authenticate("username", credentials, &[AuthMethod::Agent, AuthMethod::Password]).unwrap();
+    /// ```
+    pub fn authenticate(&mut self, username: &str, credentials: Credentials, auth_order: &[AuthMethod]) -> Result<AuthMethod, Error> {
+        self.require(ConnectionState::Connected)?;
+
+        let mut failures = Vec::new();
+        for &method in auth_order {
+            match method {
+                AuthMethod::Agent => match self.authenticate_agent(username) {
+                    Ok(()) => return Ok(method),
+                    Err(err) => failures.push(format!("{}: {}", method, err)),
+                },
+                AuthMethod::Key => {
+                    if credentials.private_keys.is_empty() {
+                        failures.push(format!("{}: skipped, no private key found", method));
+                    }
+
+                    for private_key in credentials.private_keys {
+                        let result = self.session.userauth_pubkey_file(username, None, private_key, credentials.passphrase);
+                        if self.record_attempt(format!("key '{}'", private_key.display()), result, &mut failures) {
+                            info!("Authenticated with the key '{}'.", private_key.display());
+
+                            return Ok(method);
+                        }
+                    }
+                }
+                AuthMethod::Password => match credentials.password {
+                    Some(password) => {
+                        let result = self.session.userauth_password(username, password);
+                        if self.record_attempt(method.to_string(), result, &mut failures) {
+                            return Ok(method);
+                        }
+                    }
+                    None => failures.push(format!("{}: skipped, no password set", method)),
+                },
+            }
+        }
+
+        Err(Error::new(ErrorKind::PermissionDenied, format!("Authentication failed ({})!", failures.join(", "))))
+    }
+
+    /// Records the outcome of an authentication attempt, returning whether it authenticated the session.
+    fn record_attempt(&mut self, attempt: String, result: Result<(), ssh2::Error>, failures: &mut Vec<String>) -> bool {
+        match result {
+            Ok(()) if self.session.authenticated() => {
+                self.state = ConnectionState::Authenticated;
+
+                true
+            }
+            Ok(()) => {
+                failures.push(format!("{}: rejected", attempt));
+
+                false
+            }
+            Err(err) => {
+                failures.push(format!("{}: {}", attempt, err));
+
+                false
+            }
+        }
+    }
+
+    /// Disconnects the session.
+    ///
+    /// A session that never connected, was already closed or died on the way counts as disconnected,
+    /// and the server gets `DISCONNECT_TIMEOUT_MILLIS` to take notice before the session is given up on.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut connection = Connection::new(session); // Your connection.
+    ///
+    /// connection.disconnect(None, "", None).unwrap();
+    /// ```
+    pub fn disconnect(&mut self, reason: Option<DisconnectCode>, description: &str, lang: Option<&str>) -> Result<(), Error> {
+        if matches!(self.state, ConnectionState::Disconnected | ConnectionState::Closed) {
+            return Ok(());
+        }
+
+        self.session.set_timeout(DISCONNECT_TIMEOUT_MILLIS);
+        self.state = ConnectionState::Closed;
+
+        // Closed while the session can still tell the server.
+        self.sftp_session.lock().unwrap_or_else(|err| err.into_inner()).take();
+
+        match self.session.disconnect(reason, description, lang) {
+            Err(err) if matches!(err.code(), ErrorCode::Session(code) if DEAD_SESSION_ERRORS.contains(&code)) => {
+                debug!("The session was already gone when disconnecting: {}", err);
+
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Opens a new session channel.
+    pub fn channel(&self) -> Result<Channel, Error> {
+        self.require(ConnectionState::Authenticated)?;
+
+        Ok(self.session.channel_session()?)
+    }
+
+    /// Opens a channel that the server forwards to a TCP port, as seen from the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host to connect to.
+    /// * `port` - The port to connect to.
+    pub fn direct_tcpip(&self, host: &str, port: u16) -> Result<Channel, Error> {
+        self.require(ConnectionState::Authenticated)?;
+
+        Ok(self.session.channel_direct_tcpip(host, port, None)?)
+    }
+
+    /// Gets the SFTP session, opening it on first use.
+    ///
+    /// Opening one costs a channel and a few round trips, so a single session is shared instead of one per operation.
+    pub fn sftp(&self) -> Result<Arc<Sftp>, Error> {
+        self.require(ConnectionState::Authenticated)?;
+
+        let mut sftp_session = self.sftp_session.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(sftp_session) = sftp_session.as_ref() {
+            return Ok(Arc::clone(sftp_session));
+        }
+
+        let opened = Arc::new(self.session.sftp()?);
+        *sftp_session = Some(Arc::clone(&opened));
+
+        Ok(opened)
+    }
+
+    /// Replaces the shared SFTP session with a newly opened one, after its channel died.
+    pub fn reopen_sftp(&self) -> Result<Arc<Sftp>, Error> {
+        self.sftp_session.lock().unwrap_or_else(|err| err.into_inner()).take();
+
+        self.sftp()
+    }
+
+    /// Gets an SFTP handle that re-opens the channel if it dies during a long transfer.
+    pub fn sftp_handle(&self) -> Result<SftpHandle<'_>, Error> {
+        Ok(SftpHandle {
+            connection: self,
+            sftp: RefCell::new(self.sftp()?),
+            reopens: Cell::new(0),
+        })
+    }
+
+    /// Gets the SFTP session, or returns `None` if the server doesn't provide the SFTP subsystem.
+    ///
+    /// The outcome of the first attempt is cached, so an unavailable subsystem is only probed once.
+    pub fn sftp_if_available(&self) -> Option<Arc<Sftp>> {
+        let mut sftp_available = self.sftp_available.lock().unwrap_or_else(|err| err.into_inner());
+        if *sftp_available == Some(false) {
+            return None;
+        }
+
+        match self.sftp() {
+            Ok(sftp_session) => {
+                *sftp_available = Some(true);
+
+                Some(sftp_session)
+            }
+            Err(err) => {
+                warn!("SFTP is unavailable ({}), falling back to shell commands...", err);
+
+                *sftp_available = Some(false);
+
+                None
+            }
+        }
+    }
+
+    /// Opens a channel for sending a file via SCP, optionally setting its modification and access time.
+    pub fn scp_send(&self, remote_path: &Path, mode: i32, size: u64, times: Option<(u64, u64)>) -> Result<Channel, Error> {
+        self.require(ConnectionState::Authenticated)?;
+
+        Ok(self.session.scp_send(remote_path, mode, size, times)?)
+    }
+
+    /// Opens a channel for receiving a file via SCP.
+    pub fn scp_recv(&self, remote_path: &Path) -> Result<(Channel, ScpFileStat), Error> {
+        self.require(ConnectionState::Authenticated)?;
+
+        Ok(self.session.scp_recv(remote_path)?)
+    }
+
+    /// Fails unless the connection is in the given state.
+    fn require(&self, state: ConnectionState) -> Result<(), Error> {
+        if self.state != state {
+            return Err(Error::new(ErrorKind::NotConnected, format!("The connection is {:?}, but must be {:?}!", self.state, state)));
+        }
+
+        Ok(())
+    }
+}
+
+/// An SFTP session that recovers from a half-open channel.
+///
+/// Over long runs the SFTP channel can die while the SSH session survives, after which every operation fails
+/// with an obscure error. Operations that fail like that are retried on a freshly opened channel of the same session.
+pub struct SftpHandle<'a> {
+    connection: &'a Connection,
+    sftp: RefCell<Arc<Sftp>>,
+    reopens: Cell<u32>,
+}
+
+impl SftpHandle<'_> {
+    /// Runs an SFTP operation, re-opening the channel and retrying if the channel died.
+    ///
+    /// The channel is re-opened at most `MAX_SFTP_REOPENS` times over the lifetime of the handle.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - The operation.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sftp = connection.sftp_handle().unwrap(); // Your SFTP handle.
+    ///
+    /// let stat = sftp.call(|sftp| sftp.stat(Path::new("/path/to/remote_file"))).unwrap();
+    /// ```
+    pub fn call<T>(&self, mut operation: impl FnMut(&Sftp) -> Result<T, ssh2::Error>) -> Result<T, Error> {
+        loop {
+            let result = operation(&self.sftp.borrow());
+
+            match result {
+                Err(err) if is_channel_failure(&err) && self.reopens.get() < MAX_SFTP_REOPENS => {
+                    warn!("The SFTP channel failed ({}), re-opening it...", err);
+
+                    self.reopens.set(self.reopens.get() + 1);
+                    *self.sftp.borrow_mut() = self.connection.reopen_sftp()?;
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+}
+
+/// Checks whether an error means the SFTP channel died, rather than the server rejecting the operation.
+fn is_channel_failure(err: &ssh2::Error) -> bool {
+    matches!(err.code(), ErrorCode::Session(code) if SFTP_CHANNEL_FAILURES.contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn disconnecting_an_unconnected_session_succeeds() {
+        let mut connection = Connection::new(Session::new().unwrap());
+
+        assert!(connection.disconnect(None, "", None).is_ok());
+        assert_eq!(connection.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn ipv6_addresses_are_bracketed() {
+        assert_eq!(socket_address("::1", 22), "[::1]:22");
+        assert_eq!(socket_address("[::1]", 22), "[::1]:22");
+        assert_eq!(socket_address("build-server", 2222), "build-server:2222");
+    }
+
+    #[test]
+    fn streams_open_to_the_allowed_family() {
+        let ipv4 = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = ipv4.local_addr().unwrap().port();
+        assert!(Connection::open_stream(&socket_address("127.0.0.1", port), AddressFamily::Any, None).is_ok());
+        assert!(Connection::open_stream(&socket_address("127.0.0.1", port), AddressFamily::Inet6, None).is_err());
+
+        // Not every machine has IPv6 loopback.
+        if let Ok(ipv6) = TcpListener::bind("[::1]:0") {
+            let port = ipv6.local_addr().unwrap().port();
+            assert!(Connection::open_stream(&socket_address("::1", port), AddressFamily::Any, Some(Duration::from_secs(5))).is_ok());
+            assert!(Connection::open_stream(&socket_address("::1", port), AddressFamily::Inet, None).is_err());
+        }
+    }
+
+    #[test]
+    fn only_channel_errors_are_recovered() {
+        assert!(is_channel_failure(&ssh2::Error::new(ErrorCode::Session(-26), "closed")));
+        assert!(!is_channel_failure(&ssh2::Error::new(ErrorCode::SFTP(2), "no such file")));
+        assert!(!is_channel_failure(&ssh2::Error::new(ErrorCode::Session(-18), "authentication failed")));
+    }
+}
@@ -0,0 +1,286 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Output, Stdio};
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::util::ignore::IgnoreRules;
+use crate::util::settings::{Command, CommandFilter};
+use crate::util::ssh::{compile_script, CommandOutput, DEFAULT_REMOTE_DIR_MODE};
+use crate::util::{archive, encoding, shell, status, temp, warnings};
+
+/// A local Docker container used as the build server, addressed by name instead of over SSH.
+///
+/// Commands run with `docker exec` and files are transferred with `docker cp`.
+pub struct Docker {
+    /// The name or ID of the container.
+    pub container: String,
+    /// Environment variables exported to every command.
+    pub environment: Vec<(String, String)>,
+    /// Selects which commands are run by name.
+    pub command_filter: CommandFilter,
+    /// Whether commands run in a login shell (`bash -lc`) instead of `sh -c`.
+    pub login_shell: bool,
+    /// The encoding command output is decoded from.
+    pub output_encoding: &'static Encoding,
+    /// The directory commands run in unless they set their own `working_dir`.
+    pub working_directory: Option<String>,
+    /// The local files that uploads skip, relative to the directory being sent.
+    pub ignore: IgnoreRules,
+}
+
+/// The entries of a local directory to upload, by local path (or symlink target) and path relative to the directory.
+#[derive(Debug, Default)]
+struct UploadPlan {
+    directories: Vec<(PathBuf, PathBuf)>,
+    files: Vec<(PathBuf, PathBuf)>,
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Docker {
+    /// Creates a new Docker transport for a container.
+    ///
+    /// # Arguments
+    ///
+    /// * `container` - The name or ID of the container.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ssh_build_server::util::docker::Docker;
+    /// let docker = Docker::new("build-box");
+    /// ```
+    pub fn new(container: &str) -> Self {
+        Self {
+            container: container.to_string(),
+            environment: Vec::new(),
+            command_filter: CommandFilter::default(),
+            login_shell: false,
+            output_encoding: UTF_8,
+            working_directory: None,
+            ignore: IgnoreRules::default(),
+        }
+    }
+
+    /// Makes sure the container exists and is running.
+    pub fn check_running(&self) -> Result<(), Error> {
+        let output = Self::docker(&["inspect", "--format", "{{.State.Running}}", &self.container])?;
+
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "true" => Ok(()),
+            _ => Err(Error::new(ErrorKind::NotConnected, format!("The container '{}' is not running!", self.container))),
+        }
+    }
+
+    /// Runs a shell command in the container and returns its exit code and merged output.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let docker = Docker::new("build-box"); // Your Docker transport.
+    ///
+    /// let (exit_code, output) = docker.run_remote_command("uname -a").unwrap();
+    /// ```
+    pub fn run_remote_command(&self, command: &str) -> Result<(i32, String), Error> {
+        let shell = if self.login_shell { "-lc" } else { "-c" };
+        let program = if self.login_shell { "bash" } else { "sh" };
+        let script = format!("{{ {}\n}} 2>&1", command);
+
+        let output = Process::new("docker")
+            .args(["exec", &self.container, program, shell, &script])
+            .output()?;
+
+        Ok((output.status.code().unwrap_or(-1), encoding::decode(&output.stdout, self.output_encoding)))
+    }
+
+    /// Runs the commands of one phase in the container and returns the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - The commands.
+    /// * `is_after_compilation` - Whether to run the post-compilation commands instead of the build commands.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let docker = Docker::new("build-box"); // Your Docker transport.
+    ///
+    /// let output = docker.execute_commands(&settings.commands, false).unwrap();
+    /// ```
+    pub fn execute_commands(&self, commands: &[Command], is_after_compilation: bool) -> Result<CommandOutput, Error> {
+        let commands = commands.iter()
+            .filter(|command| command.execute_after_compilation == is_after_compilation)
+            .filter(|command| self.command_filter.allows(command))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let (exit_code, stdout) = self.run_remote_command(&compile_script(&self.environment, self.working_directory.as_deref(), &commands))?;
+        let mut output = status::check(&commands, CommandOutput { stdout, stderr: String::new(), exit_code }, temp::run_token())?;
+        output.stdout = warnings::enforce(&commands, output.stdout)?;
+
+        Ok(output)
+    }
+
+    /// Resolves a path in the container, expanding a leading `~` to the home directory of its user.
+    ///
+    /// `docker cp` takes paths literally, so `~` has to be expanded up front.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path in the container.
+    pub fn resolve_path(&self, path: &str) -> Result<String, Error> {
+        let Some(rest) = path.strip_prefix('~') else {
+            return Ok(path.to_string());
+        };
+
+        match self.run_remote_command("printf %s \"$HOME\"")? {
+            (0, home) if !home.is_empty() => Ok(format!("{}{}", home, rest)),
+            (_, output) => Err(Error::other(format!("Failed to resolve the home directory of the container: {}", output.trim()))),
+        }
+    }
+
+    /// Copies the contents of a local directory into a directory in the container, creating it if necessary.
+    ///
+    /// The files matching `ignore` are left out. The rest is handed to `docker cp` as a tar archive, keeping symlinks as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The path in the container.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let docker = Docker::new("build-box"); // Your Docker transport.
+    ///
+    /// docker.send_directory(Path::new("/path/to/local_dir"), "/path/to/remote_dir").unwrap();
+    /// ```
+    pub fn send_directory(&self, local_path: &Path, remote_path: &str) -> Result<(), Error> {
+        if !local_path.is_dir() {
+            return Err(Error::new(ErrorKind::NotFound, format!("The local path '{}' does not exist!", local_path.display())));
+        }
+
+        match self.run_remote_command(&format!("mkdir -p {}", shell::quote(remote_path)))? {
+            (0, _) => {}
+            (_, output) => return Err(Error::other(format!("Failed to create the remote path '{}': {}", remote_path, output.trim()))),
+        }
+
+        let plan = plan_upload(local_path, &self.ignore)?;
+        let archive_path = std::env::temp_dir().join(format!("{}.tar", temp::unique_name("docker-upload")));
+        let result = archive::write(&archive_path, &plan.directories, &plan.files, &plan.links, DEFAULT_REMOTE_DIR_MODE as u32, None)
+            .and_then(|_| {
+                // `docker cp -` extracts the archive on stdin into the directory.
+                let archive = File::open(&archive_path)?;
+
+                Self::docker_with_input(&["cp", "-", &format!("{}:{}", self.container, remote_path)], archive.into())
+            });
+        let _ = std::fs::remove_file(&archive_path);
+
+        result.map(|_| ())
+    }
+
+    /// Copies the contents of a directory in the container into a local directory, creating it if necessary.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The path in the container.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let docker = Docker::new("build-box"); // Your Docker transport.
+    ///
+    /// docker.receive_directory(Path::new("/path/to/local_dir"), "/path/to/remote_dir").unwrap();
+    /// ```
+    pub fn receive_directory(&self, local_path: &Path, remote_path: &str) -> Result<(), Error> {
+        std::fs::create_dir_all(local_path)?;
+
+        let source = format!("{}:{}/.", self.container, remote_path.trim_end_matches('/'));
+        Self::docker(&["cp", &source, &local_path.to_string_lossy()])?;
+
+        Ok(())
+    }
+
+    /// Runs the Docker CLI, failing unless it exits successfully.
+    fn docker(args: &[&str]) -> Result<Output, Error> {
+        Self::docker_with_input(args, Stdio::null())
+    }
+
+    /// Runs the Docker CLI with the given stdin, failing unless it exits successfully.
+    fn docker_with_input(args: &[&str], input: Stdio) -> Result<Output, Error> {
+        let output = Process::new("docker").args(args).stdin(input).output()
+            .map_err(|err| Error::new(err.kind(), format!("Failed to run docker: {}", err)))?;
+
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "`docker {}` failed: {}",
+                args.first().copied().unwrap_or_default(),
+                String::from_utf8_lossy(&output.stderr).trim(),
+            )));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Lists what of a local directory is uploaded, leaving out the ignored entries. Directories come before their contents.
+fn plan_upload(root: &Path, ignore: &IgnoreRules) -> Result<UploadPlan, Error> {
+    let mut plan = UploadPlan::default();
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        let mut entries = root.join(&directory).read_dir()?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let relative_path = directory.join(entry.file_name());
+            let file_type = entry.file_type()?;
+
+            if ignore.is_ignored(&relative_path, file_type.is_dir()) {
+                continue;
+            }
+
+            if file_type.is_symlink() {
+                plan.links.push((std::fs::read_link(entry.path())?, relative_path));
+            } else if file_type.is_dir() {
+                plan.directories.push((entry.path(), relative_path.clone()));
+                directories.push(relative_path);
+            } else {
+                plan.files.push((entry.path(), relative_path));
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_leave_out_ignored_files() {
+        let root = std::env::temp_dir().join(format!("sbs-docker-upload-{}", std::process::id()));
+        for directory in [".git", "src", "target/release"] {
+            std::fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        for file in [".git/HEAD", "src/main.rs", "target/release/app", "Cargo.toml"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let plan = plan_upload(&root, &IgnoreRules::parse([".git/", "/target"]));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let plan = plan.unwrap();
+        let relative_paths = |entries: &[(PathBuf, PathBuf)]| entries.iter().map(|(_, relative_path)| relative_path.clone()).collect::<Vec<_>>();
+        assert_eq!(relative_paths(&plan.directories), vec![PathBuf::from("src")]);
+        assert_eq!(relative_paths(&plan.files), vec![PathBuf::from("Cargo.toml"), PathBuf::from("src/main.rs")]);
+        assert!(plan.links.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::{Error, ErrorKind};
+
+use encoding_rs::Encoding;
+
+/// Looks up an encoding by one of its WHATWG labels, e.g. `utf-8`, `latin1` or `shift_jis`.
+///
+/// # Arguments
+///
+/// * `label` - The label of the encoding.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::encoding::from_label;
+/// let encoding = from_label("shift_jis").unwrap();
+/// ```
+pub fn from_label(label: &str) -> Result<&'static Encoding, Error> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("The output encoding '{}' is not supported!", label)))
+}
+
+/// Decodes command output, replacing malformed sequences instead of failing.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw output.
+/// * `encoding` - The encoding the output is in.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::encoding::{decode, from_label};
+/// assert_eq!(decode(b"caf\xe9", from_label("latin1").unwrap()), "café");
+/// ```
+pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode(bytes).0.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_latin1_and_shift_jis() {
+        assert_eq!(decode(b"caf\xe9", from_label("latin1").unwrap()), "café");
+        assert_eq!(decode(b"\x83e\x83X\x83g", from_label("shift_jis").unwrap()), "テスト");
+    }
+
+    #[test]
+    fn unknown_labels_are_rejected() {
+        assert!(from_label("klingon").is_err());
+    }
+}
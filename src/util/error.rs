@@ -0,0 +1,145 @@
+use std::{fmt, io};
+
+use ssh2::ErrorCode;
+
+/// The libssh2 session errors raised while establishing the connection (banner, key exchange and socket).
+const CONNECT_ERRORS: [i32; 8] = [-1, -2, -3, -5, -7, -8, -13, -43];
+
+/// The libssh2 session errors raised when a blocking operation runs out of time.
+const TIMEOUT_ERRORS: [i32; 2] = [-9, -30];
+
+/// The libssh2 session errors raised when the server rejects the credentials.
+const AUTH_ERRORS: [i32; 3] = [-15, -18, -19];
+
+/// The libssh2 session errors raised by SCP and SFTP.
+const TRANSFER_ERRORS: [i32; 2] = [-28, -31];
+
+/// An error of an SBS operation, categorized so callers can tell failures apart.
+#[derive(Debug)]
+pub enum SbsError {
+    /// Connecting to the server failed, including host key verification.
+    Connect(io::Error),
+    /// An operation didn't finish in time.
+    Timeout(String),
+    /// The server rejected every authentication attempt.
+    Auth(String),
+    /// Sending or receiving files failed.
+    Transfer(io::Error),
+    /// A remote command failed.
+    CommandFailed {
+        /// The command that failed, if it's known which one.
+        command: Option<String>,
+        /// The description of the command that failed.
+        description: Option<String>,
+        exit_code: i32,
+        /// The output of the command. SSH merges stderr into it.
+        stderr: String,
+    },
+    /// The settings are invalid.
+    Config(String),
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl fmt::Display for SbsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SbsError::Connect(err) | SbsError::Transfer(err) | SbsError::Io(err) => write!(f, "{}", err),
+            SbsError::Auth(message) | SbsError::Config(message) | SbsError::Timeout(message) => write!(f, "{}", message),
+            SbsError::CommandFailed { command, description, exit_code, stderr } => {
+                match (command, description) {
+                    (Some(command), Some(description)) => write!(f, "The command `{}` ({}) exited with code {}!", command, description, exit_code)?,
+                    (Some(command), None) => write!(f, "The command `{}` exited with code {}!", command, exit_code)?,
+                    (None, _) => write!(f, "The commands failed with exit code {}!", exit_code)?,
+                }
+
+                if stderr.trim().is_empty() {
+                    return Ok(());
+                }
+
+                write!(f, "\n{}", stderr.trim_end())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SbsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SbsError::Connect(err) | SbsError::Transfer(err) | SbsError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl SbsError {
+    /// Categorizes an error raised while connecting, telling timeouts apart from other failures.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The error.
+    pub fn connect(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => SbsError::Timeout(format!("Timed out connecting to the server: {}", err)),
+            _ => SbsError::Connect(err),
+        }
+    }
+}
+
+impl From<io::Error> for SbsError {
+    fn from(err: io::Error) -> Self {
+        SbsError::Io(err)
+    }
+}
+
+impl From<ssh2::Error> for SbsError {
+    fn from(err: ssh2::Error) -> Self {
+        match err.code() {
+            ErrorCode::Session(code) if AUTH_ERRORS.contains(&code) => SbsError::Auth(err.to_string()),
+            ErrorCode::Session(code) if TIMEOUT_ERRORS.contains(&code) => SbsError::Timeout(err.to_string()),
+            ErrorCode::Session(code) if CONNECT_ERRORS.contains(&code) => SbsError::Connect(err.into()),
+            ErrorCode::Session(code) if TRANSFER_ERRORS.contains(&code) => SbsError::Transfer(err.into()),
+            ErrorCode::SFTP(_) => SbsError::Transfer(err.into()),
+            ErrorCode::Session(_) => SbsError::Io(err.into()),
+        }
+    }
+}
+
+/// Lets the `io::Error` based helpers use the `Sbs` methods with `?`.
+impl From<SbsError> for io::Error {
+    fn from(err: SbsError) -> Self {
+        match err {
+            SbsError::Connect(err) | SbsError::Transfer(err) | SbsError::Io(err) => err,
+            SbsError::Auth(message) => io::Error::new(io::ErrorKind::PermissionDenied, message),
+            SbsError::Config(message) => io::Error::new(io::ErrorKind::InvalidInput, message),
+            SbsError::Timeout(message) => io::Error::new(io::ErrorKind::TimedOut, message),
+            err @ SbsError::CommandFailed { .. } => io::Error::other(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh2_errors_are_categorized() {
+        let auth = SbsError::from(ssh2::Error::new(ErrorCode::Session(-18), "Authentication failed"));
+        let connect = SbsError::from(ssh2::Error::new(ErrorCode::Session(-13), "Socket disconnected"));
+        let transfer = SbsError::from(ssh2::Error::new(ErrorCode::SFTP(2), "No such file"));
+
+        assert!(matches!(auth, SbsError::Auth(_)));
+        assert!(matches!(connect, SbsError::Connect(_)));
+        assert!(matches!(transfer, SbsError::Transfer(err) if err.kind() == io::ErrorKind::NotFound));
+        assert!(matches!(SbsError::from(ssh2::Error::new(ErrorCode::Session(-9), "Timed out")), SbsError::Timeout(_)));
+    }
+
+    #[test]
+    fn connect_timeouts_are_told_apart() {
+        let timeout = SbsError::connect(io::Error::new(io::ErrorKind::TimedOut, "connection timed out"));
+        let refused = SbsError::connect(io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused"));
+
+        assert!(matches!(timeout, SbsError::Timeout(_)));
+        assert!(matches!(refused, SbsError::Connect(_)));
+    }
+}
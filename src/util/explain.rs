@@ -0,0 +1,241 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::util::{connection, identity};
+use crate::util::settings::{AddressFamily, AuthMethod, Command, CommandFilter, HostKeyChecking, Settings, SymlinkPolicy, TransferMode, Transport};
+use crate::util::ssh::DEFAULT_REMOTE_DIR_MODE;
+
+/// Describes in prose what the pipeline will do with the given settings, without connecting anywhere.
+///
+/// # Arguments
+///
+/// * `settings` - The resolved settings.
+/// * `filter` - The command name filter from the command line.
+///
+/// # Examples
+///
+/// ```ignore
+/// let settings = Settings::new("Settings.toml").unwrap();
+///
+/// println!("{}", explain(&settings, &CommandFilter::default()));
+/// ```
+pub fn explain(settings: &Settings, filter: &CommandFilter) -> String {
+    let ssh = &settings.ssh;
+    let compilation = &settings.compilation;
+
+    let mut explanation = String::new();
+    let mut step = 0;
+    let mut next_step = || {
+        step += 1;
+
+        step
+    };
+
+    // Writing to a String can't fail, so the results are ignored.
+    let private_keys = identity::candidates(&ssh.host, ssh.private_key_path.as_deref().map(Path::new));
+    let auth_order = ssh.auth_order.iter()
+        .map(|method| match method {
+            AuthMethod::Key if private_keys.is_empty() => "key (skipped, no private key found)".to_string(),
+            AuthMethod::Key => format!("key {}", private_keys.iter()
+                .map(|key| format!("'{}'", key.display()))
+                .collect::<Vec<_>>()
+                .join(" or ")),
+            AuthMethod::Password if ssh.password.is_none() && ssh.password_env.is_none() && ssh.password_command.is_none() => {
+                "password (skipped, no password set)".to_string()
+            }
+            method => method.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", then ");
+    if ssh.transport == Transport::Docker {
+        let _ = writeln!(explanation, "{}. Use the local Docker container '{}', through docker exec and docker cp.",
+                         next_step(), ssh.container.as_deref().unwrap_or("(not set)"));
+    } else {
+        let _ = writeln!(explanation, "{}. Connect to {} as '{}', authenticating with {}.",
+                         next_step(), connection::socket_address(&ssh.host, ssh.port), ssh.username, auth_order);
+        if ssh.address_family != AddressFamily::Any {
+            let _ = writeln!(explanation, "   Only {} addresses are used.", ssh.address_family);
+        }
+        if ssh.keepalive_secs > 0 {
+            let _ = writeln!(explanation, "   While the commands print nothing, a keepalive is sent every {} seconds.", ssh.keepalive_secs);
+        }
+        if ssh.compression {
+            let _ = writeln!(explanation, "   The session is compressed, if the server allows it.");
+        }
+        if let Some(jump_host) = &ssh.jump_host {
+            let _ = writeln!(explanation, "   The connection goes through the jump host '{}', which is authenticated the same way.", jump_host);
+        }
+
+        let known_hosts = ssh.known_hosts_path.as_deref().unwrap_or("~/.ssh/known_hosts");
+        let _ = match ssh.strict_host_key_checking {
+            HostKeyChecking::Yes => writeln!(explanation, "   The server's key has to be in '{}'.", known_hosts),
+            HostKeyChecking::AcceptNew => writeln!(explanation, "   The server's key has to match '{}', unknown keys are added to it.", known_hosts),
+            HostKeyChecking::No => writeln!(explanation, "   The server's key isn't checked."),
+        };
+    }
+
+    if let Some(precondition_command) = &compilation.precondition_command {
+        let _ = writeln!(explanation, "{}. Run `{}` on the server, and skip the rest of the run if it fails.",
+                         next_step(), precondition_command);
+    }
+
+    if compilation.lock {
+        let _ = writeln!(explanation, "{}. Lock '{}', waiting up to {} seconds for other runs using it.",
+                         next_step(), compilation.remote_project_root, compilation.lock_timeout_secs);
+    }
+
+    if !settings.local_commands.is_empty() {
+        let _ = writeln!(explanation, "{}. Run {} local command(s) in '{}', stopping the run if one fails:",
+                         next_step(), settings.local_commands.len(), compilation.local_project_root);
+        for command in &settings.local_commands {
+            let working_dir = command.working_dir.as_deref().map(|dir| format!(", in '{}'", dir)).unwrap_or_default();
+
+            let _ = writeln!(explanation, "   - `{}` ({}{})", command.command, command.description, working_dir);
+        }
+    }
+
+    if compilation.clean_remote {
+        let _ = writeln!(explanation, "{}. Delete '{}' with everything in it after confirming, unless it's the root or home directory.",
+                         next_step(), compilation.get_remote_upload_directory());
+    }
+
+    let _ = writeln!(explanation, "{}. Upload the contents of '{}' to '{}'{}{}{}.",
+                     next_step(),
+                     compilation.local_project_root,
+                     compilation.get_remote_upload_directory(),
+                     if compilation.incremental_upload { ", skipping unchanged files" } else { "" },
+                     if compilation.deterministic_order { ", in sorted order" } else { "" },
+                     if compilation.preserve_xattrs { ", preserving extended attributes" } else { "" });
+
+    if compilation.transfer_mode == TransferMode::Tar {
+        let _ = writeln!(explanation, "   The files are sent as a single tar archive, which is extracted on the server.");
+    } else if compilation.parallel_transfers > 1 {
+        let _ = writeln!(explanation, "   Up to {} files are sent at once.", compilation.parallel_transfers);
+    }
+
+    if compilation.remote_dir_mode != DEFAULT_REMOTE_DIR_MODE as u32 || compilation.remote_file_mode.is_some() {
+        let _ = writeln!(explanation, "   Directories are created with mode {:o}, files get {}.",
+                         compilation.remote_dir_mode,
+                         compilation.remote_file_mode.map(|mode| format!("mode {:o}", mode)).unwrap_or_else(|| "the mode of the local file".to_string()));
+    }
+
+    let _ = writeln!(explanation, "   {}", match compilation.symlinks {
+        SymlinkPolicy::Skip => "Symlinks are left out.",
+        SymlinkPolicy::Follow => "Symlinks are followed, except ones looping back into the tree.",
+        SymlinkPolicy::Recreate => "Symlinks are re-created, pointing at the same targets.",
+    });
+
+    let mut ignored = compilation.ignore.iter().map(|pattern| format!("'{}'", pattern)).collect::<Vec<_>>();
+    if compilation.use_gitignore {
+        ignored.push("the patterns of the project's .gitignore".to_string());
+    }
+    if !ignored.is_empty() {
+        let _ = writeln!(explanation, "   Files matching {} are left out.", ignored.join(", "));
+    }
+
+    if let Some(content_store_dir) = &compilation.content_store_dir {
+        let _ = writeln!(explanation, "   Only contents missing from the store '{}' are sent, the rest is copied out of it.", content_store_dir);
+    }
+
+    if let Some(command) = &compilation.remote_project_root_command {
+        let _ = writeln!(explanation, "   The remote project root is replaced by the output of `{}` after connecting.", command);
+    }
+
+    if let Some(download) = &compilation.concurrent_download {
+        let _ = writeln!(explanation, "   At the same time, download '{}' to '{}' over a second connection.", download.remote_directory, download.local_directory);
+    }
+
+    let shell = match (ssh.login_shell, compilation.sequential_commands) {
+        (true, false) => "a login shell (bash -lc)",
+        (true, true) => "separate login shells (bash -lc), one per command",
+        (false, false) => "the server's default shell",
+        (false, true) => "separate shells, one per command",
+    };
+    explain_commands(&mut explanation, next_step(), &settings.commands, filter, false, shell);
+
+    if compilation.command_timeout_secs > 0 {
+        let _ = writeln!(explanation, "   A command still running after {}s is killed, unless it sets its own timeout_secs.", compilation.command_timeout_secs);
+    }
+
+    if !compilation.send_env.is_empty() {
+        let _ = writeln!(explanation, "   The local {} variable(s) are forwarded to them.", compilation.send_env.join(", "));
+    }
+
+    if compilation.build_metadata {
+        let _ = writeln!(explanation, "   The commands can read SBS_GIT_COMMIT, SBS_GIT_BRANCH and SBS_BUILD_TIME.");
+    }
+
+    if compilation.measure_resources {
+        let _ = writeln!(explanation, "   Their CPU time and peak memory are measured with /usr/bin/time.");
+    }
+
+    let _ = writeln!(explanation, "{}. Download '{}' to '{}'{}.",
+                     next_step(),
+                     compilation.get_remote_output_directory(),
+                     compilation.get_local_output_directory(),
+                     if compilation.incremental_download { ", skipping unchanged files" } else { "" });
+
+    if !compilation.download_patterns.is_empty() {
+        let _ = writeln!(explanation, "   Only files matching {} are downloaded.", compilation.download_patterns.iter()
+            .map(|pattern| format!("'{}'", pattern))
+            .collect::<Vec<_>>()
+            .join(", "));
+    }
+
+    if compilation.verify_checksums {
+        let _ = writeln!(explanation, "   The downloaded files are compared against SHA-256 checksums computed on the server.");
+    }
+
+    if let Some(verify_command) = &compilation.verify_command {
+        let _ = writeln!(explanation, "{}. Verify the output locally by running '{}', failing the run if it fails.",
+                         next_step(), verify_command);
+    }
+
+    explain_commands(&mut explanation, next_step(), &settings.commands, filter, true, shell);
+
+    if compilation.cleanup_remote_after {
+        let _ = writeln!(explanation, "{}. Delete '{}' on the server after confirming, unless it's the root or home directory. Any failure before skips this.",
+                         next_step(), compilation.get_remote_cleanup_directory());
+    }
+
+    let _ = writeln!(explanation, "{}. Disconnect.", next_step());
+
+    explanation
+}
+
+/// Describes the commands of one phase.
+fn explain_commands(explanation: &mut String, step: usize, commands: &[Command], filter: &CommandFilter, is_after_compilation: bool, shell: &str) {
+    let commands = commands.iter()
+        .filter(|command| command.execute_after_compilation == is_after_compilation)
+        .filter(|command| filter.allows(command))
+        .collect::<Vec<_>>();
+
+    let phase = if is_after_compilation { "post-compilation" } else { "build" };
+    if commands.is_empty() {
+        let _ = writeln!(explanation, "{}. Run no {} commands.", step, phase);
+
+        return;
+    }
+
+    let _ = writeln!(explanation, "{}. Run {} {} command(s) in {}:", step, commands.len(), phase, shell);
+    for command in commands {
+        let name = command.name.as_deref().map(|name| format!(" [{}]", name)).unwrap_or_default();
+
+        let warnings = if command.warnings_as_errors { ", warnings fail the phase" } else { "" };
+        let continues = if command.continue_on_error { ", failures are ignored" } else { "" };
+        let working_dir = command.working_dir.as_deref().map(|dir| format!(", in '{}'", dir)).unwrap_or_default();
+        let env = if command.env.is_empty() {
+            String::new()
+        } else {
+            format!(", with {} set", command.env.keys().cloned().collect::<Vec<_>>().join(", "))
+        };
+
+        let timeout = match command.timeout_secs {
+            Some(0) => ", without a timeout".to_string(),
+            Some(secs) => format!(", killed after {}s", secs),
+            None => String::new(),
+        };
+
+        let _ = writeln!(explanation, "   - `{}`{} ({}{}{}{}{}{})", command.command, name, command.description, working_dir, env, timeout, warnings, continues);
+    }
+}
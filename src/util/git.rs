@@ -0,0 +1,99 @@
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The files that differ between a git reference and the working tree.
+#[derive(Debug, Clone, Default)]
+pub struct ChangedFiles {
+    /// Files that were added or modified, including untracked ones.
+    pub changed: Vec<PathBuf>,
+    /// Files that were deleted.
+    pub deleted: Vec<PathBuf>,
+}
+
+/// Runs a git command in the given directory and returns its trimmed output if it succeeded.
+///
+/// # Arguments
+///
+/// * `directory` - The directory to run git in.
+/// * `args` - The git arguments.
+///
+/// # Examples
+///
+/// ```ignore
+/// let commit = run(Path::new("/path/to/project"), &["rev-parse", "HEAD"]);
+/// ```
+pub fn run(directory: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Lists the files below a directory that changed since a git reference.
+///
+/// The working tree is compared against the reference, so uncommitted and untracked files are included.
+/// Paths are relative to the given directory.
+///
+/// # Arguments
+///
+/// * `directory` - The directory inside a git repository.
+/// * `reference` - The reference to compare against, e.g. `origin/main` or `HEAD~3`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let changes = changed_since(Path::new("/path/to/project"), "origin/main").unwrap();
+///
+/// println!("{} changed, {} deleted", changes.changed.len(), changes.deleted.len());
+/// ```
+pub fn changed_since(directory: &Path, reference: &str) -> Result<ChangedFiles, Error> {
+    let diff = output(directory, &["diff", "--name-status", "--no-renames", "--relative", reference])?;
+    let untracked = output(directory, &["ls-files", "--others", "--exclude-standard"])?;
+
+    let mut changes = ChangedFiles::default();
+    for line in diff.lines() {
+        let (status, path) = match line.split_once('\t') {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        if status.starts_with('D') {
+            changes.deleted.push(PathBuf::from(path));
+        } else {
+            changes.changed.push(PathBuf::from(path));
+        }
+    }
+
+    changes.changed.extend(untracked.lines().map(PathBuf::from));
+
+    Ok(changes)
+}
+
+/// Runs a git command and returns its output, failing with git's error message.
+fn output(directory: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::other(format!("'git {}' failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::io::{self, BufRead, Error, ErrorKind, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// The number of sample paths shown in a removal preview.
+pub const SAMPLE_PATH_COUNT: usize = 5;
+
+/// A summary of what a destructive operation is about to remove.
+#[derive(Debug, Clone)]
+pub struct RemovalPreview {
+    pub path: PathBuf,
+    /// Whether `path` is removed itself, or only the previewed files in it.
+    pub whole_directory: bool,
+    pub file_count: usize,
+    pub directory_count: usize,
+    pub total_size: u64,
+    pub sample_paths: Vec<PathBuf>,
+}
+
+impl fmt::Display for RemovalPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "About to delete {}:", self.target())?;
+        writeln!(f, "  {} file(s) in {} directory(ies), {} in total.",
+                 self.file_count,
+                 self.directory_count,
+                 format_size(self.total_size),
+        )?;
+
+        for path in &self.sample_paths {
+            writeln!(f, "  - {}", path.display())?;
+        }
+
+        let remaining = (self.file_count + self.directory_count).saturating_sub(self.sample_paths.len());
+        if remaining > 0 {
+            writeln!(f, "  ... and {} more.", remaining)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RemovalPreview {
+    /// Describes what's removed, for the messages.
+    fn target(&self) -> String {
+        if self.whole_directory {
+            format!("'{}'", self.path.display())
+        } else {
+            format!("files in '{}'", self.path.display())
+        }
+    }
+}
+
+/// Asks the user to confirm a destructive operation.
+///
+/// The preview is always printed. If `assume_yes` is set the operation is confirmed right away,
+/// otherwise the user is prompted when stdin is a terminal and the operation is refused when it is not.
+///
+/// # Arguments
+///
+/// * `preview` - The preview of what will be removed.
+/// * `assume_yes` - Whether to skip the prompt (`--yes`).
+///
+/// # Examples
+///
+/// ```ignore
+/// let preview = sbs.preview_removal(Path::new("/remote/project")).unwrap();
+///
+/// confirm_removal(&preview, false).unwrap();
+/// ```
+pub fn confirm_removal(preview: &RemovalPreview, assume_yes: bool) -> Result<(), Error> {
+    print!("{}", preview);
+
+    if assume_yes {
+        return Ok(());
+    }
+
+    // Never block on a prompt nobody can answer.
+    if !io::stdin().is_terminal() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("Refusing to delete {} without confirmation, pass --yes to proceed!", preview.target())));
+    }
+
+    print!("Proceed? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(Error::new(ErrorKind::Interrupted, format!("Deletion of {} was aborted by the user!", preview.target()))),
+    }
+}
+
+/// Refuses to wipe a remote path that's obviously not a project directory: `/`, `~`, `.`, or anything going up with `..`.
+///
+/// This only looks at the path itself, so where it leads has to be checked on the remote as well.
+///
+/// # Arguments
+///
+/// * `path` - The remote path.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::Path;
+/// # use ssh_build_server::util::guard::check_wipeable;
+/// check_wipeable(Path::new("/builds/project")).unwrap();
+/// check_wipeable(Path::new("~/")).unwrap_err();
+/// ```
+pub fn check_wipeable(path: &Path) -> Result<(), Error> {
+    let components = path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect::<Vec<_>>();
+
+    // A leading `~` is the home directory, which doesn't count as a directory below it.
+    let below_home = components.first() == Some(&Component::Normal("~".as_ref()));
+    let names = components.iter().filter(|component| matches!(component, Component::Normal(_))).count();
+
+    if names <= usize::from(below_home) || components.contains(&Component::ParentDir) {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("Refusing to wipe '{}', it isn't a project directory!", path.display())));
+    }
+
+    Ok(())
+}
+
+/// Formats a byte count as a human-readable size.
+///
+/// # Arguments
+///
+/// * `bytes` - The number of bytes.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::guard::format_size;
+/// assert_eq!(format_size(1536), "1.5 KiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_name_what_is_deleted() {
+        let mut preview = RemovalPreview {
+            path: PathBuf::from("/builds/project"),
+            whole_directory: false,
+            file_count: 1,
+            directory_count: 0,
+            total_size: 1536,
+            sample_paths: vec![PathBuf::from("src/old.rs")],
+        };
+
+        assert!(preview.to_string().starts_with("About to delete files in '/builds/project':\n  1 file(s) in 0 directory(ies), 1.5 KiB in total.\n  - src/old.rs\n"));
+        assert!(confirm_removal(&preview, true).is_ok());
+
+        preview.whole_directory = true;
+        assert!(preview.to_string().starts_with("About to delete '/builds/project':"));
+    }
+
+    #[test]
+    fn only_project_directories_are_wipeable() {
+        for path in ["/", "~", "~/", "/./", "", ".", "/builds/..", "~/project/../.."] {
+            assert!(check_wipeable(Path::new(path)).is_err(), "'{}' should be refused", path);
+        }
+
+        for path in ["/builds/project", "~/remote/project", "project"] {
+            assert!(check_wipeable(Path::new(path)).is_ok(), "'{}' should be allowed", path);
+        }
+    }
+}
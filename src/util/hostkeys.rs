@@ -0,0 +1,205 @@
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+use log::warn;
+use ssh2::{CheckResult, HashType, KnownHostFileKind, KnownHosts, Session};
+
+use crate::util::settings::HostKeyChecking;
+
+/// Gets the name a host is stored under: the host itself on port 22, `[host]:port` otherwise.
+///
+/// # Arguments
+///
+/// * `host` - The host.
+/// * `port` - The port.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::hostkeys::entry_name;
+/// assert_eq!(entry_name("build-server", 2222), "[build-server]:2222");
+/// ```
+pub fn entry_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// Checks the key of a server against a known-hosts file, after the handshake and before authenticating.
+///
+/// A key that doesn't match is always rejected. An unknown host is rejected with `HostKeyChecking::Yes`,
+/// and appended to the file with `HostKeyChecking::AcceptNew`. `HostKeyChecking::No` skips the check altogether.
+///
+/// # Arguments
+///
+/// * `session` - The session, after the handshake.
+/// * `path` - The known-hosts file.
+/// * `host` - The host.
+/// * `port` - The port.
+/// * `checking` - How strictly the key is checked.
+///
+/// # Examples
+///
+/// ```ignore
+/// verify(connection.session(), Path::new("/home/user/.ssh/known_hosts"), "build-server", 22, HostKeyChecking::Yes).unwrap();
+/// ```
+pub fn verify(session: &Session, path: &Path, host: &str, port: u16, checking: HostKeyChecking) -> Result<(), Error> {
+    if checking == HostKeyChecking::No {
+        return Ok(());
+    }
+
+    let known_hosts = read(session, path)?;
+    let (key, key_type) = session.host_key()
+        .ok_or_else(|| Error::other("The server didn't present a host key!"))?;
+
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(Error::new(ErrorKind::PermissionDenied, format!(
+            "The host key of '{}' ({}) doesn't match the one in '{}', the server may be impersonated!",
+            entry_name(host, port),
+            fingerprint(session),
+            path.display(),
+        ))),
+        CheckResult::NotFound if checking == HostKeyChecking::AcceptNew => {
+            append(session, path, &entry_name(host, port), key, key_type.into())?;
+            warn!("Permanently added '{}' ({}) to '{}'.", entry_name(host, port), fingerprint(session), path.display());
+
+            Ok(())
+        }
+        CheckResult::NotFound => Err(Error::new(ErrorKind::PermissionDenied, format!(
+            "The host '{}' ({}) is unknown, add it with `sbs hostkey add {}`!",
+            entry_name(host, port),
+            fingerprint(session),
+            host,
+        ))),
+        CheckResult::Failure => Err(Error::other(format!("Failed to check the host key of '{}'!", entry_name(host, port)))),
+    }
+}
+
+/// Connects to a server and adds its key to a known-hosts file, replacing an existing entry.
+///
+/// # Arguments
+///
+/// * `path` - The known-hosts file, created if it doesn't exist.
+/// * `host` - The host.
+/// * `port` - The port.
+///
+/// # Examples
+///
+/// ```ignore
+/// let fingerprint = add(Path::new("/home/user/.config/sbs/known_hosts"), "build-server", 22).unwrap();
+/// ```
+pub fn add(path: &Path, host: &str, port: u16) -> Result<String, Error> {
+    let mut session = Session::new()?;
+    session.set_tcp_stream(TcpStream::connect(format!("{}:{}", host, port))?);
+    session.handshake()?;
+
+    let (key, key_type) = session.host_key()
+        .ok_or_else(|| Error::other("The server didn't present a host key!"))?;
+
+    remove(path, host, port)?;
+
+    let mut known_hosts = read(&session, path)?;
+    known_hosts.add(&entry_name(host, port), key, "added by sbs", key_type.into())?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    known_hosts.write_file(path, KnownHostFileKind::OpenSSH)?;
+
+    Ok(fingerprint(&session))
+}
+
+/// Lists the hosts in a known-hosts file, with their keys.
+///
+/// # Arguments
+///
+/// * `path` - The known-hosts file.
+pub fn list(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let known_hosts = read(&Session::new()?, path)?;
+
+    Ok(known_hosts.hosts()?
+        .iter()
+        .map(|entry| (entry.name().unwrap_or("(hashed)").to_string(), entry.key().to_string()))
+        .collect())
+}
+
+/// Removes a host from a known-hosts file, returning how many entries were removed.
+///
+/// # Arguments
+///
+/// * `path` - The known-hosts file.
+/// * `host` - The host.
+/// * `port` - The port.
+pub fn remove(path: &Path, host: &str, port: u16) -> Result<usize, Error> {
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let known_hosts = read(&Session::new()?, path)?;
+    let name = entry_name(host, port);
+
+    let mut removed = 0;
+    for entry in known_hosts.hosts()? {
+        if entry.name() == Some(name.as_str()) {
+            known_hosts.remove(&entry)?;
+            removed += 1;
+        }
+    }
+
+    if removed > 0 {
+        known_hosts.write_file(path, KnownHostFileKind::OpenSSH)?;
+    }
+
+    Ok(removed)
+}
+
+/// Appends a host to a known-hosts file, leaving the existing lines untouched.
+fn append(session: &Session, path: &Path, name: &str, key: &[u8], key_format: ssh2::KnownHostKeyFormat) -> Result<(), Error> {
+    // A known-hosts collection with only the new host renders its line.
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.add(name, key, "added by sbs", key_format)?;
+    let line = match known_hosts.hosts()?.first() {
+        Some(entry) => known_hosts.write_string(entry, KnownHostFileKind::OpenSSH)?,
+        None => return Err(Error::other("Failed to render the known-hosts entry!")),
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line.trim_end())
+}
+
+/// Reads a known-hosts file, treating a missing file as empty.
+fn read(session: &Session, path: &Path) -> Result<KnownHosts, Error> {
+    let mut known_hosts = session.known_hosts()?;
+    if path.exists() {
+        known_hosts.read_file(path, KnownHostFileKind::OpenSSH)?;
+    }
+
+    Ok(known_hosts)
+}
+
+/// Formats the SHA-256 fingerprint of the server's host key.
+fn fingerprint(session: &Session) -> String {
+    let hash = session.host_key_hash(HashType::Sha256).unwrap_or_default();
+
+    format!("SHA256 {}", hash.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_name_brackets_non_default_ports() {
+        assert_eq!(entry_name("build-server", 22), "build-server");
+        assert_eq!(entry_name("build-server", 2222), "[build-server]:2222");
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+
+/// The keys OpenSSH tries when nothing else is configured, relative to `~/.ssh`.
+const DEFAULT_IDENTITIES: [&str; 2] = ["id_ed25519", "id_rsa"];
+
+/// Resolves the private keys to try for a host, the way OpenSSH does.
+///
+/// The keys are looked up in this order, and only keys that exist are returned:
+/// 1. The explicitly configured key.
+/// 2. The `IdentityFile` entries of the matching `Host` blocks in `~/.ssh/config`.
+/// 3. The default keys, `~/.ssh/id_ed25519` and `~/.ssh/id_rsa`.
+///
+/// # Arguments
+///
+/// * `host` - The host being connected to.
+/// * `explicit` - The explicitly configured key, if any.
+///
+/// # Examples
+///
+/// ```ignore
+/// for key in candidates("build-server", None) {
+///     println!("Will try '{}'", key.display());
+/// }
+/// ```
+pub fn candidates(host: &str, explicit: Option<&Path>) -> Vec<PathBuf> {
+    let home = home_directory();
+
+    let mut keys = Vec::new();
+    keys.extend(explicit.map(Path::to_path_buf));
+
+    if let Some(home) = &home {
+        if let Ok(config) = std::fs::read_to_string(home.join(".ssh").join("config")) {
+            keys.extend(config_identity_files(&config, host).iter().map(|path| expand_home(path, home)));
+        }
+
+        keys.extend(DEFAULT_IDENTITIES.iter().map(|name| home.join(".ssh").join(name)));
+    }
+
+    let mut unique = Vec::new();
+    for key in keys {
+        if key.is_file() && !unique.contains(&key) {
+            unique.push(key);
+        }
+    }
+
+    unique
+}
+
+/// Extracts the `IdentityFile` entries that apply to a host from an SSH client config.
+///
+/// # Arguments
+///
+/// * `config` - The contents of the config.
+/// * `host` - The host being connected to.
+///
+/// # Examples
+///
+/// ```ignore
+/// let config = "Host build-*\n    IdentityFile ~/.ssh/build_key\n";
+///
+/// assert_eq!(config_identity_files(config, "build-arm"), vec!["~/.ssh/build_key"]);
+/// ```
+pub fn config_identity_files(config: &str, host: &str) -> Vec<String> {
+    let mut identity_files = Vec::new();
+    // Entries before the first `Host` line apply to every host.
+    let mut applies = true;
+
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Keywords are separated from their arguments by whitespace or an equals sign.
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((keyword, value)) => (keyword, value.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim()),
+            None => continue,
+        };
+
+        if keyword.eq_ignore_ascii_case("Host") {
+            applies = host_matches(value, host);
+        } else if keyword.eq_ignore_ascii_case("Match") {
+            // Match blocks need far more context to evaluate, so they're never applied.
+            applies = false;
+        } else if applies && keyword.eq_ignore_ascii_case("IdentityFile") {
+            identity_files.push(value.trim_matches('"').to_string());
+        }
+    }
+
+    identity_files
+}
+
+/// Checks whether a host matches a list of `Host` patterns, honoring `*`, `?` and `!` negation.
+fn host_matches(patterns: &str, host: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns.split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_matches(negated, host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_matches(pattern, host),
+        }
+    }
+
+    matched
+}
+
+/// Matches a string against a pattern where `*` matches any sequence and `?` any single character.
+fn wildcard_matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p].eq_ignore_ascii_case(&value[v])) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Expands a leading `~` to the home directory.
+fn expand_home(path: &str, home: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(relative) => home.join(relative),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Expands a leading `~/` in a local path to the home directory of the current user.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::identity::expand_user_path;
+/// let known_hosts = expand_user_path("~/.config/sbs/known_hosts");
+/// ```
+pub fn expand_user_path(path: &str) -> PathBuf {
+    match home_directory() {
+        Some(home) => expand_home(path, &home),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Gets the home directory of the current user.
+fn home_directory() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+IdentityFile ~/.ssh/global_key
+
+Host build-* !build-legacy
+    IdentityFile ~/.ssh/build_key
+
+Host=*.example.com
+    IdentityFile=\"/keys/example key\"
+";
+
+    #[test]
+    fn identity_files_follow_host_patterns() {
+        assert_eq!(config_identity_files(CONFIG, "build-arm"), vec!["~/.ssh/global_key", "~/.ssh/build_key"]);
+        assert_eq!(config_identity_files(CONFIG, "build-legacy"), vec!["~/.ssh/global_key"]);
+        assert_eq!(config_identity_files(CONFIG, "ci.example.com"), vec!["~/.ssh/global_key", "/keys/example key"]);
+    }
+
+    #[test]
+    fn wildcards_match() {
+        assert!(wildcard_matches("*", "anything"));
+        assert!(wildcard_matches("build-?", "build-1"));
+        assert!(wildcard_matches("*.example.com", "a.b.example.com"));
+        assert!(!wildcard_matches("build-?", "build-12"));
+        assert!(!wildcard_matches("*.example.com", "example.com"));
+    }
+}
@@ -0,0 +1,240 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// A single ignore pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    pattern: String,
+    /// Whether the pattern re-includes what an earlier one ignored (`!pattern`).
+    negated: bool,
+    /// Whether the pattern only matches directories (`pattern/`).
+    directory_only: bool,
+    /// Whether the pattern matches the whole relative path instead of any file name (it contains a `/`).
+    anchored: bool,
+}
+
+/// A list of `.gitignore` style patterns, matched against paths relative to the project root.
+///
+/// Supported are `*` and `?` (which don't match `/`), `**` (which does), a leading `/` or an inner `/`
+/// to anchor a pattern to the root, a trailing `/` to only match directories, and `!` to negate.
+/// Later patterns override earlier ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Parses ignore patterns, skipping blank lines and `#` comments.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - The patterns.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ssh_build_server::util::ignore::IgnoreRules;
+    /// let rules = IgnoreRules::parse(["target/", "*.log"]);
+    /// ```
+    pub fn parse<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let rules = patterns.into_iter()
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty() && !pattern.starts_with('#'))
+            .filter_map(|pattern| {
+                let (negated, pattern) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern),
+                };
+                let (directory_only, pattern) = match pattern.strip_suffix('/') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern),
+                };
+                let anchored = pattern.contains('/');
+                let pattern = pattern.trim_start_matches('/');
+
+                (!pattern.is_empty()).then(|| Rule { pattern: pattern.to_string(), negated, directory_only, anchored })
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    /// Loads the configured patterns, followed by the `.gitignore` of the project root if asked to.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The local project root.
+    /// * `patterns` - The configured patterns.
+    /// * `use_gitignore` - Whether to add the patterns of `<root>/.gitignore`, if it exists.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let rules = IgnoreRules::load(Path::new("/path/to/project"), &settings.compilation.ignore, true).unwrap();
+    /// ```
+    pub fn load(root: &Path, patterns: &[String], use_gitignore: bool) -> Result<Self, Error> {
+        let mut rules = Self::parse(patterns.iter().map(String::as_str));
+
+        if use_gitignore {
+            match std::fs::read_to_string(root.join(".gitignore")) {
+                Ok(gitignore) => rules.rules.extend(Self::parse(gitignore.lines()).rules),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Checks whether there are no patterns at all.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks whether a path is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative_path` - The path relative to the project root.
+    /// * `is_dir` - Whether the path is a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use ssh_build_server::util::ignore::IgnoreRules;
+    /// let rules = IgnoreRules::parse(["target/"]);
+    ///
+    /// assert!(rules.is_ignored(Path::new("target"), true));
+    /// ```
+    pub fn is_ignored(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let path = relative_path.to_string_lossy().replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.directory_only && !is_dir {
+                continue;
+            }
+
+            let matches = if rule.anchored {
+                glob_matches(&rule.pattern, &path)
+            } else {
+                glob_matches(&rule.pattern, name)
+            };
+            if matches {
+                ignored = !rule.negated;
+            }
+        }
+
+        ignored
+    }
+
+    /// Checks whether a path matches the patterns, for patterns selecting paths rather than ignoring them.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative_path` - The path relative to the root the patterns apply to.
+    /// * `is_dir` - Whether the path is a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use ssh_build_server::util::ignore::IgnoreRules;
+    /// let patterns = IgnoreRules::parse(["/release/myapp"]);
+    ///
+    /// assert!(patterns.matches(Path::new("release/myapp"), false));
+    /// ```
+    pub fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.is_ignored(relative_path, is_dir)
+    }
+
+    /// Checks whether a file matches the patterns itself, or through one of the directories containing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative_path` - The path of the file relative to the root the patterns apply to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use ssh_build_server::util::ignore::IgnoreRules;
+    /// let patterns = IgnoreRules::parse(["doc/"]);
+    ///
+    /// assert!(patterns.selects_file(Path::new("doc/index.html")));
+    /// ```
+    pub fn selects_file(&self, relative_path: &Path) -> bool {
+        self.matches(relative_path, false) || relative_path.ancestors()
+            .skip(1)
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .any(|directory| self.matches(directory, true))
+    }
+}
+
+/// Matches a path against a glob where `*` and `?` stay within a path segment and `**` spans segments.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let path = path.as_bytes();
+
+    // Backtracking over the last `*` (which can't cross `/`) and the last `**` (which can).
+    let (mut p, mut v) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    let mut double_star: Option<(usize, usize)> = None;
+
+    while v < path.len() {
+        if pattern[p..].starts_with(b"**") {
+            // `**/` also matches no directories at all.
+            let after = if pattern[p + 2..].starts_with(b"/") { p + 3 } else { p + 2 };
+            double_star = Some((after, v));
+            star = None;
+            p = after;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p + 1, v));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == path[v] || (pattern[p] == b'?' && path[v] != b'/')) {
+            p += 1;
+            v += 1;
+        } else if let Some((after, matched)) = star.filter(|&(_, matched)| path[matched] != b'/') {
+            star = Some((after, matched + 1));
+            p = after;
+            v = matched + 1;
+        } else if let Some((after, matched)) = double_star {
+            double_star = Some((after, matched + 1));
+            star = None;
+            p = after;
+            v = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_like_gitignore() {
+        let rules = IgnoreRules::parse(["# Build output", "target/", "*.log", "!keep.log", "/docs/*.pdf", "**/node_modules"]);
+
+        assert!(rules.is_ignored(Path::new("target"), true));
+        assert!(!rules.is_ignored(Path::new("target"), false));
+        assert!(rules.is_ignored(Path::new("crates/cli/target"), true));
+        assert!(rules.is_ignored(Path::new("logs/build.log"), false));
+        assert!(!rules.is_ignored(Path::new("logs/keep.log"), false));
+        assert!(rules.is_ignored(Path::new("docs/manual.pdf"), false));
+        assert!(!rules.is_ignored(Path::new("docs/old/manual.pdf"), false));
+        assert!(!rules.is_ignored(Path::new("src/docs/manual.pdf"), false));
+        assert!(rules.is_ignored(Path::new("node_modules"), true));
+        assert!(rules.is_ignored(Path::new("web/app/node_modules"), true));
+        assert!(!rules.is_ignored(Path::new("src/main.rs"), false));
+
+        assert!(rules.selects_file(Path::new("target/release/myapp")));
+        assert!(rules.selects_file(Path::new("logs/build.log")));
+        assert!(!rules.selects_file(Path::new("src/main.rs")));
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use log::{info, warn};
+
+use crate::util::shell;
+
+/// Runs a shell command on the local machine, inheriting stdin, stdout and stderr.
+///
+/// The command is run by `sh -c` on Unix and `cmd /C` on Windows.
+///
+/// # Arguments
+///
+/// * `command` - The command line.
+/// * `args` - Positional arguments passed to the command (`$1`, `$2`, ... on Unix).
+/// * `envs` - Additional environment variables.
+///
+/// # Examples
+///
+/// ```ignore
+/// let status = run_command("echo \"$1\"", &["hello"], &[]).unwrap();
+///
+/// assert!(status.success());
+/// ```
+pub fn run_command(command: &str, args: &[&str], envs: &[(&str, &str)]) -> Result<ExitStatus, Error> {
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command).args(args);
+
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command).arg("sh").args(args);
+
+        process
+    };
+
+    process.envs(envs.iter().copied()).status()
+}
+
+/// Runs a shell command on the local machine and returns its stdout, failing unless it exits successfully.
+///
+/// Stdin and stderr stay attached to the terminal, so the command can prompt for input.
+///
+/// # Arguments
+///
+/// * `command` - The command line.
+///
+/// # Examples
+///
+/// ```ignore
+/// let password = run_output("pass show build-server").unwrap();
+/// ```
+pub fn run_output(command: &str) -> Result<String, Error> {
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command);
+
+        process
+    };
+
+    let output = process.stdin(Stdio::inherit()).stderr(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("The local command '{}' failed with {}!", command, output.status)));
+    }
+
+    String::from_utf8(output.stdout).map_err(|_| Error::new(ErrorKind::InvalidData, format!("The output of '{}' isn't valid UTF-8!", command)))
+}
+
+/// Runs a shell command on the local machine and fails unless it exits successfully.
+///
+/// # Arguments
+///
+/// * `command` - The command line.
+/// * `args` - Positional arguments passed to the command.
+/// * `envs` - Additional environment variables.
+///
+/// # Examples
+///
+/// ```ignore
+/// run_checked("test -d \"$1\"", &["/tmp"], &[]).unwrap();
+/// ```
+pub fn run_checked(command: &str, args: &[&str], envs: &[(&str, &str)]) -> Result<(), Error> {
+    let status = run_command(command, args, envs)?;
+    if !status.success() {
+        return Err(Error::other(format!("The local command '{}' failed with {}!", command, status)));
+    }
+
+    Ok(())
+}
+
+/// Runs a shell command on the local machine with its output captured and logged, failing unless it exits successfully.
+///
+/// Stdin is closed, so a command waiting for input fails instead of hanging the run.
+///
+/// # Arguments
+///
+/// * `command` - The command line.
+/// * `working_dir` - The directory the command runs in.
+/// * `envs` - Additional environment variables.
+///
+/// # Examples
+///
+/// ```ignore
+/// run_logged("cargo vendor", Path::new("/path/to/project"), &[]).unwrap();
+/// ```
+pub fn run_logged(command: &str, working_dir: &Path, envs: &[(&str, &str)]) -> Result<(), Error> {
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command);
+
+        process
+    };
+
+    let output = process.current_dir(working_dir).envs(envs.iter().copied()).stdin(Stdio::null()).output()?;
+    for line in String::from_utf8_lossy(&output.stdout).lines().chain(String::from_utf8_lossy(&output.stderr).lines()) {
+        info!("{}", line);
+    }
+
+    if !output.status.success() {
+        return Err(Error::other(format!("The local command '{}' failed with {}!", command, output.status)));
+    }
+
+    Ok(())
+}
+
+/// Reads local environment variables to forward to the remote commands, like `SendEnv` in OpenSSH.
+///
+/// Variables that aren't set locally, or whose names aren't valid shell identifiers, are skipped with a warning.
+///
+/// # Arguments
+///
+/// * `names` - The names of the variables.
+///
+/// # Examples
+///
+/// ```ignore
+/// let environment = forwarded_environment(&["CI".to_string(), "GITHUB_SHA".to_string()]);
+/// ```
+pub fn forwarded_environment(names: &[String]) -> Vec<(String, String)> {
+    let mut environment = Vec::new();
+
+    for name in names {
+        if !shell::is_variable_name(name) {
+            warn!("'{}' in send_env is not a valid variable name, skipping it.", name);
+
+            continue;
+        }
+
+        match std::env::var(name) {
+            Ok(value) => environment.push((name.clone(), value)),
+            Err(_) => warn!("The variable '{}' in send_env is not set locally, skipping it.", name),
+        }
+    }
+
+    environment
+}
@@ -0,0 +1,135 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+
+use crate::util::metadata::format_timestamp;
+use crate::util::{shell, temp};
+use crate::util::ssh::Sbs;
+
+/// How long to wait between attempts to take a held lock.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A lock on a remote directory, released when dropped.
+///
+/// The lock is a sibling directory named after the locked one (`/builds/.project.sbs-lock` for `/builds/project`),
+/// so only runs targeting the same remote directory wait for each other.
+pub struct RemoteLock<'a> {
+    sbs: &'a Sbs,
+    path: PathBuf,
+}
+
+impl RemoteLock<'_> {
+    /// Gets the remote path of the lock directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RemoteLock<'_> {
+    fn drop(&mut self) {
+        let command = format!("rm -rf {}", shell::quote(&self.path.to_string_lossy()));
+
+        if let Err(err) = self.sbs.run_remote_command(&command) {
+            warn!("Failed to release the remote lock '{}': {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Gets the remote path of the lock for a directory.
+///
+/// # Arguments
+///
+/// * `remote_path` - The remote directory to lock.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::Path;
+/// # use ssh_build_server::util::lock::lock_path;
+/// assert_eq!(lock_path(Path::new("/builds/project")), Path::new("/builds/.project.sbs-lock"));
+/// ```
+pub fn lock_path(remote_path: &Path) -> PathBuf {
+    let name = remote_path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    remote_path.with_file_name(format!(".{}.sbs-lock", name))
+}
+
+/// Takes the lock on a remote directory, waiting for other runs to release it.
+///
+/// Creating a directory is atomic, so exactly one run can hold the lock at a time.
+/// Information about the holder is written into the lock, to help with clearing a stale one.
+///
+/// # Arguments
+///
+/// * `sbs` - A connected SBS instance.
+/// * `remote_path` - The remote directory to lock.
+/// * `timeout` - How long to wait for the lock.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sbs = Sbs::new(session); // Your connected SBS instance.
+///
+/// let lock = acquire(&sbs, Path::new("/builds/project"), Duration::from_secs(60)).unwrap();
+/// // ... build ...
+/// drop(lock);
+/// ```
+pub fn acquire<'a>(sbs: &'a Sbs, remote_path: &Path, timeout: Duration) -> Result<RemoteLock<'a>, Error> {
+    let path = lock_path(remote_path);
+    let quoted_path = shell::quote(&path.to_string_lossy());
+    let owner = format!("run {} since {}", temp::run_token(), format_timestamp(SystemTime::now()));
+
+    let command = format!(
+        "mkdir -p \"$(dirname {0})\" && mkdir {0} 2>/dev/null && echo {1} > {0}/owner",
+        quoted_path,
+        shell::quote(&owner),
+    );
+
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        let (exit_code, _) = sbs.run_remote_command(&command)?;
+        if exit_code == 0 {
+            return Ok(RemoteLock { sbs, path });
+        }
+
+        if started.elapsed() >= timeout {
+            let (_, holder) = sbs.run_remote_command(&format!("cat {}/owner", quoted_path))?;
+
+            return Err(Error::new(ErrorKind::TimedOut, format!(
+                "Timed out waiting for the remote lock '{}' (held by {}), remove it if it is stale!",
+                path.display(),
+                holder.trim(),
+            )));
+        }
+
+        if !announced {
+            info!("Waiting for another run to release '{}'...", path.display());
+
+            announced = true;
+        }
+
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_is_a_sibling_named_after_the_directory() {
+        assert_eq!(lock_path(Path::new("/builds/project")), Path::new("/builds/.project.sbs-lock"));
+        assert_eq!(lock_path(Path::new("/builds/project/")), Path::new("/builds/.project.sbs-lock"));
+    }
+
+    #[test]
+    fn different_directories_get_different_locks() {
+        assert_ne!(lock_path(Path::new("/builds/a")), lock_path(Path::new("/builds/b")));
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::git;
+
+/// Collects build metadata about the local project as environment variables for the remote commands.
+///
+/// The git variables are omitted when the project isn't a git repository or git isn't installed.
+///
+/// * `SBS_GIT_COMMIT` - The commit hash of `HEAD`.
+/// * `SBS_GIT_BRANCH` - The name of the checked out branch.
+/// * `SBS_BUILD_TIME` - The time the build was started, in RFC 3339 format (UTC).
+///
+/// # Arguments
+///
+/// * `project_root` - The local project root.
+///
+/// # Examples
+///
+/// ```ignore
+/// for (name, value) in collect(Path::new("/path/to/project")) {
+///     println!("{}={}", name, value);
+/// }
+/// ```
+pub fn collect(project_root: &Path) -> Vec<(String, String)> {
+    let mut variables = Vec::new();
+
+    if let Some(commit) = git::run(project_root, &["rev-parse", "HEAD"]) {
+        variables.push(("SBS_GIT_COMMIT".to_string(), commit));
+    }
+
+    if let Some(branch) = git::run(project_root, &["rev-parse", "--abbrev-ref", "HEAD"]) {
+        variables.push(("SBS_GIT_BRANCH".to_string(), branch));
+    }
+
+    variables.push(("SBS_BUILD_TIME".to_string(), format_timestamp(SystemTime::now())));
+
+    variables
+}
+
+/// Formats a point in time as an RFC 3339 timestamp in UTC.
+///
+/// # Arguments
+///
+/// * `time` - The time to format.
+///
+/// # Examples
+///
+/// ```
+/// # use std::time::UNIX_EPOCH;
+/// # use ssh_build_server::util::metadata::format_timestamp;
+/// assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+/// ```
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+
+    // Convert the day count to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            seconds_of_day / 3_600,
+            seconds_of_day % 3_600 / 60,
+            seconds_of_day % 60,
+    )
+}
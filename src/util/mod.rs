@@ -1,2 +1,37 @@
+pub mod archive;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod benchmark;
+pub mod builder;
+pub mod commands_file;
+pub mod compression;
+pub mod connection;
+pub mod docker;
+pub mod encoding;
+pub mod error;
+pub mod explain;
+pub mod git;
+pub mod hostkeys;
+pub mod identity;
+pub mod ignore;
+pub mod guard;
+pub mod local;
+pub mod lock;
+pub mod metadata;
+pub mod notify;
+pub mod report;
+pub mod resources;
 pub mod settings;
+pub mod shell;
 pub mod ssh;
+pub mod status;
+pub mod store;
+pub mod temp;
+pub mod timeout;
+pub mod transfer;
+pub mod tunnel;
+pub mod verify;
+pub mod walk;
+pub mod watch;
+pub mod warnings;
+pub mod xattrs;
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use log::warn;
+
+use crate::util::local;
+use crate::util::report::RunReport;
+use crate::util::settings::Notifications;
+
+/// How long to wait for the webhook before giving up.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends the configured notifications about a finished run.
+///
+/// Notifications are best-effort: failures are printed as warnings and never change the outcome of the run.
+///
+/// * The webhook receives the report as a JSON `POST` body.
+/// * The command runs locally with `SBS_RUN_STATUS` (`success`, `skipped` or `failure`) and `SBS_RUN_REPORT` (the JSON report) set.
+///
+/// # Arguments
+///
+/// * `notifications` - The notification settings.
+/// * `report` - The report of the finished run.
+///
+/// # Examples
+///
+/// ```ignore
+/// notify(&settings.notifications, &report);
+/// ```
+pub fn notify(notifications: &Notifications, report: &RunReport) {
+    let json = report.to_json();
+
+    if let Some(webhook_url) = &notifications.webhook_url {
+        let result = ureq::post(webhook_url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(&json);
+
+        if let Err(err) = result {
+            warn!("Failed to send webhook notification: {}", err);
+        }
+    }
+
+    if let Some(command) = &notifications.command {
+        match local::run_command(command, &[], &[("SBS_RUN_STATUS", report.status()), ("SBS_RUN_REPORT", &json)]) {
+            Ok(exit_status) if exit_status.success() => {}
+            Ok(exit_status) => warn!("The notification command failed with {}!", exit_status),
+            Err(err) => warn!("Failed to run the notification command: {}", err),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::{Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::util::metadata::format_timestamp;
+use crate::util::resources::ResourceUsage;
+use crate::util::settings::Settings;
+
+/// A summary of a pipeline run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub success: bool,
+    pub error: Option<String>,
+    /// Why the run was skipped, if its precondition wasn't met.
+    pub skipped: Option<String>,
+    pub host: String,
+    pub local_project_root: String,
+    pub remote_project_root: String,
+    /// The local socket address the connection was made from.
+    pub local_address: Option<String>,
+    /// The resolved address of the server.
+    pub remote_address: Option<String>,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub stages: Vec<StageReport>,
+    /// The resources consumed by the build commands, if they were measured.
+    pub resource_usage: Option<ResourceUsage>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// The outcome of a single pipeline stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub name: String,
+    pub success: bool,
+    pub duration_secs: f64,
+}
+
+impl RunReport {
+    /// Starts a new report for a run with the given settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The settings of the run.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut report = RunReport::new(&settings);
+    /// ```
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            success: false,
+            error: None,
+            skipped: None,
+            host: settings.ssh.host.clone(),
+            local_project_root: settings.compilation.local_project_root.clone(),
+            remote_project_root: settings.compilation.remote_project_root.clone(),
+            local_address: None,
+            remote_address: None,
+            started_at: format_timestamp(SystemTime::now()),
+            duration_secs: 0.0,
+            stages: Vec::new(),
+            resource_usage: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Runs a stage and records how long it took and whether it succeeded.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the stage.
+    /// * `stage` - The stage to run.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let output = report.stage("build", || sbs.execute_commands(&settings.commands, false)).unwrap();
+    /// ```
+    pub fn stage<T, E>(&mut self, name: &str, stage: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let started = Instant::now();
+        let result = stage();
+
+        self.stages.push(StageReport {
+            name: name.to_string(),
+            success: result.is_ok(),
+            duration_secs: started.elapsed().as_secs_f64(),
+        });
+
+        result
+    }
+
+    /// Completes the report with the outcome of the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error that ended the run, if it failed.
+    pub fn finish(&mut self, error: Option<String>) {
+        self.success = error.is_none();
+        self.error = error;
+        self.duration_secs = self.started.elapsed().as_secs_f64();
+    }
+
+    /// Gets the outcome of the run: `success`, `skipped` or `failure`.
+    pub fn status(&self) -> &'static str {
+        match (self.success, &self.skipped) {
+            (true, Some(_)) => "skipped",
+            (true, None) => "success",
+            (false, _) => "failure",
+        }
+    }
+
+    /// Serializes the report as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+
+use crate::util::shell;
+
+/// The resources consumed by a remote build, as reported by GNU `time -v`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    pub peak_memory_kib: u64,
+    pub user_cpu_secs: f64,
+    pub system_cpu_secs: f64,
+    pub wall_secs: f64,
+}
+
+/// Wraps a script so its resource usage is written to a file.
+///
+/// If `/usr/bin/time` isn't available on the server, the script is run as is and no file is written.
+///
+/// # Arguments
+///
+/// * `script` - The script to run.
+/// * `output_path` - The remote file to write the usage to.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::resources::wrap_command;
+/// let wrapped = wrap_command("make", "/tmp/sbs-time");
+/// ```
+pub fn wrap_command(script: &str, output_path: &str) -> String {
+    let script = shell::quote(script);
+
+    format!(
+        "if [ -x /usr/bin/time ]; then /usr/bin/time -v -o {0} \"${{SHELL:-sh}}\" -c {1}; else \"${{SHELL:-sh}}\" -c {1}; fi",
+        shell::quote(output_path),
+        script,
+    )
+}
+
+/// Parses the output of `time -v`, returning `None` if it doesn't contain any usage.
+///
+/// # Arguments
+///
+/// * `output` - The output of `time -v`.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::resources::parse_time_output;
+/// let usage = parse_time_output("\tMaximum resident set size (kbytes): 1920\n").unwrap();
+///
+/// assert_eq!(usage.peak_memory_kib, 1920);
+/// ```
+pub fn parse_time_output(output: &str) -> Option<ResourceUsage> {
+    let mut usage = ResourceUsage::default();
+    let mut found = false;
+
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().rsplit_once(": ") else {
+            continue;
+        };
+
+        let parsed = match key {
+            "User time (seconds)" => value.parse().ok().map(|secs| usage.user_cpu_secs = secs),
+            "System time (seconds)" => value.parse().ok().map(|secs| usage.system_cpu_secs = secs),
+            "Maximum resident set size (kbytes)" => value.parse().ok().map(|kib| usage.peak_memory_kib = kib),
+            _ if key.starts_with("Elapsed (wall clock) time") => parse_clock(value).map(|secs| usage.wall_secs = secs),
+            _ => None,
+        };
+
+        found |= parsed.is_some();
+    }
+
+    found.then_some(usage)
+}
+
+/// Parses a `[h:]mm:ss.ss` duration into seconds.
+fn parse_clock(value: &str) -> Option<f64> {
+    value.split(':').try_fold(0.0, |total, part| Some(total * 60.0 + part.parse::<f64>().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gnu_time_output() {
+        let output = "\tCommand being timed: \"make\"\n\
+                      \tUser time (seconds): 12.34\n\
+                      \tSystem time (seconds): 1.50\n\
+                      \tPercent of CPU this job got: 98%\n\
+                      \tElapsed (wall clock) time (h:mm:ss or m:ss): 1:02:03.50\n\
+                      \tMaximum resident set size (kbytes): 204800\n\
+                      \tExit status: 0\n";
+
+        assert_eq!(parse_time_output(output), Some(ResourceUsage {
+            peak_memory_kib: 204800,
+            user_cpu_secs: 12.34,
+            system_cpu_secs: 1.5,
+            wall_secs: 3723.5,
+        }));
+    }
+
+    #[test]
+    fn missing_output_has_no_usage() {
+        assert_eq!(parse_time_output(""), None);
+        assert_eq!(parse_time_output("cat: /tmp/sbs-time: No such file or directory"), None);
+    }
+}
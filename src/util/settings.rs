@@ -1,64 +1,662 @@
-use config::{Config, ConfigError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, ConfigError, FileFormat, Value, ValueKind};
 use serde::Deserialize;
 
+use crate::util::tunnel::JumpHost;
+use crate::util::{commands_file, guard, local, shell};
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     pub ssh: Ssh,
     pub compilation: Compilation,
     pub commands: Vec<Command>,
+    /// A file with more build commands, appended to the inline ones. Relative paths are resolved against the directory of the config.
+    #[serde(default)]
+    pub commands_file: Option<String>,
+    /// The commands run on the local machine before the project is uploaded, in order.
+    #[serde(default)]
+    pub local_commands: Vec<LocalCommand>,
+    #[serde(default)]
+    pub notifications: Notifications,
+    #[serde(default)]
+    pub provision: Provision,
+    /// The named server profiles, whose `ssh` and `compilation` settings can replace the top-level ones.
+    #[serde(default)]
+    pub servers: Vec<ServerProfile>,
 }
 
 impl Settings {
     pub fn new(path: &str) -> Result<Self, ConfigError> {
+        Self::load(path, FileFormat::Toml, None)
+    }
+
+    /// Loads the settings from a path, or from stdin if the path is `-`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the config file, or `-` for stdin.
+    /// * `stdin_format` - The format of a config read from stdin. Files are detected by their extension, see [`format_from_name`].
+    /// * `server` - The name of the `[[servers]]` entry whose settings apply on top of the top-level ones, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let settings = Settings::load("-", FileFormat::Json, Some("arm")).unwrap();
+    /// ```
+    pub fn load(path: &str, stdin_format: FileFormat, server: Option<&str>) -> Result<Self, ConfigError> {
         let default_config = Config::builder()
-            .add_source(config::File::from_str(DEFAULT_SETTINGS, config::FileFormat::Toml))
+            .add_source(config::File::from_str(DEFAULT_SETTINGS, FileFormat::Toml))
             .build()?;
 
+        let user_config = if path == STDIN_PATH {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)
+                .map_err(|err| ConfigError::Message(format!("Failed to read the config from stdin: {}", err)))?;
+
+            // A config that was piped in on purpose has to be valid, there's no falling back to the defaults.
+            Ok(Config::builder()
+                .add_source(config::File::from_str(&contents, stdin_format))
+                .build()?)
+        } else if Path::new(path).extension().is_some() {
+            // An unsupported extension is a mistake, not a missing config.
+            Config::builder()
+                .add_source(config::File::new(path, file_format(Path::new(path))?))
+                .build()
+        } else {
+            Config::builder()
+                .add_source(config::File::with_name(path))
+                .build()
+        };
+
         // If the user did not supply a valid config path, we use the default config.
-        match Config::builder()
-            .add_source(config::File::with_name(path))
-            .build()
-        {
+        let mut has_inline_commands = false;
+        let config = match user_config {
             Ok(config) => {
-                // Merge the default config with the user-supplied config.
-                let config = Config::builder()
-                    .add_source(default_config)
+                // Load the configs this one extends, the most basic one first.
+                let bases = Self::load_bases(Path::new(path), &config, &mut Vec::new())?;
+                has_inline_commands = bases.iter().chain([&config]).any(|config| config.get_array("commands").is_ok());
+
+                // Merge the default config, the base configs and the user-supplied config.
+                let mut builder = Config::builder().add_source(default_config);
+                for base in bases {
+                    builder = builder.add_source(base);
+                }
+                builder
                     .add_source(config)
-                    .build()?;
+                    .build()?
+            }
+            Err(_) => default_config,
+        };
+
+        let config = match server {
+            Some(server) => Self::select_server(config, server)?,
+            None => config,
+        };
+        let config = Self::interpolate_environment(config)?;
+
+        // Deserialize the config into a Settings instance.
+        let mut settings = config.try_deserialize::<Self>()?;
+
+        if let Some(commands_file) = &settings.commands_file {
+            let config_directory = if path == STDIN_PATH { Path::new("") } else { Path::new(path).parent().unwrap_or(Path::new("")) };
+            let commands = commands_file::load(&config_directory.join(commands_file))?;
+
+            // The example commands of the defaults don't belong to the project.
+            if !has_inline_commands {
+                settings.commands.clear();
+            }
+            settings.commands.extend(commands);
+        }
+
+        Ok(settings)
+    }
+
+    /// Checks the settings for problems that would otherwise only surface mid-run, collecting all of them.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let settings = Settings::new("Settings.toml").unwrap();
+    ///
+    /// if let Err(problems) = settings.validate() {
+    ///     for problem in problems {
+    ///         eprintln!("{}", problem);
+    ///     }
+    /// }
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        let ssh = &self.ssh;
+        let compilation = &self.compilation;
+
+        match ssh.transport {
+            Transport::Ssh => {
+                if ssh.host.trim().is_empty() {
+                    problems.push("ssh.host is empty!".to_string());
+                }
+                if ssh.port == 0 {
+                    problems.push("ssh.port must not be 0!".to_string());
+                }
+                if ssh.username.trim().is_empty() {
+                    problems.push("ssh.username is empty!".to_string());
+                }
+                if ssh.auth_order.is_empty() {
+                    problems.push("ssh.auth_order doesn't list any authentication method!".to_string());
+                }
+
+                let password_sources = [&ssh.password, &ssh.password_env, &ssh.password_command].iter()
+                    .filter(|source| source.is_some())
+                    .count();
+                if password_sources > 1 {
+                    problems.push("Only one of ssh.password, ssh.password_env and ssh.password_command may be set!".to_string());
+                }
+                let uses_password = ssh.auth_order.contains(&AuthMethod::Password);
+                if uses_password && password_sources == 0 {
+                    problems.push("ssh.auth_order lists password, but none of ssh.password, ssh.password_env and ssh.password_command is set!".to_string());
+                }
+                if !uses_password && password_sources > 0 {
+                    problems.push("A password is set, but ssh.auth_order doesn't list password!".to_string());
+                }
+                if let Some(Err(err)) = ssh.jump_host.as_deref().map(JumpHost::parse) {
+                    problems.push(format!("ssh.jump_host is invalid: {}", err));
+                }
+            }
+            Transport::Docker => {
+                if ssh.container.as_deref().is_none_or(|container| container.trim().is_empty()) {
+                    problems.push("The docker transport needs ssh.container to be set!".to_string());
+                }
+            }
+        }
+
+        if !Path::new(&compilation.local_project_root).is_dir() {
+            problems.push(format!("compilation.local_project_root '{}' isn't an existing directory!", compilation.local_project_root));
+        }
+        if compilation.remote_project_root.trim().is_empty() && compilation.remote_project_root_command.is_none() {
+            problems.push("compilation.remote_project_root is empty!".to_string());
+        }
+        if compilation.clean_remote && guard::check_wipeable(Path::new(&compilation.get_remote_upload_directory())).is_err() {
+            problems.push(format!("compilation.clean_remote would wipe '{}', which isn't a project directory!", compilation.get_remote_upload_directory()));
+        }
+        if compilation.cleanup_remote_after && guard::check_wipeable(Path::new(&compilation.get_remote_cleanup_directory())).is_err() {
+            problems.push(format!("compilation.cleanup_remote_after would delete '{}', which isn't a project directory!", compilation.get_remote_cleanup_directory()));
+        }
+        if compilation.output_directory.trim().is_empty() {
+            problems.push("compilation.output_directory is empty!".to_string());
+        }
+        if compilation.remote_dir_mode > 0o7777 || compilation.remote_dir_mode & 0o700 != 0o700 {
+            problems.push(format!("compilation.remote_dir_mode {:#o} has to let the owner read, write and enter the directories!", compilation.remote_dir_mode));
+        }
+        if let Some(mode) = compilation.remote_file_mode.filter(|&mode| mode > 0o7777 || mode & 0o600 != 0o600) {
+            problems.push(format!("compilation.remote_file_mode {:#o} has to let the owner read and write the files!", mode));
+        }
+
+        if self.commands.is_empty() {
+            problems.push("No commands are defined!".to_string());
+        }
+        for (index, command) in self.local_commands.iter().enumerate() {
+            if command.command.trim().is_empty() {
+                problems.push(format!("Local command #{} is empty!", index + 1));
+            }
+        }
+        for (index, command) in self.commands.iter().enumerate() {
+            if command.command.trim().is_empty() {
+                problems.push(format!("Command #{} is empty!", index + 1));
+            }
+            for name in command.env.keys().filter(|name| !shell::is_variable_name(name)) {
+                problems.push(format!("Command #{} sets '{}', which is not a valid variable name!", index + 1, name));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Applies the `ssh` and `compilation` settings of a `[[servers]]` entry on top of the top-level ones.
+    ///
+    /// Only the keys the entry sets are replaced, the rest are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The merged config.
+    /// * `name` - The name of the server.
+    fn select_server(config: Config, name: &str) -> Result<Config, ConfigError> {
+        let servers = match config.get_array("servers") {
+            Ok(servers) => servers,
+            Err(ConfigError::NotFound(_)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut names = Vec::new();
+        let mut selected = None;
+        for server in servers {
+            let mut server = server.into_table()?;
+            let server_name = match server.remove("name") {
+                Some(server_name) => server_name.into_string()?,
+                None => return Err(ConfigError::Message("Every entry of servers needs a name!".to_string())),
+            };
 
-                // Deserialize the config into a Settings instance.
-                config.try_deserialize::<Self>()
+            if server_name == name {
+                selected = Some(server);
+            }
+            names.push(server_name);
+        }
+
+        let mut server = selected.ok_or_else(|| ConfigError::Message(if names.is_empty() {
+            format!("There's no server named '{}', the config doesn't define any!", name)
+        } else {
+            format!("There's no server named '{}', use one of {}!", name, names.join(", "))
+        }))?;
+
+        let mut builder = Config::builder().add_source(config);
+        for section in ["ssh", "compilation"] {
+            if let Some(value) = server.remove(section) {
+                builder = Self::override_leaves(builder, section, value)?;
+            }
+        }
+
+        builder.build()
+    }
+
+    /// Replaces the `${NAME}` references in the string values of the interpolated sections with environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The merged config.
+    fn interpolate_environment(config: Config) -> Result<Config, ConfigError> {
+        let mut builder = Config::builder();
+        let mut sections = Vec::new();
+        for section in INTERPOLATED_SECTIONS {
+            match config.get::<Value>(section) {
+                Ok(value) => sections.push((section, interpolate_value(section, value)?)),
+                Err(ConfigError::NotFound(_)) => {}
+                Err(err) => return Err(err),
             }
-            Err(_) => {
-                // Deserialize the default config into a Settings instance.
-                default_config.try_deserialize::<Self>()
+        }
+
+        builder = builder.add_source(config);
+        for (section, value) in sections {
+            builder = builder.set_override(section, value)?;
+        }
+
+        builder.build()
+    }
+
+    /// Overrides every leaf of a value separately, so tables are merged instead of replaced.
+    fn override_leaves(mut builder: ConfigBuilder<DefaultState>, key: &str, value: Value) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+        match value.kind {
+            ValueKind::Table(table) => {
+                for (child_key, child) in table {
+                    builder = Self::override_leaves(builder, &format!("{}.{}", key, child_key), child)?;
+                }
+
+                Ok(builder)
             }
+            _ => builder.set_override(key, value),
+        }
+    }
+
+    /// Recursively loads the configs referenced by the `extends` key, the most basic one first.
+    ///
+    /// Relative paths are resolved against the directory of the extending config.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the extending config.
+    /// * `config` - The extending config.
+    /// * `visited` - The configs visited so far, used to detect cycles.
+    fn load_bases(path: &Path, config: &Config, visited: &mut Vec<PathBuf>) -> Result<Vec<Config>, ConfigError> {
+        visited.push(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+
+        let base = match config.get_string("extends") {
+            Ok(base) => base,
+            Err(ConfigError::NotFound(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let base_path = path.parent().unwrap_or(Path::new("")).join(base);
+        let identity = std::fs::canonicalize(&base_path).unwrap_or_else(|_| base_path.clone());
+        if visited.contains(&identity) {
+            return Err(ConfigError::Message(format!("The config '{}' extends itself through '{}'!", path.display(), base_path.display())));
         }
+
+        let base_config = Config::builder()
+            .add_source(config::File::from(base_path.as_path()).format(file_format(&base_path)?))
+            .build()?;
+
+        let mut bases = Self::load_bases(&base_path, &base_config, visited)?;
+        bases.push(base_config);
+
+        Ok(bases)
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// The password and passphrase are masked in the `Debug` output, so printing the settings doesn't leak them.
+#[derive(Deserialize, Clone)]
 pub struct Ssh {
+    /// How the build server is reached.
+    #[serde(default)]
+    pub transport: Transport,
+    /// The container to build in, with the `docker` transport.
+    #[serde(default)]
+    pub container: Option<String>,
     pub host: String,
     pub port: u16,
     pub username: String,
-    pub password: String,
+    /// The jump host the server is reached through, in `[user@]host[:port]` form like OpenSSH's `ProxyJump`.
+    #[serde(default)]
+    pub jump_host: Option<String>,
+    /// The password, inline. At most one of `password`, `password_env` and `password_command` may be set.
+    #[serde(default)]
+    pub password: Option<String>,
+    /// The local environment variable holding the password.
+    #[serde(default)]
+    pub password_env: Option<String>,
+    /// A local command printing the password, e.g. `pass show build-server`.
+    #[serde(default)]
+    pub password_command: Option<String>,
+    /// The private key tried first by the `key` authentication method,
+    /// before the ones from `~/.ssh/config` and the default keys.
+    #[serde(default)]
+    pub private_key_path: Option<String>,
+    /// The passphrase of the private key, if it is encrypted.
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    /// The authentication methods to try, in order.
+    pub auth_order: Vec<AuthMethod>,
+    /// The known-hosts file the server's key is checked against, `~/.ssh/known_hosts` if not set.
+    /// `sbs hostkey` only manages a file set here.
+    #[serde(default)]
+    pub known_hosts_path: Option<String>,
+    /// How strictly the server's key is checked against the known-hosts file.
+    pub strict_host_key_checking: HostKeyChecking,
+    pub login_shell: bool,
+    /// Which IP versions the host (and jump host) may resolve to.
+    pub address_family: AddressFamily,
+    /// How often to send a keepalive while commands are silent, so the connection isn't dropped as idle. `0` sends none.
+    pub keepalive_secs: u64,
+    /// Whether to compress the SSH session, trading CPU time on both ends for bandwidth.
+    pub compression: bool,
+    /// How long connecting, including the handshake and authentication, may take. `0` waits indefinitely.
+    pub connect_timeout_secs: u64,
+    /// How many times a failed connection is retried.
+    pub max_retries: u32,
+    /// How long to wait before the first retry in milliseconds, doubling for every retry after it.
+    pub retry_backoff_ms: u64,
+}
+
+impl fmt::Debug for Ssh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| REDACTED);
+
+        f.debug_struct("Ssh")
+            .field("transport", &self.transport)
+            .field("container", &self.container)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("jump_host", &self.jump_host)
+            .field("password", &redacted(&self.password))
+            .field("password_env", &self.password_env)
+            .field("password_command", &self.password_command)
+            .field("private_key_path", &self.private_key_path)
+            .field("passphrase", &redacted(&self.passphrase))
+            .field("auth_order", &self.auth_order)
+            .field("known_hosts_path", &self.known_hosts_path)
+            .field("strict_host_key_checking", &self.strict_host_key_checking)
+            .field("login_shell", &self.login_shell)
+            .field("address_family", &self.address_family)
+            .field("keepalive_secs", &self.keepalive_secs)
+            .field("compression", &self.compression)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("max_retries", &self.max_retries)
+            .field("retry_backoff_ms", &self.retry_backoff_ms)
+            .finish()
+    }
+}
+
+/// How the build server is reached.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// An SSH session, the default.
+    #[default]
+    Ssh,
+    /// A local Docker container, through `docker exec` and `docker cp`.
+    Docker,
+}
+
+impl Ssh {
+    /// Gets the password from whichever of `password`, `password_env` and `password_command` is set.
+    ///
+    /// The command runs in the local shell, and only the trailing line break of its output is removed.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let settings = Settings::new("Settings.toml").unwrap();
+    ///
+    /// let password = settings.ssh.resolve_password().unwrap();
+    /// ```
+    pub fn resolve_password(&self) -> Result<Option<String>, ConfigError> {
+        match (&self.password, &self.password_env, &self.password_command) {
+            (None, None, None) => Ok(None),
+            (Some(password), None, None) => Ok(Some(password.clone())),
+            (None, Some(name), None) => std::env::var(name)
+                .map(Some)
+                .map_err(|_| ConfigError::Message(format!("The environment variable '{}' in ssh.password_env isn't set!", name))),
+            (None, None, Some(command)) => {
+                let output = local::run_output(command)
+                    .map_err(|err| ConfigError::Message(format!("Failed to run ssh.password_command: {}", err)))?;
+
+                let password = output.strip_suffix('\n').unwrap_or(&output);
+                let password = password.strip_suffix('\r').unwrap_or(password);
+
+                Ok(Some(password.to_string()))
+            }
+            _ => Err(ConfigError::Message("Only one of ssh.password, ssh.password_env and ssh.password_command may be set!".to_string())),
+        }
+    }
+}
+
+/// How strictly the key of the server is checked, like OpenSSH's `StrictHostKeyChecking`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Unknown and changed keys are rejected.
+    Yes,
+    /// Unknown keys are added to the known-hosts file, changed keys are rejected.
+    AcceptNew,
+    /// The key isn't checked at all.
+    No,
+}
+
+/// Which IP versions a host name may resolve to, like OpenSSH's `AddressFamily`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// IPv4 and IPv6, in the order the resolver returns them.
+    #[default]
+    Any,
+    /// IPv4 only.
+    Inet,
+    /// IPv6 only.
+    Inet6,
+}
+
+impl AddressFamily {
+    /// Checks whether an address belongs to the family.
+    pub fn allows(self, address: &SocketAddr) -> bool {
+        match self {
+            AddressFamily::Any => true,
+            AddressFamily::Inet => address.is_ipv4(),
+            AddressFamily::Inet6 => address.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::Any => write!(f, "IPv4 or IPv6"),
+            AddressFamily::Inet => write!(f, "IPv4"),
+            AddressFamily::Inet6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// How a directory tree is uploaded.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    /// Every file is sent on its own, over SCP.
+    Scp,
+    /// The tree is sent as a single tar archive and extracted on the server, which needs `tar` there.
+    Tar,
+}
+
+/// What happens to symlinks in a transferred directory tree.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Symlinks aren't transferred.
+    Skip,
+    /// The file or directory a symlink points at is transferred in its place, unless that would loop.
+    Follow,
+    /// The symlink itself is transferred, pointing at the same target.
+    Recreate,
+}
+
+/// A way of authenticating with the SSH server.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    /// Use the keys loaded into the running SSH agent.
+    Agent,
+    /// Use `private_key_path`, the `IdentityFile`s from `~/.ssh/config` or the default keys.
+    Key,
+    /// Use the password.
+    Password,
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Agent => write!(f, "agent"),
+            AuthMethod::Key => write!(f, "key"),
+            AuthMethod::Password => write!(f, "password"),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Compilation {
     pub local_project_root: String,
     pub remote_project_root: String,
+    /// A remote command whose output replaces `remote_project_root` once connected.
+    #[serde(default)]
+    pub remote_project_root_command: Option<String>,
+    /// The directory the build output is downloaded from, relative to the project root on both sides unless it's absolute.
     pub output_directory: String,
+    pub preserve_xattrs: bool,
+    /// What happens to symlinks in uploaded and downloaded directories.
+    pub symlinks: SymlinkPolicy,
+    pub deterministic_order: bool,
+    pub build_metadata: bool,
+    pub output_buffer_size: usize,
+    /// The encoding of the remote command output, as a WHATWG label like `utf-8`, `latin1` or `shift_jis`.
+    pub output_encoding: String,
+    /// Whether the stderr of the commands is interleaved into their stdout, in the order it was written.
+    pub merge_stderr: bool,
+    /// Whether every command runs in its own channel and shell, instead of all of a phase in one script.
+    pub sequential_commands: bool,
+    /// How long a command may run before it's killed, unless it sets its own `timeout_secs`. 0 for no limit.
+    pub command_timeout_secs: u64,
+    pub rsync_trailing_slash: bool,
+    pub incremental_download: bool,
+    /// Whether to skip uploading files whose size and modification time haven't changed.
+    pub incremental_upload: bool,
+    /// Whether the remote upload directory is removed before every upload, so files deleted locally don't linger.
+    pub clean_remote: bool,
+    /// Whether a remote directory is removed once the output was downloaded, to free space on the server.
+    pub cleanup_remote_after: bool,
+    /// The remote directory `cleanup_remote_after` removes, relative to the remote project root unless it's absolute.
+    /// `.` is the whole project, the remote output directory if not set.
+    #[serde(default)]
+    pub cleanup_remote_path: Option<String>,
+    /// Whether the project is uploaded file by file, or as a single tar archive.
+    pub transfer_mode: TransferMode,
+    /// How many files are uploaded at once.
+    pub parallel_transfers: usize,
+    /// The permissions of the remote directories the upload creates.
+    pub remote_dir_mode: u32,
+    /// The permissions of the uploaded files, those of the local files if not set.
+    #[serde(default)]
+    pub remote_file_mode: Option<u32>,
+    pub max_files: usize,
+    pub lock: bool,
+    pub lock_timeout_secs: u64,
+    pub measure_resources: bool,
+    /// A remote directory storing uploaded files by content hash, which may be shared between projects.
+    #[serde(default)]
+    pub content_store_dir: Option<String>,
+    /// Local environment variables forwarded to the remote commands.
+    pub send_env: Vec<String>,
+    /// `.gitignore` style patterns of local files that aren't uploaded.
+    pub ignore: Vec<String>,
+    /// Whether the patterns in the `.gitignore` of the local project root are added to `ignore`.
+    pub use_gitignore: bool,
+    /// A remote command that has to succeed for the run to go ahead, otherwise the run is skipped.
+    #[serde(default)]
+    pub precondition_command: Option<String>,
+    /// Whether files are gzipped one by one before they're sent, for servers without SSH compression.
+    pub compress_files: bool,
+    /// The size in bytes below which files are sent uncompressed.
+    pub compress_min_size: u64,
+    /// Extensions of already-compressed files, which compressed transfers send as-is.
+    pub incompressible_extensions: Vec<String>,
+    /// A directory to download while the project is being uploaded.
+    #[serde(default)]
+    pub concurrent_download: Option<ConcurrentDownload>,
+    /// A local command that checks the downloaded output, receiving its path as `$1` and `SBS_OUTPUT_DIR`.
+    #[serde(default)]
+    pub verify_command: Option<String>,
+    /// Whether the downloaded output is compared against SHA-256 checksums computed on the server.
+    pub verify_checksums: bool,
+    /// How long the local project has to stay unchanged before `--watch` runs again, in milliseconds.
+    pub watch_debounce_ms: u64,
+    /// `.gitignore` style patterns of the output files to download, relative to the output directory. Empty to download everything.
+    pub download_patterns: Vec<String>,
+}
+
+/// A remote directory that is downloaded concurrently with the upload, e.g. the artifacts of a previous build.
+///
+/// It's downloaded over a second connection, so it doesn't wait for the upload.
+///
+/// Neither path may overlap with the corresponding project root.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConcurrentDownload {
+    pub local_directory: String,
+    pub remote_directory: String,
 }
 
 impl Compilation {
     /// Gets the remote output directory.
     ///
+    /// An absolute `output_directory` (e.g. a shared `/artifacts` mount) is used as is.
+    ///
     /// # Example
     ///
-    /// ```
-    /// use crate::util::settings::Settings;
+    /// ```no_run
+    /// use ssh_build_server::util::settings::Settings;
     ///
     /// let settings = Settings::new("Settings.toml").unwrap();
     /// let remote_output_directory = settings.compilation.get_remote_output_directory();
@@ -66,15 +664,74 @@ impl Compilation {
     /// println!("Remote output directory: {}", remote_output_directory);
     /// ```
     pub fn get_remote_output_directory(&self) -> String {
-        format!("{}/{}", self.remote_project_root, self.output_directory)
+        if self.has_absolute_output_directory() {
+            return self.output_directory.clone();
+        }
+
+        format!("{}/{}", self.get_remote_upload_directory(), self.output_directory)
     }
 
-    /// Gets the local output directory.
+    /// Gets the remote directory the contents of the local project root are uploaded into.
+    ///
+    /// With `rsync_trailing_slash` enabled, this follows rsync's rules: a local root with a trailing slash
+    /// (`/path/to/project/`) uploads its contents into the remote root, while one without (`/path/to/project`)
+    /// uploads the directory itself, i.e. into `<remote_project_root>/project`.
+    /// Otherwise the contents are always uploaded into the remote root.
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// use ssh_build_server::util::settings::Settings;
+    ///
+    /// let settings = Settings::new("Settings.toml").unwrap();
+    /// let remote_upload_directory = settings.compilation.get_remote_upload_directory();
+    ///
+    /// println!("Remote upload directory: {}", remote_upload_directory);
     /// ```
-    /// use crate::util::settings::Settings;
+    pub fn get_remote_upload_directory(&self) -> String {
+        let copies_contents = self.local_project_root.ends_with('/') || self.local_project_root.ends_with('\\');
+        if !self.rsync_trailing_slash || copies_contents {
+            return self.remote_project_root.clone();
+        }
+
+        match Path::new(&self.local_project_root).file_name() {
+            Some(name) => format!("{}/{}", self.remote_project_root.trim_end_matches('/'), name.to_string_lossy()),
+            None => self.remote_project_root.clone(),
+        }
+    }
+
+    /// Gets the remote directory `cleanup_remote_after` removes.
+    ///
+    /// That's the remote output directory unless `cleanup_remote_path` is set, which is relative to the remote upload directory
+    /// unless it's absolute, and `.` for the upload directory itself.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ssh_build_server::util::settings::Settings;
+    ///
+    /// let settings = Settings::new("Settings.toml").unwrap();
+    /// let remote_cleanup_directory = settings.compilation.get_remote_cleanup_directory();
+    ///
+    /// println!("Remote cleanup directory: {}", remote_cleanup_directory);
+    /// ```
+    pub fn get_remote_cleanup_directory(&self) -> String {
+        match self.cleanup_remote_path.as_deref() {
+            None => self.get_remote_output_directory(),
+            Some("." | "./") => self.get_remote_upload_directory(),
+            Some(path) if Path::new(path).has_root() => path.to_string(),
+            Some(path) => format!("{}/{}", self.get_remote_upload_directory(), path),
+        }
+    }
+
+    /// Gets the local output directory.
+    ///
+    /// An absolute `output_directory` is used as is, like on the remote.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use ssh_build_server::util::settings::Settings;
     ///
     /// let settings = Settings::new("Settings.toml").unwrap();
     /// let local_output_directory = settings.compilation.get_local_output_directory();
@@ -82,37 +739,724 @@ impl Compilation {
     /// println!("Local output directory: {}", local_output_directory);
     /// ```
     pub fn get_local_output_directory(&self) -> String {
+        if self.has_absolute_output_directory() {
+            return self.output_directory.clone();
+        }
+
         format!("{}/{}", self.local_project_root, self.output_directory)
     }
+
+    /// Checks whether the output directory is an absolute path rather than one relative to the project roots.
+    fn has_absolute_output_directory(&self) -> bool {
+        Path::new(&self.output_directory).has_root()
+    }
+}
+
+/// The names and file extensions of the config formats.
+const FORMATS: [(&str, FileFormat); 7] = [
+    ("toml", FileFormat::Toml),
+    ("json", FileFormat::Json),
+    ("yaml", FileFormat::Yaml),
+    ("yml", FileFormat::Yaml),
+    ("ini", FileFormat::Ini),
+    ("ron", FileFormat::Ron),
+    ("json5", FileFormat::Json5),
+];
+
+/// Gets the config format with the given name or file extension.
+///
+/// # Arguments
+///
+/// * `name` - The name, e.g. `toml`, `json` or `yml`, in any case.
+///
+/// # Examples
+///
+/// ```
+/// # use config::FileFormat;
+/// # use ssh_build_server::util::settings::format_from_name;
+/// assert_eq!(format_from_name("YML"), Some(FileFormat::Yaml));
+/// ```
+pub fn format_from_name(name: &str) -> Option<FileFormat> {
+    let name = name.to_lowercase();
+
+    FORMATS.iter()
+        .find(|(format_name, _)| *format_name == name)
+        .map(|(_, format)| *format)
+}
+
+/// Lists the names of the config formats for error messages, each with the given prefix.
+///
+/// # Arguments
+///
+/// * `prefix` - What goes in front of each name, e.g. `.` for file extensions.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::settings::format_names;
+/// assert_eq!(format_names("."), ".toml, .json, .yaml, .yml, .ini, .ron or .json5");
+/// ```
+pub fn format_names(prefix: &str) -> String {
+    let names = FORMATS.iter().map(|(name, _)| format!("{}{}", prefix, name)).collect::<Vec<_>>();
+    let (last, rest) = names.split_last().expect("There are config formats");
+
+    format!("{} or {}", rest.join(", "), last)
+}
+
+/// Gets the format of a config file from its extension.
+///
+/// # Arguments
+///
+/// * `path` - The path of the config file.
+fn file_format(path: &Path) -> Result<FileFormat, ConfigError> {
+    let extension = path.extension().map(|extension| extension.to_string_lossy()).unwrap_or_default();
+
+    format_from_name(&extension).ok_or_else(|| ConfigError::Message(format!(
+        "The config '{}' has an unsupported extension, use {}!", path.display(), format_names("."))))
+}
+
+/// Interpolates the environment into a value and everything in it.
+///
+/// # Arguments
+///
+/// * `key` - The key of the value, for the error message.
+/// * `value` - The value.
+fn interpolate_value(key: &str, value: Value) -> Result<Value, ConfigError> {
+    let kind = match value.kind {
+        ValueKind::String(string) => ValueKind::String(interpolate(&string, |name| std::env::var(name).ok())
+            .map_err(|err| ConfigError::Message(format!("Failed to interpolate `{}`: {}", key, err)))?),
+        ValueKind::Table(table) => ValueKind::Table(table.into_iter()
+            .map(|(child_key, child)| {
+                if is_command_key(&child_key) {
+                    return Ok((child_key, child));
+                }
+
+                let child = interpolate_value(&format!("{}.{}", key, child_key), child)?;
+
+                Ok((child_key, child))
+            })
+            .collect::<Result<_, ConfigError>>()?),
+        ValueKind::Array(array) => ValueKind::Array(array.into_iter()
+            .enumerate()
+            .map(|(index, child)| interpolate_value(&format!("{}[{}]", key, index), child))
+            .collect::<Result<_, _>>()?),
+        kind => kind,
+    };
+
+    Ok(Value::new(None, kind))
+}
+
+/// Checks whether a key holds a shell command, like `precondition_command`, whose shell expands variables on its own.
+fn is_command_key(key: &str) -> bool {
+    key == "command" || key.ends_with("_command")
+}
+
+/// Replaces every `${NAME}` in a string with the value of the variable, and `$${` with a literal `${`.
+///
+/// # Arguments
+///
+/// * `string` - The string.
+/// * `lookup` - Gets the value of a variable, or `None` if it isn't set.
+///
+/// # Examples
+///
+/// ```ignore
+/// assert_eq!(interpolate("${USER}@host", |_| Some("root".to_string())).unwrap(), "root@host");
+/// ```
+fn interpolate(string: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(string.len());
+    let mut rest = string;
+
+    while let Some(start) = rest.find('$') {
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            interpolated.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| "A reference is missing its closing brace!".to_string())?;
+            let name = &after[..end];
+
+            let value = lookup(name).ok_or_else(|| format!("The environment variable `{}` isn't set!", name))?;
+            interpolated.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            interpolated.push('$');
+            rest = &rest[1..];
+        }
+    }
+    interpolated.push_str(rest);
+
+    Ok(interpolated)
+}
+
+/// A named server, selected with `--server`.
+///
+/// Its `ssh` and `compilation` tables are only read when it's selected, see `Settings::load`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ServerProfile {
+    pub name: String,
+}
+
+/// Where to send a summary once a run finishes.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Notifications {
+    /// A URL the run report is `POST`ed to as JSON.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// A local command run with the outcome in `SBS_RUN_STATUS` and the JSON report in `SBS_RUN_REPORT`.
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+/// A command preparing the local project before it's uploaded, like `cargo vendor`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LocalCommand {
+    pub command: String,
+    pub description: String,
+    /// The directory the command runs in, relative to the local project root, which is also where commands without one run.
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Environment variables set for the command only.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl LocalCommand {
+    /// Gets the directory the command runs in.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_project_root` - The local project root, which relative directories are resolved against.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let working_dir = command.resolve_working_dir("/path/to/project");
+    /// ```
+    pub fn resolve_working_dir(&self, local_project_root: &str) -> PathBuf {
+        match &self.working_dir {
+            Some(dir) => Path::new(local_project_root).join(dir),
+            None => PathBuf::from(local_project_root),
+        }
+    }
+}
+
+/// One-time setup for a fresh build server, run with `sbs init-remote`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Provision {
+    /// The commands that install the toolchain and dependencies, in order.
+    #[serde(default)]
+    pub commands: Vec<Command>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Command {
+    /// An optional name used to select the command with `--only` and `--skip`.
+    #[serde(default)]
+    pub name: Option<String>,
     pub command: String,
     pub description: String,
+    /// The directory the command runs in. Relative paths are resolved against the directory the project is uploaded into,
+    /// which is also where commands without one run.
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Environment variables exported to this command only, on top of the ones every command gets.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// How long the command may run before it's killed, `compilation.command_timeout_secs` if not set and 0 for no limit.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
     pub execute_after_compilation: bool,
+    /// Whether a failure of the command is only logged, instead of stopping the phase.
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Whether output matching `warning_pattern` fails the phase, even if the command succeeds.
+    #[serde(default)]
+    pub warnings_as_errors: bool,
+    /// The regular expression that detects warnings, `(?im)^\s*warning\b` if not set.
+    #[serde(default)]
+    pub warning_pattern: Option<String>,
+    /// The exit codes that count as success, any other code stops the phase.
+    #[serde(default = "default_success_codes")]
+    pub success_codes: Vec<i32>,
 }
 
+impl Command {
+    /// Creates a build command with every optional setting left at its default, like a `[[commands]]` entry
+    /// that only sets `command` and `description`.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line.
+    /// * `description` - What the command does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ssh_build_server::util::settings::Command;
+    /// let command = Command { timeout_secs: Some(600), ..Command::new("cargo build --release", "Build the project.") };
+    /// ```
+    pub fn new(command: &str, description: &str) -> Self {
+        Self {
+            name: None,
+            command: command.to_string(),
+            description: description.to_string(),
+            working_dir: None,
+            env: BTreeMap::new(),
+            timeout_secs: None,
+            execute_after_compilation: false,
+            continue_on_error: false,
+            warnings_as_errors: false,
+            warning_pattern: None,
+            success_codes: default_success_codes(),
+        }
+    }
+
+    /// Gets the directory the command runs in.
+    ///
+    /// # Arguments
+    ///
+    /// * `default_dir` - The directory of commands without a `working_dir`, which relative ones are resolved against.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ssh_build_server::util::settings::Command;
+    /// # let command = Command::new("make", "Build the native library.");
+    /// let working_dir = command.resolve_working_dir(Some("/builds/project"));
+    /// ```
+    pub fn resolve_working_dir(&self, default_dir: Option<&str>) -> Option<String> {
+        match (self.working_dir.as_deref(), default_dir) {
+            (Some(dir), Some(default_dir)) if !dir.starts_with('/') && !dir.starts_with('~') =>
+                Some(format!("{}/{}", default_dir.trim_end_matches('/'), dir)),
+            (Some(dir), _) => Some(dir.to_string()),
+            (None, default_dir) => default_dir.map(String::from),
+        }
+    }
+}
+
+/// Only `0` counts as success, unless a command says otherwise.
+fn default_success_codes() -> Vec<i32> {
+    vec![0]
+}
+
+/// Selects commands by name, on top of the phase they belong to.
+#[derive(Debug, Default, Clone)]
+pub struct CommandFilter {
+    /// If not empty, only the commands with one of these names are run.
+    pub only: Vec<String>,
+    /// The commands with one of these names are never run.
+    pub skip: Vec<String>,
+}
+
+impl CommandFilter {
+    /// Checks whether a command passes the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use ssh_build_server::util::settings::{CommandFilter, Settings};
+    /// # let settings = Settings::new("").unwrap();
+    /// let filter = CommandFilter { only: vec!["build".to_string()], skip: Vec::new() };
+    ///
+    /// let commands = settings.commands.iter().filter(|command| filter.allows(command));
+    /// ```
+    pub fn allows(&self, command: &Command) -> bool {
+        let name = command.name.as_deref();
+
+        if !self.only.is_empty() && !self.only.iter().any(|only| Some(only.as_str()) == name) {
+            return false;
+        }
+
+        !self.skip.iter().any(|skip| Some(skip.as_str()) == name)
+    }
+}
+
+/// The sections whose string values may reference environment variables as `${NAME}`.
+///
+/// The `[[commands]]` and `[provision]` commands are left alone, their shell expands variables on its own,
+/// and so are the commands inside the sections, see `is_command_key`.
+const INTERPOLATED_SECTIONS: [&str; 3] = ["ssh", "compilation", "notifications"];
+
+/// What secrets are replaced with when the settings are printed.
+const REDACTED: &str = "****";
+
+/// The config path that makes the settings be read from stdin.
+pub const STDIN_PATH: &str = "-";
+
 /// The default settings profile for the program.
 pub const DEFAULT_SETTINGS: &str = r##"
 [ssh]
+transport = "ssh" # How to reach the build server: "ssh", or "docker" to build in the local container named by `container`.
 host = "localhost"
 port = 22
 username = "root"
-password = "root"
+auth_order = ["agent", "key"] # The authentication methods to try, in order, add "password" to log in with a password.
+login_shell = false # Whether to run commands in a login shell (bash -lc), which loads the user's profile and PATH.
+address_family = "any" # Which IP versions the host may resolve to: "any", "inet" (IPv4 only) or "inet6" (IPv6 only).
+keepalive_secs = 30 # How often to send a keepalive while the commands print nothing, so firewalls and NATs don't drop the connection, 0 to send none.
+compression = false # Whether to compress the SSH session, which pays off on slow links but costs CPU time on both ends.
+connect_timeout_secs = 30 # How long connecting (including the handshake and authentication) may take, 0 to wait indefinitely.
+strict_host_key_checking = "yes" # "yes" rejects unknown hosts, "accept-new" trusts them on first use, "no" doesn't check the host key.
+max_retries = 3 # How many times to retry a failed connection. Rejected credentials and host keys aren't retried.
+retry_backoff_ms = 500 # How long to wait before the first retry, doubling for every retry after it.
 
 [compilation]
 local_project_root = "/path/to/project" # The path to the project on your local machine from the root of the project.
 remote_project_root = "~/remote/project" # The path to the project on the remote machine from the root of the project.
-output_directory = "target/release" # The directory where the compiled binary is located relative to the project root.
-
-[[commands]]
-command = "cd /remote/project"
-description = "Change directory to the project root."
-execute_after_compilation = false
+output_directory = "target/release" # The directory where the compiled binary is located relative to the project root, or an absolute path used on both sides as is.
+preserve_xattrs = false # Whether to carry over extended attributes (ACLs, SELinux contexts, capabilities) during transfers.
+symlinks = "follow" # What happens to symlinks during transfers: "follow" sends what they point at (skipping links that loop), "recreate" re-creates the links, "skip" leaves them out.
+deterministic_order = true # Whether to transfer directory entries sorted by name, for reproducible logs and archives.
+send_env = [] # Local environment variables to forward to the remote commands, e.g. ["CI", "GITHUB_SHA"].
+ignore = [".git/"] # Patterns of local files to leave out of the upload, in .gitignore syntax.
+use_gitignore = true # Whether to also leave out what the .gitignore of the local project root ignores.
+build_metadata = false # Whether to expose SBS_GIT_COMMIT, SBS_GIT_BRANCH and SBS_BUILD_TIME to the remote commands.
+output_buffer_size = 65536 # The size in bytes of the buffer used to read command output.
+output_encoding = "utf-8" # The encoding of the remote command output, e.g. "latin1" or "shift_jis" for localized toolchains.
+merge_stderr = false # Whether to interleave the stderr of the commands into their stdout instead of capturing it separately.
+sequential_commands = false # Whether to run every command in its own shell, so nothing (like a variable) carries over between them.
+command_timeout_secs = 0 # How long a build or post-compilation command may run before it's killed, 0 for no limit. Commands can set their own timeout_secs.
+rsync_trailing_slash = false # Whether a local_project_root without a trailing slash is uploaded as a directory into the remote root, like rsync.
+clean_remote = false # Whether to delete the remote upload directory before uploading, so files deleted locally don't linger there. Asks first, non-interactive runs need --yes.
+cleanup_remote_after = false # Whether to delete the remote output directory once a run downloaded it, to free space on the server. Set cleanup_remote_path to delete another directory, e.g. "." for the whole project. Asks first, non-interactive runs need --yes.
+transfer_mode = "scp" # How to upload the project: "scp" sends every file on its own, "tar" sends one archive and extracts it on the server (needs tar there), which is much faster for many small files.
+parallel_transfers = 1 # How many files are uploaded at once, each over a channel of its own. Keep it below the server's MaxSessions (10 by default).
+remote_dir_mode = 0o755 # The permissions of the remote directories the upload creates, e.g. 0o700 to keep them private. Set remote_file_mode to override the permissions of the uploaded files, which keep those of the local files otherwise.
+incremental_upload = false # Whether to skip uploading files whose size and modification time haven't changed. Uploaded files then keep their local modification time.
+incremental_download = false # Whether to skip downloading files whose size and modification time haven't changed.
+watch_debounce_ms = 500 # How long the local project has to stay unchanged before --watch runs again, so a burst of saves triggers a single run.
+download_patterns = [] # Patterns of the output files to download, in .gitignore syntax, e.g. ["/myapp", "*.so"]. Empty to download everything.
+verify_checksums = false # Whether to compare the downloaded files against SHA-256 checksums computed on the server (needs sha256sum there).
+max_files = 100000 # The maximum number of files in the local project root before the upload is refused, 0 for no limit.
+lock = false # Whether to lock the remote project root, so runs targeting the same directory don't collide.
+lock_timeout_secs = 600 # How long to wait for another run to release the lock.
+measure_resources = false # Whether to record the CPU time and peak memory of the build commands (needs GNU time on the server).
+compress_files = false # Whether to gzip files one by one before sending them, for servers that refuse SSH compression (needs gzip on the server).
+compress_min_size = 4096 # The size in bytes below which files are sent uncompressed.
+incompressible_extensions = [ # Already-compressed files that compressed transfers don't compress again.
+    "7z", "apk", "avif", "br", "bz2", "deb", "flac", "gif", "gz", "jar", "jpeg", "jpg",
+    "lz4", "mkv", "mp3", "mp4", "ogg", "png", "rpm", "webm", "webp", "xz", "zip", "zst",
+]
 
 [[commands]]
 command = "cargo build --release"
 description = "Build the project."
 execute_after_compilation = false
 "##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compilation(local_project_root: &str, rsync_trailing_slash: bool) -> Compilation {
+        let mut compilation = Settings::new("").unwrap().compilation;
+        compilation.local_project_root = local_project_root.to_string();
+        compilation.remote_project_root = "/remote/build".to_string();
+        compilation.rsync_trailing_slash = rsync_trailing_slash;
+
+        compilation
+    }
+
+    /// A temporary directory of configs, removed with everything in it when it's dropped, even if an assertion failed.
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("sbs-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+
+            Self(path)
+        }
+
+        /// Writes a file, creating its directories, and returns its path for loading it.
+        fn write(&self, relative_path: &str, contents: &str) -> String {
+            let path = self.0.join(relative_path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+
+            path.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Loads the settings from a TOML config with the given contents.
+    fn load_toml(name: &str, contents: &str) -> Result<Settings, ConfigError> {
+        let directory = ConfigDir::new(name);
+
+        Settings::new(&directory.write("config.toml", contents))
+    }
+
+    #[test]
+    fn extends_merges_base_configs() {
+        let directory = ConfigDir::new("extends");
+        directory.write("bases/base.toml", "[ssh]\nhost = \"base\"\nport = 2222\n");
+
+        let settings = Settings::new(&directory.write("child.toml", "extends = \"bases/base.toml\"\n[ssh]\nhost = \"child\"\n")).unwrap();
+
+        assert_eq!(settings.ssh.host, "child");
+        assert_eq!(settings.ssh.port, 2222);
+        assert_eq!(settings.ssh.username, "root");
+    }
+
+    #[test]
+    fn commands_file_is_appended_to_the_inline_commands() {
+        let directory = ConfigDir::new("commands-file");
+        directory.write("recipes/build.sh", "# Build the project.\nmake\n");
+
+        let file_only = Settings::new(&directory.write("file.toml", "commands_file = \"recipes/build.sh\"\n")).unwrap();
+        let both = Settings::new(&directory.write(
+            "both.toml",
+            "commands_file = \"recipes/build.sh\"\n[[commands]]\ncommand = \"./configure\"\ndescription = \"Configure.\"\n",
+        )).unwrap();
+
+        let commands = |settings: &Settings| settings.commands.iter().map(|command| command.command.clone()).collect::<Vec<_>>();
+        assert_eq!(commands(&file_only), vec!["make"]);
+        assert_eq!(file_only.commands[0].description, "Build the project.");
+        assert_eq!(commands(&both), vec!["./configure", "make"]);
+    }
+
+    #[test]
+    fn local_commands_run_in_the_local_project_root() {
+        let settings = load_toml(
+            "local-commands",
+            "[[local_commands]]\ncommand = \"cargo vendor\"\ndescription = \"Vendor.\"\n\n[[local_commands]]\ncommand = \"make\"\ndescription = \"Generate.\"\nworking_dir = \"assets\"\n",
+        ).unwrap();
+
+        assert!(Settings::new("").unwrap().local_commands.is_empty());
+        assert_eq!(settings.local_commands.len(), 2);
+        assert_eq!(settings.local_commands[0].resolve_working_dir("/project"), PathBuf::from("/project"));
+        assert_eq!(settings.local_commands[1].resolve_working_dir("/project"), PathBuf::from("/project/assets"));
+    }
+
+    #[test]
+    fn extends_rejects_cycles() {
+        let directory = ConfigDir::new("extends-cycle");
+        directory.write("b.toml", "extends = \"a.toml\"\n");
+
+        assert!(Settings::new(&directory.write("a.toml", "extends = \"b.toml\"\n")).is_err());
+    }
+
+    #[test]
+    fn server_profiles_override_the_top_level_settings() {
+        let directory = ConfigDir::new("servers");
+        let path = directory.write("config.toml", "\
+[ssh]
+host = \"x86-box\"
+username = \"builder\"
+
+[[servers]]
+name = \"arm\"
+ssh = { host = \"arm-box\", port = 2222 }
+compilation = { remote_project_root = \"/build/arm\" }
+");
+
+        let top_level = Settings::load(&path, FileFormat::Toml, None).unwrap();
+        let arm = Settings::load(&path, FileFormat::Toml, Some("arm")).unwrap();
+        let missing = Settings::load(&path, FileFormat::Toml, Some("riscv"));
+
+        assert_eq!(top_level.ssh.host, "x86-box");
+        assert_eq!(arm.ssh.host, "arm-box");
+        assert_eq!(arm.ssh.port, 2222);
+        assert_eq!(arm.ssh.username, "builder");
+        assert_eq!(arm.compilation.remote_project_root, "/build/arm");
+        assert!(missing.unwrap_err().to_string().contains("use one of arm"));
+    }
+
+    #[test]
+    fn formats_are_detected_by_extension() {
+        let directory = ConfigDir::new("formats");
+        directory.write("base.toml", "[ssh]\nport = 2222\n");
+
+        let yaml = Settings::new(&directory.write("build.yml", "extends: base.toml\nssh:\n  host: yaml-box\ncommands:\n  - command: make\n    description: Build.\n")).unwrap();
+        let json = Settings::new(&directory.write("build.json", r#"{"ssh": {"host": "json-box"}, "commands": [{"command": "make", "description": "Build."}]}"#)).unwrap();
+        let unsupported = Settings::new(&directory.write("build.conf", ""));
+
+        assert_eq!((yaml.ssh.host.as_str(), yaml.ssh.port), ("yaml-box", 2222));
+        assert_eq!(json.ssh.host, "json-box");
+        assert_eq!(json.ssh.username, "root");
+        assert_eq!(json.commands[0].command, "make");
+        assert!(unsupported.unwrap_err().to_string().contains("unsupported extension, use .toml, .json, .yaml, .yml, .ini, .ron or .json5!"));
+    }
+
+    #[test]
+    fn environment_variables_are_interpolated() {
+        let lookup = |name: &str| (name == "SBS_HOST").then(|| "arm-box".to_string());
+
+        assert_eq!(interpolate("${SBS_HOST}:22", lookup).unwrap(), "arm-box:22");
+        assert_eq!(interpolate("$HOME and $${SBS_HOST} stay", lookup).unwrap(), "$HOME and ${SBS_HOST} stay");
+        assert!(interpolate("${SBS_UNSET}", lookup).unwrap_err().contains("`SBS_UNSET` isn't set"));
+        assert!(interpolate("${SBS_HOST", lookup).is_err());
+
+        let result = load_toml("interpolate", "[ssh]\npassword = \"${SBS_TEST_UNSET_PASSWORD}\"\n");
+
+        assert!(result.unwrap_err().to_string().contains("ssh.password"));
+    }
+
+    #[test]
+    fn commands_are_left_to_their_shell() {
+        let settings = load_toml("interpolate-commands", concat!(
+            "[compilation]\n",
+            "remote_project_root_command = \"echo ${SBS_TEST_UNSET_ROOT}\"\n",
+            "precondition_command = \"test -d ${HOME}\"\n",
+            "verify_command = \"test -x ${SBS_OUTPUT_DIR}/app\"\n",
+            "[notifications]\ncommand = \"echo ${SBS_RUN_STATUS}\"\n",
+        )).unwrap();
+
+        assert_eq!(settings.compilation.remote_project_root_command.as_deref(), Some("echo ${SBS_TEST_UNSET_ROOT}"));
+        assert_eq!(settings.compilation.precondition_command.as_deref(), Some("test -d ${HOME}"));
+        assert_eq!(settings.compilation.verify_command.as_deref(), Some("test -x ${SBS_OUTPUT_DIR}/app"));
+        assert_eq!(settings.notifications.command.as_deref(), Some("echo ${SBS_RUN_STATUS}"));
+    }
+
+    #[test]
+    fn malformed_secrets_stay_out_of_the_error() {
+        let err = load_toml("malformed-secret", "[ssh]\npassword = \"hunter2${x\"\n").unwrap_err().to_string();
+        assert!(err.contains("ssh.password"));
+        assert!(!err.contains("hunter2"));
+    }
+
+    #[test]
+    fn password_comes_from_exactly_one_source() {
+        let mut ssh = Settings::new("").unwrap().ssh;
+        assert_eq!(ssh.resolve_password().unwrap(), None);
+
+        ssh.password_command = Some("echo hunter2".to_string());
+        assert_eq!(ssh.resolve_password().unwrap().as_deref(), Some("hunter2"));
+
+        ssh.password = Some("root".to_string());
+        assert!(ssh.resolve_password().is_err());
+    }
+
+    #[test]
+    fn debug_output_hides_the_secrets() {
+        let mut settings = Settings::new("").unwrap();
+        settings.ssh.password = Some("hunter2".to_string());
+        settings.ssh.passphrase = Some("correct horse".to_string());
+
+        let printed = format!("{:?}", settings);
+
+        assert!(!printed.contains("hunter2"));
+        assert!(!printed.contains("correct horse"));
+        assert!(printed.contains("password: Some(\"****\")"));
+        assert!(printed.contains("username: "));
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut settings = Settings::new("").unwrap();
+        settings.ssh.host = String::new();
+        settings.ssh.port = 0;
+        settings.commands.clear();
+
+        let problems = settings.validate().unwrap_err();
+        assert_eq!(problems.len(), 4);
+        assert!(problems.iter().any(|problem| problem.contains("local_project_root")));
+
+        settings = Settings::new("").unwrap();
+        settings.compilation.local_project_root = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn password_auth_needs_a_password() {
+        let mut settings = load_toml("password-auth", "[ssh]\nauth_order = [\"key\", \"password\"]\n").unwrap();
+        settings.compilation.local_project_root = std::env::temp_dir().to_string_lossy().into_owned();
+        assert!(settings.validate().unwrap_err()[0].contains("ssh.auth_order lists password"));
+
+        settings.ssh.password_env = Some("SBS_PASSWORD".to_string());
+        assert_eq!(settings.validate(), Ok(()));
+
+        settings.ssh.auth_order = vec![AuthMethod::Key];
+        assert!(settings.validate().unwrap_err()[0].contains("doesn't list password"));
+    }
+
+    #[test]
+    fn remote_modes_are_read_in_octal() {
+        let mut settings = load_toml("remote-modes", "[compilation]\nremote_dir_mode = 0o700\nremote_file_mode = 0o600\n").unwrap();
+
+        assert_eq!(Settings::new("").unwrap().compilation.remote_dir_mode, 0o755);
+        assert_eq!(settings.compilation.remote_dir_mode, 0o700);
+        assert_eq!(settings.compilation.remote_file_mode, Some(0o600));
+
+        settings.compilation.local_project_root = std::env::temp_dir().to_string_lossy().into_owned();
+        settings.compilation.remote_dir_mode = 0o644;
+        assert!(settings.validate().unwrap_err()[0].contains("remote_dir_mode"));
+    }
+
+    #[test]
+    fn host_key_checking_is_strict_by_default() {
+        let settings = load_toml("host-key-checking", "[ssh]\nstrict_host_key_checking = \"accept-new\"\n").unwrap();
+
+        assert_eq!(Settings::new("").unwrap().ssh.strict_host_key_checking, HostKeyChecking::Yes);
+        assert_eq!(settings.ssh.strict_host_key_checking, HostKeyChecking::AcceptNew);
+    }
+
+    #[test]
+    fn cleanup_removes_the_output_directory_unless_told_otherwise() {
+        let mut compilation = compilation("/home/user/project", false);
+        assert_eq!(compilation.get_remote_cleanup_directory(), "/remote/build/target/release");
+
+        compilation.cleanup_remote_path = Some(".".to_string());
+        assert_eq!(compilation.get_remote_cleanup_directory(), "/remote/build");
+
+        compilation.cleanup_remote_path = Some("target".to_string());
+        assert_eq!(compilation.get_remote_cleanup_directory(), "/remote/build/target");
+
+        compilation.cleanup_remote_path = Some("/".to_string());
+        assert_eq!(compilation.get_remote_cleanup_directory(), "/");
+
+        let mut settings = Settings::new("").unwrap();
+        settings.compilation = compilation;
+        settings.compilation.local_project_root = std::env::temp_dir().to_string_lossy().into_owned();
+        settings.compilation.cleanup_remote_after = true;
+        assert!(settings.validate().unwrap_err()[0].contains("cleanup_remote_after"));
+    }
+
+    #[test]
+    fn trailing_slash_uploads_contents() {
+        let compilation = compilation("/home/user/project/", true);
+
+        assert_eq!(compilation.get_remote_upload_directory(), "/remote/build");
+        assert_eq!(compilation.get_remote_output_directory(), "/remote/build/target/release");
+    }
+
+    #[test]
+    fn no_trailing_slash_uploads_directory_itself() {
+        let compilation = compilation("/home/user/project", true);
+
+        assert_eq!(compilation.get_remote_upload_directory(), "/remote/build/project");
+        assert_eq!(compilation.get_remote_output_directory(), "/remote/build/project/target/release");
+    }
+
+    #[test]
+    fn absolute_output_directory_is_used_as_is() {
+        let mut compilation = compilation("/home/user/project", false);
+
+        assert_eq!(compilation.get_remote_output_directory(), "/remote/build/target/release");
+        assert_eq!(compilation.get_local_output_directory(), "/home/user/project/target/release");
+
+        compilation.output_directory = "/artifacts/release".to_string();
+
+        assert_eq!(compilation.get_remote_output_directory(), "/artifacts/release");
+        assert_eq!(compilation.get_local_output_directory(), "/artifacts/release");
+    }
+
+    #[test]
+    fn trailing_slash_is_ignored_when_disabled() {
+        assert_eq!(compilation("/home/user/project", false).get_remote_upload_directory(), "/remote/build");
+        assert_eq!(compilation("/home/user/project/", false).get_remote_upload_directory(), "/remote/build");
+    }
+}
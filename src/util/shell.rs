@@ -0,0 +1,53 @@
+/// Quotes a string for safe use as a single POSIX shell word.
+///
+/// # Arguments
+///
+/// * `value` - The string to quote.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::shell::quote;
+/// assert_eq!(quote("it's"), "'it'\\''s'");
+/// ```
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quotes a remote path for use as a single POSIX shell word, leaving a leading `~` to be expanded by the shell.
+///
+/// # Arguments
+///
+/// * `path` - The path to quote.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::shell::quote_path;
+/// assert_eq!(quote_path("~/my project"), "~/'my project'");
+/// ```
+pub fn quote_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", quote(rest)),
+        None if path == "~" => path.to_string(),
+        None => quote(path),
+    }
+}
+
+/// Checks whether a string is a valid name for a shell variable.
+///
+/// # Arguments
+///
+/// * `name` - The name.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::shell::is_variable_name;
+/// assert!(is_variable_name("CARGO_TARGET_DIR"));
+/// assert!(!is_variable_name("1st"));
+/// ```
+pub fn is_variable_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
@@ -1,15 +1,159 @@
-use std::fs::File;
+use std::fs::{File, Metadata};
 use std::io;
 use std::io::{Error, ErrorKind, Read, Write};
-use std::net::TcpStream;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use ssh2::{DisconnectCode, Session, Sftp};
+use encoding_rs::{Encoding, UTF_8};
+use log::{debug, info, warn};
+use ssh2::{Channel, DisconnectCode, ErrorCode, ExtendedData, FileStat, Session, Sftp};
 
-use crate::util::settings::Command;
+use crate::util::compression::{self, FileCompression};
+use crate::util::connection::{self, Connection, Credentials, SftpHandle};
+use crate::util::error::SbsError;
+use crate::util::guard::{self, format_size, RemovalPreview};
+use crate::util::ignore::IgnoreRules;
+use crate::util::resources::{self, ResourceUsage};
+use crate::util::settings::{AddressFamily, AuthMethod, Command, CommandFilter, HostKeyChecking, SymlinkPolicy, TransferMode};
+use crate::util::timeout::{self, CommandClock};
+use crate::util::transfer::{self, ProgressTracker, ScpBackend, TransferBackend, TransferProgress, TransferSummary};
+use crate::util::tunnel::{JumpHost, Tunnel};
+use crate::util::{archive, encoding, git, hostkeys, shell, status, temp, walk, warnings, xattrs};
+
+/// The default size of the buffer used to read command output.
+pub const DEFAULT_OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The default permissions of the remote directories uploads create.
+pub const DEFAULT_REMOTE_DIR_MODE: i32 = 0o755;
+
+/// The generic SFTP failure status, which most servers report when `mkdir` hits an existing path.
+const SFTP_FAILURE: i32 = 4;
+/// The dedicated "file already exists" SFTP status (protocol version 5 and up).
+const SFTP_FILE_ALREADY_EXISTS: i32 = 11;
+
+/// How long killing a timed out command may take, so an unresponsive server can't hang the run after all.
+const ABORT_TIMEOUT_MILLIS: u32 = 10_000;
+
+/// How long to wait before looking for command output again when none of its streams had any.
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Receives command output while it's streamed.
+type OutputCallback<'a> = &'a mut dyn FnMut(OutputStream, &[u8]);
+
+/// The stream streamed command output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// The remote operations needed to create directories.
+trait DirectoryCreator {
+    /// Checks whether the path is a directory, failing if it doesn't exist.
+    fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error>;
+
+    /// Creates a single directory.
+    fn mkdir(&self, path: &Path, mode: i32) -> Result<(), ssh2::Error>;
+}
+
+impl DirectoryCreator for Sftp {
+    fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error> {
+        self.stat(path).map(|stat| stat.is_dir())
+    }
+
+    fn mkdir(&self, path: &Path, mode: i32) -> Result<(), ssh2::Error> {
+        Sftp::mkdir(self, path, mode)
+    }
+}
+
+/// What a local directory entry is sent as, see `symlinks`.
+enum LocalEntry {
+    Directory,
+    File,
+    /// A symlink that's re-created remotely, pointing at this target.
+    Symlink(PathBuf),
+    Skip,
+}
+
+/// The output of a batch of remote commands.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// The exit status of the remote shell.
+    pub exit_code: i32,
+}
 
 pub struct Sbs {
-    pub session: Session,
+    pub connection: Connection,
+    /// Whether destructive operations may proceed without asking for confirmation.
+    pub assume_yes: bool,
+    /// Whether extended attributes (and thereby ACLs and SELinux contexts) are carried over during transfers.
+    pub preserve_xattrs: bool,
+    /// What happens to symlinks in transferred directories.
+    pub symlinks: SymlinkPolicy,
+    /// Whether directory entries are processed sorted by name instead of in filesystem order.
+    pub deterministic_order: bool,
+    /// Environment variables exported to every remote command.
+    pub environment: Vec<(String, String)>,
+    /// Selects which commands are run by name.
+    pub command_filter: CommandFilter,
+    /// The size of the buffer used to read command output.
+    pub output_buffer_size: usize,
+    /// Whether commands run in a login shell (`bash -lc`) instead of the server's default non-login shell.
+    pub login_shell: bool,
+    /// Whether downloads skip files whose size and modification time match the local copy.
+    pub incremental_download: bool,
+    /// Whether uploads skip files whose size and modification time match the remote copy.
+    /// Uploaded files then keep their local modification time.
+    pub incremental_upload: bool,
+    /// Whether `send_directory` sends every file on its own, or the whole tree as one tar archive.
+    pub transfer_mode: TransferMode,
+    /// How many files `send_directory` sends at once, each over a channel of its own.
+    pub parallel_transfers: usize,
+    /// The permissions of the remote directories uploads create.
+    pub remote_dir_mode: i32,
+    /// The permissions of uploaded files, or `None` to keep the permission bits of the local files.
+    pub remote_file_mode: Option<i32>,
+    /// Whether transfers, commands and deletions only print what they would do, instead of touching the server.
+    pub dry_run: bool,
+    /// Whether build commands are run under `time -v` to record their resource usage.
+    pub measure_resources: bool,
+    /// The encoding command output is decoded from.
+    pub output_encoding: &'static Encoding,
+    /// Whether stderr is interleaved into stdout instead of being captured separately.
+    pub merge_stderr: bool,
+    /// How long connecting (including the handshake and authentication) may take, or `None` to wait indefinitely.
+    pub connect_timeout: Option<Duration>,
+    /// How many times a failed connection is retried.
+    pub max_retries: u32,
+    /// How long to wait before the first retry, doubling for every retry after it.
+    pub retry_backoff: Duration,
+    /// Which IP versions the host names may resolve to.
+    pub address_family: AddressFamily,
+    /// How often a keepalive is sent while waiting for silent commands, or `None` to never send one.
+    pub keepalive: Option<Duration>,
+    /// The jump host the server is reached through, if any. It's authenticated like the server.
+    pub jump_host: Option<JumpHost>,
+    /// The local files that uploads skip, relative to the directory being sent.
+    pub ignore: IgnoreRules,
+    /// The known-hosts file the server's key is checked against, if any.
+    pub known_hosts_path: Option<PathBuf>,
+    /// How strictly the server's key is checked against `known_hosts_path`.
+    pub host_key_checking: HostKeyChecking,
+    /// Whether the SSH session is compressed (zlib), if the server allows it.
+    pub compression: bool,
+    /// Whether (and which) files are gzipped before they're sent.
+    pub file_compression: Option<FileCompression>,
+    /// The directory commands run in unless they set their own `working_dir`, usually the uploaded project.
+    pub working_directory: Option<String>,
+    /// How long a command may run unless it sets its own `timeout_secs`, if at all.
+    pub command_timeout: Option<Duration>,
+    xattrs_unsupported: AtomicBool,
+    resource_usage: Mutex<Option<ResourceUsage>>,
 }
 
 impl Sbs {
@@ -22,13 +166,48 @@ impl Sbs {
     /// # Examples
     ///
     /// ```
+    /// # use ssh2::Session;
+    /// # use ssh_build_server::util::ssh::Sbs;
     /// let session = Session::new().unwrap(); // Your SSH session.
     ///
     /// let sbs = Sbs::new(session);
     /// ```
     pub fn new(session: Session) -> Self {
         Self {
-            session,
+            connection: Connection::new(session),
+            assume_yes: false,
+            preserve_xattrs: false,
+            symlinks: SymlinkPolicy::Follow,
+            deterministic_order: true,
+            environment: Vec::new(),
+            command_filter: CommandFilter::default(),
+            output_buffer_size: DEFAULT_OUTPUT_BUFFER_SIZE,
+            login_shell: false,
+            incremental_download: false,
+            incremental_upload: false,
+            transfer_mode: TransferMode::Scp,
+            parallel_transfers: 1,
+            remote_dir_mode: DEFAULT_REMOTE_DIR_MODE,
+            remote_file_mode: None,
+            dry_run: false,
+            measure_resources: false,
+            output_encoding: UTF_8,
+            merge_stderr: false,
+            connect_timeout: None,
+            max_retries: 0,
+            retry_backoff: Duration::from_millis(500),
+            address_family: AddressFamily::Any,
+            keepalive: None,
+            jump_host: None,
+            ignore: IgnoreRules::default(),
+            known_hosts_path: None,
+            host_key_checking: HostKeyChecking::Yes,
+            compression: false,
+            file_compression: None,
+            working_directory: None,
+            command_timeout: None,
+            xattrs_unsupported: AtomicBool::new(false),
+            resource_usage: Mutex::new(None),
         }
     }
 
@@ -43,67 +222,228 @@ impl Sbs {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
     ///
     /// sbs.connect("localhost", &22, "username", "password").unwrap();
     /// ```
-    pub fn connect(&mut self, host: &str, port: &u16, username: &str, password: &str) -> Result<(), Error> {
-        let address = format!("{}:{}", host, port);
+    pub fn connect(&mut self, host: &str, port: &u16, username: &str, password: &str) -> Result<(), SbsError> {
+        let credentials = Credentials {
+            password: Some(password),
+            private_keys: &[],
+            passphrase: None,
+        };
 
-        self.session.set_tcp_stream(TcpStream::connect(address)?);
-        self.session.handshake()?;
-        self.session.userauth_password(username, password)?;
+        self.connect_with(host, port, username, credentials, &[AuthMethod::Password])?;
 
         Ok(())
     }
 
-    /// Disconnects from the SSH server.
+    /// Connects to the SSH server, authenticating with the running SSH agent.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `port` - The port.
+    /// * `username` - The username.
     ///
     /// # Examples
     ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.connect_with_agent("localhost", &22, "username").unwrap();
     /// ```
+    pub fn connect_with_agent(&mut self, host: &str, port: &u16, username: &str) -> Result<(), SbsError> {
+        let credentials = Credentials {
+            password: None,
+            private_keys: &[],
+            passphrase: None,
+        };
+        self.open(host, port, username, credentials, &[AuthMethod::Agent])?;
+
+        let result = self.connection.authenticate_agent(username);
+        self.finish_connecting();
+
+        result.map_err(|err| SbsError::Auth(err.to_string()))
+    }
+
+    /// Connects to the SSH server, trying the given authentication methods in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host.
+    /// * `port` - The port.
+    /// * `username` - The username.
+    /// * `credentials` - The available secrets.
+    /// * `auth_order` - The authentication methods to try, in order.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
+    /// let credentials = Credentials { password: Some("password"), private_keys: &[], passphrase: None };
     ///
-    /// sbs.disconnect(None, "", None).unwrap();
+    /// let method = sbs.connect_with("localhost", &22, "username", credentials, &[AuthMethod::Agent, AuthMethod::Password]).unwrap();
+    /// ```
+    pub fn connect_with(&mut self, host: &str, port: &u16, username: &str, credentials: Credentials, auth_order: &[AuthMethod]) -> Result<AuthMethod, SbsError> {
+        self.open(host, port, username, credentials, auth_order)?;
+
+        let result = self.connection.authenticate(username, credentials, auth_order);
+        self.finish_connecting();
+
+        result.map_err(|err| SbsError::Auth(err.to_string()))
+    }
+
+    /// Connects to the SSH server (through the jump host, if there is one) and verifies its host key, if a known-hosts file is configured.
+    ///
+    /// A failed connection is retried up to `max_retries` times on a fresh session, waiting twice as long before each retry.
+    /// A host key that doesn't match isn't retried.
+    /// The connect timeout also applies to authentication, `finish_connecting` lifts it again.
+    /// The username, credentials and authentication methods are only used for the jump host here.
+    fn open(&mut self, host: &str, port: &u16, username: &str, credentials: Credentials, auth_order: &[AuthMethod]) -> Result<(), SbsError> {
+        // IPv6 addresses may come in brackets, which the known-hosts file and the jump host don't expect.
+        let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        let address = connection::socket_address(host, *port);
+
+        let mut attempt = 0;
+        loop {
+            // Negotiated during the handshake, and lost with every reset.
+            self.connection.session().set_compress(self.compression);
+
+            let result = match self.jump_host.clone() {
+                Some(jump_host) => self.open_tunnel(&jump_host, host, *port, username, credentials, auth_order)?
+                    .and_then(|tunnel| self.connection.connect_through(tunnel, self.connect_timeout)),
+                None => self.connection.connect(&address, self.address_family, self.connect_timeout),
+            };
+            let Err(err) = result else {
+                break;
+            };
+
+            if attempt >= self.max_retries {
+                return Err(SbsError::connect(err));
+            }
+
+            let backoff = self.retry_backoff.saturating_mul(1 << attempt.min(16));
+            attempt += 1;
+            warn!(
+                "Failed to connect to '{}' ({}), retrying in {} ms (retry {} of {})...",
+                address,
+                err,
+                backoff.as_millis(),
+                attempt,
+                self.max_retries,
+            );
+
+            thread::sleep(backoff);
+            self.connection.reset()?;
+        }
+
+        if let Some(known_hosts_path) = &self.known_hosts_path {
+            hostkeys::verify(self.connection.session(), known_hosts_path, host, *port, self.host_key_checking).map_err(SbsError::Connect)?;
+        }
+
+        Ok(())
+    }
+
+    /// Connects and authenticates to the jump host, and opens a tunnel through it to the server.
+    ///
+    /// Failing to reach the jump host, or the server through it, is returned in the inner result so it can be retried.
+    /// A host key or credentials the jump host rejects fail the outer one.
+    fn open_tunnel(
+        &self,
+        jump_host: &JumpHost,
+        host: &str,
+        port: u16,
+        username: &str,
+        credentials: Credentials,
+        auth_order: &[AuthMethod],
+    ) -> Result<Result<Tunnel, Error>, SbsError> {
+        let mut jump = Connection::new(Session::new()?);
+        if let Err(err) = jump.connect(&jump_host.address(), self.address_family, self.connect_timeout) {
+            return Ok(Err(err));
+        }
+
+        if let Some(known_hosts_path) = &self.known_hosts_path {
+            hostkeys::verify(jump.session(), known_hosts_path, &jump_host.host, jump_host.port, self.host_key_checking).map_err(SbsError::Connect)?;
+        }
+
+        let method = jump.authenticate(jump_host.username.as_deref().unwrap_or(username), credentials, auth_order)
+            .map_err(|err| SbsError::Auth(format!("Failed to authenticate with the jump host '{}': {}", jump_host.host, err)))?;
+        info!("Authenticated with the jump host '{}' using {}.", jump_host.host, method);
+
+        Ok(Tunnel::open(jump, host, port))
+    }
+
+    /// Lifts the connect timeout, so long-running commands and transfers aren't cut off by it, and sets up the keepalives.
+    fn finish_connecting(&self) {
+        self.connection.session().set_timeout(0);
+
+        if let Some(keepalive) = self.keepalive {
+            self.connection.session().set_keepalive(false, u32::try_from(keepalive.as_secs()).unwrap_or(u32::MAX).max(1));
+        }
+    }
+
+    /// Sends a keepalive to the server, so the connection isn't dropped as idle.
+    ///
+    /// Commands send keepalives on their own while they're silent, see `keepalive`. This is for idle periods between operations,
+    /// and has to be called from the thread using the connection, since the session is locked while it's waiting on the server.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.send_keepalive().unwrap();
     /// ```
-    pub fn disconnect(&mut self, reason: Option<DisconnectCode>, description: &str, lang: Option<&str>) -> Result<(), Error> {
-        self.session.disconnect(reason, description, lang)?;
+    pub fn send_keepalive(&self) -> Result<(), SbsError> {
+        self.connection.session().keepalive_send()?;
 
         Ok(())
     }
 
+    /// Disconnects from the SSH server.
+    ///
+    /// Disconnecting a session that's already closed or dead succeeds, and waiting for the server is bounded by a short timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.disconnect(None, "", None).unwrap();
+    /// ```
+    pub fn disconnect(&mut self, reason: Option<DisconnectCode>, description: &str, lang: Option<&str>) -> Result<(), SbsError> {
+        self.connection.disconnect(reason, description, lang).map_err(SbsError::Connect)
+    }
+
     /// Compiles a list of commands into a single string.
     ///
+    /// The configured environment variables are exported ahead of the commands.
+    ///
     /// # Arguments
     ///
     /// * `commands` - The commands.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
     ///
     /// let commands = vec![
     ///    "ls",
-    ///    "cd /test",
-    ///    "ls",
+    ///    "make",
     /// ];
     ///
     /// let compiled = sbs.compile_commands(&commands);
     /// ```
-    fn compile_commands(&self, commands: &Vec<Command>) -> String {
-        let mut compiled = String::new();
-
-        for command in commands {
-            compiled.push_str(command.command.as_str());
-            compiled.push('\n');
-        }
-
-        compiled
+    fn compile_commands(&self, commands: &[Command]) -> String {
+        compile_script(&self.environment, self.working_directory.as_deref(), commands)
     }
 
-    /// Sends a list of commands to the SSH server and returns the output.
+    /// Sends a list of commands to the SSH server and returns their output and exit status.
+    ///
+    /// Fails with `SbsError::CommandFailed`, naming the command, as soon as a command exits with a code outside its success codes.
     ///
     /// # Arguments
     ///
@@ -112,183 +452,2329 @@ impl Sbs {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
     ///
     /// let commands = vec![
     ///     "ls",
-    ///     "cd /test",
-    ///     "ls",
+    ///     "make",
     /// ];
     ///
     /// let output = sbs.execute_commands(&commands, false).unwrap();
     /// ```
-    pub fn execute_commands(&self, commands: &[Command], is_after_compilation: bool) -> Result<String, Error> {
-        // If it's after compilation, we remove the commands that are before compilation.
-        let mut commands = commands.to_vec();
-        // For each command that does not match is_after_compilation, remove it.
-        commands.retain(|command| command.execute_after_compilation == is_after_compilation);
-
-        // Compile the commands into a single string.
-        let compiled_commands = self.compile_commands(&commands);
-
-        let mut channel = self.session.channel_session()?;
+    pub fn execute_commands(&self, commands: &[Command], is_after_compilation: bool) -> Result<CommandOutput, SbsError> {
+        let commands = self.phase_commands(commands, is_after_compilation);
 
-        // Execute the commands.
-        channel.exec(&compiled_commands)?;
-
-        // Read the output.
-        let mut output = String::new();
-        channel.read_to_string(&mut output)?;
+        // Only the build itself is measured, not the post-compilation commands.
+        self.run_batch(&commands, self.measure_resources && !is_after_compilation, None)
+    }
 
-        channel.wait_eof()?;
-        channel.wait_close()?;
-        channel.close()?;
+    /// Sends a list of commands to the SSH server, passing their stdout and stderr to a callback as they arrive.
+    ///
+    /// The callback gets whole lines, as raw bytes, along with the stream they were written to.
+    /// With `merge_stderr`, everything arrives as stdout, in the order it was written.
+    /// The full output is returned at the end, like `execute_commands` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - The commands.
+    /// * `is_after_compilation` - Whether this function is called before or after program compilation.
+    /// * `on_output` - Called with each batch of complete output lines and the stream they're from.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let output = sbs.execute_commands_streaming(&settings.commands, false, |stream, chunk| match stream {
+    ///     OutputStream::Stdout => io::stdout().write_all(chunk).unwrap(),
+    ///     OutputStream::Stderr => io::stderr().write_all(chunk).unwrap(),
+    /// }).unwrap();
+    /// ```
+    pub fn execute_commands_streaming(&self, commands: &[Command], is_after_compilation: bool, mut on_output: impl FnMut(OutputStream, &[u8])) -> Result<CommandOutput, SbsError> {
+        let commands = self.phase_commands(commands, is_after_compilation);
 
-        // Return the output.
-        Ok(output)
+        self.run_batch(&commands, self.measure_resources && !is_after_compilation, Some(&mut on_output))
     }
 
-    /// Sends a directory recursively via SCP.
+    /// Sends the commands to the SSH server one by one, each in its own channel and shell, and returns their combined output.
+    ///
+    /// Nothing carries over between the commands, e.g. every command starts in the home directory.
+    /// Execution stops at the first command that fails, and the error names it by its description.
+    /// Resource usage isn't measured in this mode.
     ///
     /// # Arguments
     ///
-    /// * `local_path` - The local path.
-    /// * `remote_path` - The remote path.
+    /// * `commands` - The commands.
+    /// * `is_after_compilation` - Whether this function is called before or after program compilation.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
     ///
-    /// let local_path = Path::new("/path/to/local_dir");
-    /// let remote_path = Path::new("/path/to/remote_dir");
-    ///
-    /// sbs.send_directory(&local_path, &remote_path).unwrap();
+    /// let output = sbs.execute_commands_sequential(&settings.commands, false).unwrap();
     /// ```
-    pub fn send_directory(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
-        // Make sure the local path exists.
-        if !local_path.exists() {
-            return Err(Error::new(ErrorKind::NotFound, format!("The local path '{}' does not exist!", local_path.display())));
+    pub fn execute_commands_sequential(&self, commands: &[Command], is_after_compilation: bool) -> Result<CommandOutput, SbsError> {
+        let mut output = CommandOutput::default();
+
+        for command in self.phase_commands(commands, is_after_compilation) {
+            let command_output = self.run_batch(std::slice::from_ref(&command), false, None)?;
+
+            output.stdout.push_str(&command_output.stdout);
+            output.stderr.push_str(&command_output.stderr);
+            output.exit_code = command_output.exit_code;
         }
 
-        let sftp_session = self.session.sftp()?;
+        Ok(output)
+    }
 
-        // Make sure the remote path exists.
-        match sftp_session.stat(remote_path) {
-            Ok(stat) => {
-                if !stat.is_dir() {
-                    return Err(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' is not a directory!", remote_path.display())));
-                }
-            }
-            Err(_) => {
-                eprintln!("The remote path '{}' does not exist, creating it...", remote_path.display());
+    /// Selects the commands of one phase that pass the name filter, filling in the default timeout.
+    fn phase_commands(&self, commands: &[Command], is_after_compilation: bool) -> Vec<Command> {
+        commands.iter()
+            .filter(|command| command.execute_after_compilation == is_after_compilation)
+            .filter(|command| self.command_filter.allows(command))
+            .cloned()
+            .map(|mut command| {
+                command.timeout_secs = command.timeout_secs
+                    .or(self.command_timeout.map(|timeout| timeout.as_secs()))
+                    .filter(|&secs| secs > 0);
+
+                command
+            })
+            .collect()
+    }
 
-                Self::make_dirs(&sftp_session, remote_path);
+    /// Runs commands as a single script in one channel, failing if any of them fails.
+    fn run_batch(&self, commands: &[Command], measure_resources: bool, on_output: Option<OutputCallback>) -> Result<CommandOutput, SbsError> {
+        if self.dry_run {
+            for command in commands {
+                println!("Would run `{}` ({}).", command.command, command.description);
             }
+
+            return Ok(CommandOutput::default());
         }
 
-        // Iterate over the local directory.
-        for entry in local_path.read_dir()? {
-            let entry = entry?;
-            let path = entry.path();
+        // Compile the commands into a single string.
+        let mut compiled_commands = self.compile_commands(commands);
+        if self.login_shell {
+            // Force a login shell, so the user's profile (and thereby their PATH) is loaded.
+            compiled_commands = format!("bash -lc {}", shell::quote(&compiled_commands));
+        }
 
-            if path.is_dir() {
-                // Send the directory recursively.
-                self.send_directory(&path, &remote_path.join(entry.file_name()))?;
-            } else {
-                // Send the file.
-                let mut remote_file = self.session.scp_send(
-                    &remote_path.join(entry.file_name()),
-                    0o755, // Read, write, execute by owner.
-                    path.metadata()?.len(),
-                    None,
-                )?;
+        let usage_path = temp::remote_temp_path("time").to_string_lossy().into_owned();
+        if measure_resources {
+            compiled_commands = resources::wrap_command(&compiled_commands, &usage_path);
+        }
+
+        let mut channel = self.connection.channel()?;
+        if self.merge_stderr {
+            channel.handle_extended_data(ExtendedData::Merge)?;
+        }
 
-                let mut local_file = File::open(&path)?;
-                io::copy(&mut local_file, &mut remote_file)?;
+        // Execute the commands.
+        channel.exec(&compiled_commands)?;
 
-                remote_file.flush()?;
+        // Read the output, passing it on while it arrives if asked to, until the running command runs out of time.
+        let mut clock = CommandClock::new(commands, temp::run_token());
+        let read = self.read_batch_output(&mut channel, &mut clock, on_output);
+        self.connection.session().set_timeout(0);
+        let (raw_stdout, raw_stderr) = match read {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut && clock.remaining().is_some() => {
+                return Err(self.abort_batch(&mut channel, &clock));
             }
+            Err(err) => return Err(err.into()),
+        };
+
+        if measure_resources {
+            self.record_resource_usage(&usage_path);
         }
 
-        Ok(())
+        // Return the output, as long as every command succeeded.
+        let output = CommandOutput {
+            stdout: timeout::strip_pid_marker(encoding::decode(&raw_stdout, self.output_encoding), temp::run_token()),
+            stderr: encoding::decode(&raw_stderr, self.output_encoding),
+            exit_code: channel.exit_status()?,
+        };
+
+        let mut output = status::check(commands, output, temp::run_token())?;
+        output.stdout = warnings::enforce(commands, output.stdout)?;
+
+        Ok(output)
     }
 
-    fn make_dirs(sftp_session: &Sftp, remote_path: &Path) {
-        let mut path = PathBuf::new();
+    /// Reads the stdout and stderr of a running script until it exits, enforcing the timeout of the running command.
+    ///
+    /// Both streams are drained in the same loop, with the session in non-blocking mode, so a script filling the window
+    /// of one of them can't stall while the other one is waited on.
+    /// Gives up once the running command is out of time, failing with `ErrorKind::TimedOut`.
+    fn read_batch_output(&self, channel: &mut Channel, clock: &mut CommandClock, on_output: Option<OutputCallback>) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        self.connection.session().set_blocking(false);
+        let read = self.drain_batch_output(channel, clock, on_output);
+        self.connection.session().set_blocking(true);
+        let output = read?;
 
-        for component in remote_path.components() {
-            path.push(component.as_os_str());
+        channel.wait_eof()?;
+        channel.wait_close()?;
+        channel.close()?;
 
-            match sftp_session.stat(&path) {
-                Ok(stat) => {
-                    if !stat.is_dir() {
-                        panic!("The remote path '{}' is not a directory!", path.display());
+        Ok(output)
+    }
+
+    /// Reads both output streams of a script as they have data, until both of them ended. The session has to be non-blocking.
+    fn drain_batch_output(&self, channel: &mut Channel, clock: &mut CommandClock, mut on_output: Option<OutputCallback>) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut raw_stdout = Vec::new();
+        let mut raw_stderr = Vec::new();
+        let mut chunk = vec![0u8; self.output_buffer_size.max(1)];
+        let mut pending_stdout = Vec::new();
+        let mut pending_stderr = Vec::new();
+        let (mut stdout_ended, mut stderr_ended) = (false, false);
+        let mut last_output = Instant::now();
+
+        while !(stdout_ended && stderr_ended) {
+            if clock.remaining().is_some_and(|remaining| remaining.is_zero()) {
+                return Err(Error::new(ErrorKind::TimedOut, "The running command ran out of time!"));
+            }
+
+            let mut received = false;
+            if !stdout_ended {
+                match read_available(channel, &mut chunk)? {
+                    // libssh2 also reads nothing when the data it got was for the other stream.
+                    Some(0) => stdout_ended = channel.eof(),
+                    Some(read) => {
+                        received = true;
+                        raw_stdout.extend_from_slice(&chunk[..read]);
+                        clock.observe(&raw_stdout);
+                        if let Some(on_output) = on_output.as_mut() {
+                            pending_stdout.extend_from_slice(&chunk[..read]);
+                            stream_lines(&mut pending_stdout, false, temp::run_token(), &mut |lines| on_output(OutputStream::Stdout, lines));
+                        }
                     }
+                    None => {}
                 }
-                Err(_) => {
-                    sftp_session.mkdir(&path, 0o755).unwrap();
+            }
+            if !stderr_ended {
+                match read_available(&mut channel.stderr(), &mut chunk)? {
+                    Some(0) => stderr_ended = channel.eof(),
+                    Some(read) => {
+                        received = true;
+                        raw_stderr.extend_from_slice(&chunk[..read]);
+                        if let Some(on_output) = on_output.as_mut() {
+                            pending_stderr.extend_from_slice(&chunk[..read]);
+                            stream_lines(&mut pending_stderr, false, temp::run_token(), &mut |lines| on_output(OutputStream::Stderr, lines));
+                        }
+                    }
+                    None => {}
+                }
+            }
+
+            if received {
+                last_output = Instant::now();
+
+                continue;
+            }
+
+            // Nothing to read, so keep an idle connection alive and wait a moment before looking again.
+            if self.keepalive.is_some_and(|keepalive| last_output.elapsed() >= keepalive) {
+                debug!("No output for a while, sending a keepalive...");
+                match self.connection.session().keepalive_send().map_err(Error::from) {
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                    result => {
+                        result?;
+                    }
                 }
+                last_output = Instant::now();
+            }
+
+            thread::sleep(OUTPUT_POLL_INTERVAL);
+        }
+        if let Some(on_output) = on_output {
+            stream_lines(&mut pending_stdout, true, temp::run_token(), &mut |lines| on_output(OutputStream::Stdout, lines));
+            stream_lines(&mut pending_stderr, true, temp::run_token(), &mut |lines| on_output(OutputStream::Stderr, lines));
+        }
+
+        Ok((raw_stdout, raw_stderr))
+    }
+
+    /// Kills a script whose running command ran out of time and closes its channel, as far as the server lets it.
+    fn abort_batch(&self, channel: &mut Channel, clock: &CommandClock) -> SbsError {
+        // Don't let a server that stopped responding hang the cleanup as well.
+        self.connection.session().set_timeout(ABORT_TIMEOUT_MILLIS);
+        if let Some(pid) = clock.pid() {
+            if let Err(err) = self.run_remote_command(&timeout::kill_command(pid)) {
+                warn!("Failed to kill the timed out command: {}", err);
             }
         }
+        let _ = channel.close();
+        self.connection.session().set_timeout(0);
+
+        match clock.current() {
+            Some(command) => SbsError::Timeout(format!("The command `{}` ({}) timed out after {}s!",
+                                                       command.command, command.description, command.timeout_secs.unwrap_or_default())),
+            None => SbsError::Timeout("The commands timed out!".to_string()),
+        }
     }
 
-    /// Receives a directory recursively via SCP.
+    /// Runs the provisioning commands that bring a fresh server up to spec, stopping at the first that fails.
     ///
     /// # Arguments
     ///
-    /// * `local_path` - The local path.
-    /// * `remote_path` - The remote path.
+    /// * `commands` - The provisioning commands.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// let sbs = Sbs::new(session); // Your SBS instance.
     ///
-    /// let local_path = Path::new("/path/to/local_dir");
-    /// let remote_path = Path::new("/path/to/remote_dir");
-    ///
-    /// sbs.receive_directory(&local_path, &remote_path).unwrap();
+    /// let output = sbs.provision(&settings.provision.commands).unwrap();
     /// ```
-    pub fn receive_directory(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
-        // Create the local directory.
-        std::fs::create_dir_all(local_path)?;
+    pub fn provision(&self, commands: &[Command]) -> Result<CommandOutput, SbsError> {
+        let commands = commands.iter()
+            .filter(|command| self.command_filter.allows(command))
+            .cloned()
+            .collect::<Vec<_>>();
 
-        // Retrieve the directory contents.
-        let remote_files = self.session.sftp()?.readdir(remote_path)?;
+        if self.dry_run {
+            for command in &commands {
+                println!("Would run `{}` ({}).", command.command, command.description);
+            }
 
-        // Iterate over the remote files.
-        for remote_file in remote_files {
-            let path_buf = remote_file.0;
-            let file_stat = remote_file.1;
+            return Ok(CommandOutput::default());
+        }
 
-            let remote_filename = match path_buf.file_name() {
-                Some(filename) => filename,
-                None => continue,
-            };
+        // The project doesn't have to exist yet, so only an explicit `working_dir` applies.
+        let mut compiled_commands = compile_script(&self.environment, None, &commands);
+        if self.login_shell {
+            compiled_commands = format!("bash -lc {}", shell::quote(&compiled_commands));
+        }
 
-            let remote_file_path = remote_path.join(remote_filename);
-            let local_file_path = local_path.join(remote_filename);
+        let (exit_code, stdout) = self.run_remote_command(&compiled_commands)?;
+        let mut output = status::check(&commands, CommandOutput { stdout, stderr: String::new(), exit_code }, temp::run_token())?;
+        output.stdout = warnings::enforce(&commands, output.stdout)?;
 
-            if file_stat.is_dir() {
-                // Create the corresponding local subdirectory.
-                std::fs::create_dir_all(&local_file_path)?;
+        Ok(output)
+    }
 
-                // Receive the subdirectory recursively.
-                self.receive_directory(&local_file_path, &remote_file_path)?;
-            } else {
-                // Receive the file.
-                let remote_file = self.session.scp_recv(&remote_file_path)?;
-                let mut local_file = File::create(&local_file_path)?;
+    /// Gets the resource usage of the last measured command execution, if it could be measured.
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.resource_usage.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
 
-                let mut channel = remote_file.0;
-                io::copy(&mut channel, &mut local_file)?;
+    /// Reads the resource usage written by `time -v` and removes the file.
+    fn record_resource_usage(&self, usage_path: &str) {
+        let path = shell::quote(usage_path);
+        let usage = match self.run_remote_command(&format!("cat {0} && rm -f {0}", path)) {
+            Ok((0, output)) => resources::parse_time_output(&output),
+            _ => None,
+        };
 
-                local_file.flush()?;
-            }
+        if usage.is_none() {
+            warn!("Failed to measure resource usage, is GNU time installed at /usr/bin/time?");
         }
 
-        Ok(())
+        *self.resource_usage.lock().unwrap_or_else(|err| err.into_inner()) = usage;
+    }
+
+    /// Sends a directory recursively via SCP, returning what was sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let local_path = Path::new("/path/to/local_dir");
+    /// let remote_path = Path::new("/path/to/remote_dir");
+    ///
+    /// let summary = sbs.send_directory(&local_path, &remote_path).unwrap();
+    ///
+    /// println!("Sent {}.", summary);
+    /// ```
+    pub fn send_directory(&self, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, SbsError> {
+        if self.transfer_mode == TransferMode::Tar && !self.dry_run {
+            return self.send_tree_tar(local_path, remote_path).map_err(SbsError::Transfer);
+        }
+
+        if self.parallel_transfers > 1 && !self.dry_run {
+            return self.send_tree_parallel(local_path, remote_path).map_err(SbsError::Transfer);
+        }
+
+        self.send_directory_with(&ScpBackend::new(self), local_path, remote_path)
+    }
+
+    /// Sends a directory recursively with the given transfer backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The transfer backend.
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let backend = SftpBackend::new(sbs.connection.sftp_handle().unwrap());
+    ///
+    /// sbs.send_directory_with(&backend, Path::new("/path/to/local_dir"), Path::new("/path/to/remote_dir")).unwrap();
+    /// ```
+    pub fn send_directory_with<B: TransferBackend>(&self, backend: &B, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, SbsError> {
+        // Nothing is sent by a dry run.
+        if self.dry_run {
+            return self.print_planned_upload(local_path, remote_path).map(|_| TransferSummary::default()).map_err(SbsError::Transfer);
+        }
+
+        let mut progress = ProgressTracker::none();
+        self.send_tree(backend, local_path, remote_path, Path::new(""), &mut progress).map_err(SbsError::Transfer)?;
+
+        Ok(progress.into_summary())
+    }
+
+    /// Sends a directory recursively via SCP, reporting the progress after every file.
+    ///
+    /// The local directory is walked once up front, to count the total bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    /// * `on_progress` - Called with the progress after every file.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.send_directory_with_progress(Path::new("/path/to/local_dir"), Path::new("/path/to/remote_dir"), |progress| {
+    ///     eprintln!("{} of {:?} bytes, '{}'", progress.bytes_done, progress.total_bytes, progress.current_file.display());
+    /// }).unwrap();
+    /// ```
+    pub fn send_directory_with_progress(&self, local_path: &Path, remote_path: &Path, mut on_progress: impl FnMut(TransferProgress)) -> Result<TransferSummary, SbsError> {
+        if self.dry_run {
+            return self.print_planned_upload(local_path, remote_path).map(|_| TransferSummary::default()).map_err(SbsError::Transfer);
+        }
+
+        // A missing local path is reported by the upload itself.
+        let total_bytes = walk::total_size(local_path, &self.ignore).ok();
+        let mut progress = ProgressTracker::new(&mut on_progress, total_bytes);
+        self.send_tree(&ScpBackend::new(self), local_path, remote_path, Path::new(""), &mut progress).map_err(SbsError::Transfer)?;
+
+        Ok(progress.into_summary())
+    }
+
+    /// Sends a directory recursively, see `send_directory_with`.
+    fn send_tree<B: TransferBackend>(&self, backend: &B, local_path: &Path, remote_path: &Path, relative_path: &Path, progress: &mut ProgressTracker) -> Result<(), Error> {
+        // Make sure the local path exists.
+        if !local_path.exists() {
+            return Err(Error::new(ErrorKind::NotFound, format!("The local path '{}' does not exist!", local_path.display())));
+        }
+
+        // Make sure the remote path exists.
+        backend.make_dir(remote_path)?;
+
+        let mut entries = local_path.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+        if self.deterministic_order {
+            entries.sort_by_key(|entry| entry.file_name());
+        }
+
+        // Iterate over the local directory.
+        for entry in entries {
+            let path = entry.path();
+            let relative_path = relative_path.join(entry.file_name());
+
+            // Skip ignored files, and ignored directories with everything in them.
+            if self.ignore.is_ignored(&relative_path, path.is_dir()) {
+                continue;
+            }
+
+            match self.classify_local_entry(&path, &relative_path)? {
+                // Send the directory recursively.
+                LocalEntry::Directory => self.send_tree(backend, &path, &remote_path.join(entry.file_name()), &relative_path, progress)?,
+                LocalEntry::File => {
+                    // Send the file.
+                    if self.send_tree_file(backend, &path, &remote_path.join(entry.file_name()), &relative_path)? {
+                        progress.file_done(&path, path.metadata()?.len());
+                    } else {
+                        progress.file_unchanged(&path, path.metadata()?.len());
+                    }
+                }
+                LocalEntry::Symlink(target) => {
+                    debug!("Linking '{}' to '{}'...", relative_path.display(), target.display());
+                    backend.symlink(&target, &remote_path.join(entry.file_name()))?;
+                }
+                LocalEntry::Skip => {}
+            }
+        }
+
+        self.send_xattrs(local_path, remote_path);
+
+        Ok(())
+    }
+
+    /// Decides what a local directory entry is sent as, following `symlinks`.
+    ///
+    /// Following a symlink to a directory that contains it would never end, so such a link is skipped with a warning,
+    /// as is a link to nothing.
+    fn classify_local_entry(&self, path: &Path, relative_path: &Path) -> Result<LocalEntry, Error> {
+        if !path.symlink_metadata()?.file_type().is_symlink() {
+            return Ok(if path.is_dir() { LocalEntry::Directory } else { LocalEntry::File });
+        }
+
+        match self.symlinks {
+            SymlinkPolicy::Skip => {
+                debug!("Skipping the symlink '{}'.", relative_path.display());
+
+                Ok(LocalEntry::Skip)
+            }
+            SymlinkPolicy::Recreate => Ok(LocalEntry::Symlink(path.read_link()?)),
+            SymlinkPolicy::Follow => {
+                let Ok(target) = path.canonicalize() else {
+                    warn!("Skipping the symlink '{}', since it points at nothing.", relative_path.display());
+
+                    return Ok(LocalEntry::Skip);
+                };
+
+                if !target.is_dir() {
+                    return Ok(LocalEntry::File);
+                }
+
+                let loops = path.parent()
+                    .into_iter()
+                    .flat_map(Path::ancestors)
+                    .any(|ancestor| ancestor.canonicalize().is_ok_and(|ancestor| ancestor == target));
+                if loops {
+                    warn!("Skipping the symlink '{}', since it points at a directory containing it.", relative_path.display());
+
+                    return Ok(LocalEntry::Skip);
+                }
+
+                Ok(LocalEntry::Directory)
+            }
+        }
+    }
+
+    /// Sends a file of a directory tree, unless an incremental upload finds it unchanged. Returns whether it was sent.
+    fn send_tree_file<B: TransferBackend>(&self, backend: &B, local_path: &Path, remote_path: &Path, relative_path: &Path) -> Result<bool, Error> {
+        // Without SFTP, every file counts as changed.
+        let unchanged = self.incremental_upload && backend.stat(remote_path).ok().flatten()
+            .is_some_and(|remote_stat| Self::is_unchanged(local_path, &remote_stat));
+        if unchanged {
+            debug!("Skipping the unchanged file '{}'.", relative_path.display());
+
+            return Ok(false);
+        }
+
+        debug!("Sending '{}'...", relative_path.display());
+        backend.send_file(local_path, remote_path)?;
+
+        Ok(true)
+    }
+
+    /// Sends a directory recursively with `parallel_transfers` workers, see `send_directory`.
+    ///
+    /// The directories are all created up front, so the workers only have to place files into them.
+    /// Every file is sent over a channel of its own, so the workers never share one.
+    fn send_tree_parallel(&self, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, Error> {
+        let backend = ScpBackend::new(self);
+
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut links = Vec::new();
+        self.plan_tree(local_path, remote_path, Path::new(""), &mut directories, &mut files, &mut links)?;
+
+        for (_, remote_directory) in &directories {
+            backend.make_dir(remote_directory)?;
+        }
+
+        for (target, remote_link_path) in &links {
+            backend.symlink(target, remote_link_path)?;
+        }
+
+        let queue = Mutex::new(files.iter());
+        let summary = Mutex::new(TransferSummary::default());
+        let failed = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let workers = (0..self.parallel_transfers.min(files.len()))
+                .map(|_| scope.spawn(|| {
+                    let backend = ScpBackend::new(self);
+
+                    // Stop taking files once any worker failed.
+                    while !failed.load(Ordering::Relaxed) {
+                        let next = queue.lock().unwrap_or_else(|err| err.into_inner()).next();
+                        let Some((local_file_path, remote_file_path, relative_path)) = next else {
+                            break;
+                        };
+
+                        let sent = self.send_tree_file(&backend, local_file_path, remote_file_path, relative_path)
+                            .and_then(|sent| Ok((sent, local_file_path.metadata()?.len())));
+                        let mut summary = summary.lock().unwrap_or_else(|err| err.into_inner());
+                        match sent {
+                            Ok((true, size)) => {
+                                summary.files.push(local_file_path.clone());
+                                summary.bytes += size;
+                            }
+                            Ok((false, _)) => summary.unchanged += 1,
+                            Err(err) => {
+                                failed.store(true, Ordering::Relaxed);
+
+                                return Err(err);
+                            }
+                        }
+                    }
+
+                    Ok(())
+                }))
+                .collect::<Vec<_>>();
+
+            workers.into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|_| Err(Error::other("An upload worker panicked!"))))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        // Like a serial upload, directories get their attributes after their contents.
+        for (local_directory, remote_directory) in directories.iter().rev() {
+            self.send_xattrs(local_directory, remote_directory);
+        }
+
+        Ok(summary.into_inner().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Sends a directory as a single tar archive and extracts it on the remote, see `transfer_mode`.
+    ///
+    /// The archive is written to a local temporary file first, and removed on both ends afterwards.
+    fn send_tree_tar(&self, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, Error> {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut links = Vec::new();
+        self.plan_tree(local_path, remote_path, Path::new(""), &mut directories, &mut files, &mut links)?;
+
+        // The root is extracted into, not archived.
+        let directories = directories.into_iter()
+            .filter_map(|(local_directory, _)| {
+                let relative_path = local_directory.strip_prefix(local_path).ok()?.to_path_buf();
+
+                (!relative_path.as_os_str().is_empty()).then_some((local_directory, relative_path))
+            })
+            .collect::<Vec<_>>();
+        let files = files.into_iter()
+            .map(|(local_file_path, _, relative_path)| (local_file_path, relative_path))
+            .collect::<Vec<_>>();
+        let links = links.into_iter()
+            .map(|(target, remote_link_path)| (target, remote_link_path.strip_prefix(remote_path).map(Path::to_path_buf).unwrap_or(remote_link_path)))
+            .collect::<Vec<_>>();
+
+        let archive_name = format!("{}.tar", temp::unique_name("upload"));
+        let local_archive_path = std::env::temp_dir().join(&archive_name);
+        let result = archive::write(&local_archive_path, &directories, &files, &links, self.remote_dir_mode as u32, self.remote_file_mode.map(|mode| mode as u32))
+            .and_then(|_| {
+                info!("Sending {} file(s) as a {} archive...", files.len(), format_size(local_archive_path.metadata()?.len()));
+
+                self.extract_remote_archive(&local_archive_path, remote_path, &archive_name)
+            });
+        let _ = std::fs::remove_file(&local_archive_path);
+        result?;
+
+        // Everything in the archive counts as sent.
+        let mut summary = TransferSummary::default();
+        for (local_file_path, _) in files {
+            summary.bytes += local_file_path.metadata()?.len();
+            summary.files.push(local_file_path);
+        }
+
+        Ok(summary)
+    }
+
+    /// Sends a local tar archive to the remote temporary directory, extracts it into the remote path and removes it.
+    fn extract_remote_archive(&self, local_archive_path: &Path, remote_path: &Path, archive_name: &str) -> Result<(), Error> {
+        self.ensure_remote_directory(remote_path)?;
+
+        let remote_archive_path = temp::remote_temp_path("upload").with_file_name(archive_name);
+        self.upload_file(local_archive_path, &remote_archive_path)?;
+
+        let archive = shell::quote(&remote_archive_path.to_string_lossy());
+        let command = format!(
+            "tar -xf {0} -C {1}; sbs_status=$?; rm -f {0}; exit $sbs_status",
+            archive,
+            shell::quote_path(&remote_path.to_string_lossy()),
+        );
+
+        match self.run_remote_command(&command)? {
+            (0, _) => Ok(()),
+            (_, output) => Err(Error::other(format!("Failed to extract the upload into '{}' on the remote: {}", remote_path.display(), output.trim()))),
+        }
+    }
+
+    /// Prints the files a directory upload would send, see `dry_run`.
+    fn print_planned_upload(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut links = Vec::new();
+        self.plan_tree(local_path, remote_path, Path::new(""), &mut directories, &mut files, &mut links)?;
+
+        for (local_file_path, remote_file_path, _) in &files {
+            println!("Would send '{}' to '{}'.", local_file_path.display(), remote_file_path.display());
+        }
+
+        for (target, remote_link_path) in &links {
+            println!("Would link '{}' to '{}'.", remote_link_path.display(), target.display());
+        }
+
+        Ok(())
+    }
+
+    /// Lists the directories, files and re-created symlinks of a tree that aren't ignored, parents before their contents.
+    fn plan_tree(
+        &self,
+        local_path: &Path,
+        remote_path: &Path,
+        relative_path: &Path,
+        directories: &mut Vec<(PathBuf, PathBuf)>,
+        files: &mut Vec<(PathBuf, PathBuf, PathBuf)>,
+        links: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<(), Error> {
+        // Make sure the local path exists.
+        if !local_path.exists() {
+            return Err(Error::new(ErrorKind::NotFound, format!("The local path '{}' does not exist!", local_path.display())));
+        }
+
+        directories.push((local_path.to_path_buf(), remote_path.to_path_buf()));
+
+        let mut entries = local_path.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+        if self.deterministic_order {
+            entries.sort_by_key(|entry| entry.file_name());
+        }
+
+        for entry in entries {
+            let path = entry.path();
+            let relative_path = relative_path.join(entry.file_name());
+
+            if self.ignore.is_ignored(&relative_path, path.is_dir()) {
+                continue;
+            }
+
+            match self.classify_local_entry(&path, &relative_path)? {
+                LocalEntry::Directory => self.plan_tree(&path, &remote_path.join(entry.file_name()), &relative_path, directories, files, links)?,
+                LocalEntry::File => files.push((path, remote_path.join(entry.file_name()), relative_path)),
+                LocalEntry::Symlink(target) => links.push((target, remote_path.join(entry.file_name()))),
+                LocalEntry::Skip => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the remote directory tree matching a local directory, without sending any files.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let local_path = Path::new("/path/to/local_dir");
+    /// let remote_path = Path::new("/path/to/remote_dir");
+    ///
+    /// sbs.mirror_directory_structure(&local_path, &remote_path).unwrap();
+    /// ```
+    pub fn mirror_directory_structure(&self, local_path: &Path, remote_path: &Path) -> Result<(), SbsError> {
+        if self.dry_run {
+            println!("Would create the directories of '{}' in '{}'.", local_path.display(), remote_path.display());
+
+            return Ok(());
+        }
+
+        self.mirror_tree(local_path, remote_path, Path::new("")).map_err(SbsError::Transfer)
+    }
+
+    /// Creates the remote directory tree, see `mirror_directory_structure`.
+    fn mirror_tree(&self, local_path: &Path, remote_path: &Path, relative_path: &Path) -> Result<(), Error> {
+        // Make sure the local path exists.
+        if !local_path.is_dir() {
+            return Err(Error::new(ErrorKind::NotFound, format!("The local path '{}' is not a directory!", local_path.display())));
+        }
+
+        self.ensure_remote_directory(remote_path)?;
+
+        let mut entries = local_path.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+        if self.deterministic_order {
+            entries.sort_by_key(|entry| entry.file_name());
+        }
+
+        for entry in entries {
+            let path = entry.path();
+            let relative_path = relative_path.join(entry.file_name());
+
+            if path.is_dir() && !self.ignore.is_ignored(&relative_path, true)
+                && matches!(self.classify_local_entry(&path, &relative_path)?, LocalEntry::Directory) {
+                self.mirror_tree(&path, &remote_path.join(entry.file_name()), &relative_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a single file via SCP.
+    ///
+    /// The remote parent directory has to exist already.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path of the file.
+    /// * `remote_path` - The remote path of the file.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.send_file(Path::new("/path/to/local_file"), Path::new("/path/to/remote_file")).unwrap();
+    /// ```
+    pub fn send_file(&self, local_path: &Path, remote_path: &Path) -> Result<(), SbsError> {
+        if self.dry_run {
+            println!("Would send '{}' to '{}'.", local_path.display(), remote_path.display());
+
+            return Ok(());
+        }
+
+        self.upload_file(local_path, remote_path).map_err(SbsError::Transfer)
+    }
+
+    /// Sends a single file, see `send_file`.
+    fn upload_file(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        let metadata = local_path.metadata()?;
+        // Files that don't shrink when compressed are sent as-is after all.
+        let compress = self.file_compression.as_ref().is_some_and(|compression| compression.applies_to(local_path, metadata.len()));
+        if compress && self.send_file_compressed(local_path, remote_path, &metadata)? {
+            self.send_xattrs(local_path, remote_path);
+
+            return Ok(());
+        }
+
+        let mut remote_file = self.connection.scp_send(
+            remote_path,
+            self.file_mode(&metadata),
+            metadata.len(),
+            self.upload_times(&metadata),
+        )?;
+
+        let mut local_file = File::open(local_path)?;
+        Self::copy_exact(&mut local_file, &mut remote_file, metadata.len(), local_path)?;
+
+        remote_file.flush()?;
+
+        self.send_xattrs(local_path, remote_path);
+
+        Ok(())
+    }
+
+    /// Sends a file gzipped and decompresses it on the remote, returning `false` if compressing it doesn't save anything.
+    ///
+    /// The compressed file is staged next to its final name, so the remote never sees a half-decompressed file.
+    fn send_file_compressed(&self, local_path: &Path, remote_path: &Path, metadata: &Metadata) -> Result<bool, Error> {
+        let data = std::fs::read(local_path)?;
+        let compressed = compression::gzip(&data)?;
+        if compressed.len() >= data.len() {
+            return Ok(false);
+        }
+
+        let staging_path = temp::staging_path(remote_path);
+        let compressed_path = PathBuf::from(format!("{}.gz", staging_path.display()));
+        self.send_bytes(&compressed, &compressed_path, self.file_mode(metadata))?;
+
+        // `gzip -d` keeps the mode of the compressed file.
+        let mut command = format!(
+            "gzip -d -f {} && mv -f {} {}",
+            shell::quote(&compressed_path.to_string_lossy()),
+            shell::quote(&staging_path.to_string_lossy()),
+            shell::quote(&remote_path.to_string_lossy()),
+        );
+        if let Some((mtime, _)) = self.upload_times(metadata) {
+            command.push_str(&format!(" && touch -m -d @{} {}", mtime, shell::quote(&remote_path.to_string_lossy())));
+        }
+        match self.run_remote_command(&command)? {
+            (0, _) => Ok(true),
+            (_, output) => Err(Error::other(format!("Failed to decompress '{}' on the remote: {}", remote_path.display(), output.trim()))),
+        }
+    }
+
+    /// Sends only the files that changed since a git reference, and removes the remote copies of deleted files.
+    ///
+    /// The removals are previewed and have to be confirmed first, unless `assume_yes` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local project root, inside a git repository.
+    /// * `remote_path` - The remote project root.
+    /// * `reference` - The git reference to compare against.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.send_changed_since(Path::new("/path/to/local_dir"), Path::new("/path/to/remote_dir"), "origin/main").unwrap();
+    /// ```
+    pub fn send_changed_since(&self, local_path: &Path, remote_path: &Path, reference: &str) -> Result<(), SbsError> {
+        self.send_changes(local_path, remote_path, reference).map_err(SbsError::Transfer)
+    }
+
+    /// Sends the changed files, see `send_changed_since`.
+    fn send_changes(&self, local_path: &Path, remote_path: &Path, reference: &str) -> Result<(), Error> {
+        let mut changes = git::changed_since(local_path, reference)?;
+        if self.deterministic_order {
+            changes.changed.sort();
+            changes.deleted.sort();
+        }
+
+        // Asking git is local, so a dry run can still list the changes.
+        if self.dry_run {
+            for relative_path in changes.changed.iter().filter(|relative_path| local_path.join(relative_path).is_file()) {
+                println!("Would send '{}' to '{}'.", local_path.join(relative_path).display(), remote_path.join(relative_path).display());
+            }
+            for relative_path in &changes.deleted {
+                println!("Would delete '{}', if it was uploaded.", remote_path.join(relative_path).display());
+            }
+
+            return Ok(());
+        }
+
+        for relative_path in &changes.changed {
+            let local_file_path = local_path.join(relative_path);
+            let remote_file_path = remote_path.join(relative_path);
+
+            // Directories and files that disappeared since git was asked are skipped.
+            if !local_file_path.is_file() {
+                continue;
+            }
+
+            if let Some(parent) = remote_file_path.parent() {
+                self.ensure_remote_directory(parent)?;
+            }
+
+            debug!("Sending changed file '{}'...", relative_path.display());
+            self.send_file(&local_file_path, &remote_file_path)?;
+        }
+
+        if !changes.deleted.is_empty() {
+            let sftp_session = self.connection.sftp_handle()?;
+
+            // The files may never have been uploaded in the first place.
+            let mut preview = RemovalPreview {
+                path: remote_path.to_path_buf(),
+                whole_directory: false,
+                file_count: 0,
+                directory_count: 0,
+                total_size: 0,
+                sample_paths: Vec::new(),
+            };
+            let mut removals = Vec::new();
+            for relative_path in &changes.deleted {
+                let Ok(stat) = sftp_session.call(|sftp| sftp.lstat(&remote_path.join(relative_path))) else {
+                    continue;
+                };
+
+                preview.file_count += 1;
+                preview.total_size += stat.size.unwrap_or(0);
+                if preview.sample_paths.len() < guard::SAMPLE_PATH_COUNT {
+                    preview.sample_paths.push(relative_path.clone());
+                }
+                removals.push(relative_path);
+            }
+
+            if !removals.is_empty() {
+                guard::confirm_removal(&preview, self.assume_yes)?;
+            }
+
+            for relative_path in removals {
+                debug!("Removing deleted file '{}'...", relative_path.display());
+                sftp_session.call(|sftp| sftp.unlink(&remote_path.join(relative_path)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies exactly the number of bytes declared to SCP, failing if the source has more or fewer.
+    ///
+    /// SCP announces the file length up front, so a file that changed after it was stat'ed
+    /// (or a virtual file that reports a bogus length) would otherwise end up corrupted on the remote.
+    fn copy_exact(reader: &mut impl Read, writer: &mut impl Write, expected_len: u64, path: &Path) -> Result<(), Error> {
+        let copied = io::copy(&mut reader.take(expected_len), writer)?;
+
+        // Anything left to read means the file grew past its declared length.
+        let mut extra = [0u8; 1];
+        let grew = reader.read(&mut extra)? > 0;
+
+        if copied != expected_len || grew {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "The file '{}' changed during the transfer (expected {} bytes, read {}{})!",
+                path.display(),
+                expected_len,
+                copied,
+                if grew { " and more" } else { "" },
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Gets the modification and access time an uploaded file keeps, only done for incremental uploads.
+    ///
+    /// Other uploads get the current time, so build tools on the server see the files as changed.
+    fn upload_times(&self, metadata: &Metadata) -> Option<(u64, u64)> {
+        if !self.incremental_upload {
+            return None;
+        }
+
+        let seconds = |time: io::Result<SystemTime>| time.ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+        let mtime = seconds(metadata.modified())?;
+
+        Some((mtime, seconds(metadata.accessed()).unwrap_or(mtime)))
+    }
+
+    /// Picks the mode of an uploaded file: `remote_file_mode` if it's set, otherwise that of the local file.
+    pub(crate) fn file_mode(&self, metadata: &Metadata) -> i32 {
+        self.remote_file_mode.unwrap_or_else(|| Self::upload_mode(metadata))
+    }
+
+    /// Picks the mode of an uploaded file: the permission bits of the local file.
+    #[cfg(unix)]
+    pub(crate) fn upload_mode(metadata: &Metadata) -> i32 {
+        use std::os::unix::fs::PermissionsExt;
+
+        (metadata.permissions().mode() & 0o777) as i32
+    }
+
+    /// Picks the mode of an uploaded file: `0o755`, or `0o555` for a read-only file.
+    ///
+    /// Platforms without an executable bit always get it, so nothing that might be a script loses it.
+    #[cfg(not(unix))]
+    pub(crate) fn upload_mode(metadata: &Metadata) -> i32 {
+        if metadata.permissions().readonly() {
+            0o555
+        } else {
+            0o755
+        }
+    }
+
+    /// Gives a downloaded file the permission bits of the remote file, if the server reported them.
+    #[cfg(unix)]
+    fn apply_remote_mode(local_path: &Path, remote_stat: &FileStat) -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        match remote_stat.perm {
+            Some(perm) => std::fs::set_permissions(local_path, std::fs::Permissions::from_mode(perm & 0o777)),
+            None => Ok(()),
+        }
+    }
+
+    /// Gives a downloaded file the permission bits of the remote file, which only leaves it read-only or not here.
+    #[cfg(not(unix))]
+    fn apply_remote_mode(local_path: &Path, remote_stat: &FileStat) -> Result<(), Error> {
+        let Some(perm) = remote_stat.perm else {
+            return Ok(());
+        };
+
+        let mut permissions = local_path.metadata()?.permissions();
+        permissions.set_readonly(perm & 0o200 == 0);
+
+        std::fs::set_permissions(local_path, permissions)
+    }
+
+    /// Sends an in-memory buffer to a remote file via SCP.
+    ///
+    /// The parent directories of the remote file are created as needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The file contents.
+    /// * `remote_path` - The remote path of the file.
+    /// * `mode` - The permissions of the remote file.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.send_bytes(b"answer = 42\n", Path::new("/path/to/remote_dir/generated.toml"), 0o644).unwrap();
+    /// ```
+    pub fn send_bytes(&self, data: &[u8], remote_path: &Path, mode: i32) -> Result<(), SbsError> {
+        if self.dry_run {
+            println!("Would write {} to '{}'.", format_size(data.len() as u64), remote_path.display());
+
+            return Ok(());
+        }
+
+        self.upload_bytes(data, remote_path, mode).map_err(SbsError::Transfer)
+    }
+
+    /// Sends an in-memory buffer, see `send_bytes`.
+    fn upload_bytes(&self, data: &[u8], remote_path: &Path, mode: i32) -> Result<(), Error> {
+        if let Some(parent) = remote_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            self.ensure_remote_directory(parent)?;
+        }
+
+        let mut remote_file = self.connection.scp_send(remote_path, mode, data.len() as u64, None)?;
+        remote_file.write_all(data)?;
+
+        remote_file.send_eof()?;
+        remote_file.wait_eof()?;
+        remote_file.close()?;
+        remote_file.wait_close()?;
+
+        Ok(())
+    }
+
+    /// Makes sure a remote directory exists, creating it and its parents if necessary.
+    pub(crate) fn ensure_remote_directory(&self, remote_path: &Path) -> Result<(), Error> {
+        match self.connection.sftp_if_available() {
+            Some(sftp_session) => match sftp_session.stat(remote_path) {
+                Ok(stat) => {
+                    if !stat.is_dir() {
+                        return Err(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' is not a directory!", remote_path.display())));
+                    }
+                }
+                Err(_) => {
+                    info!("The remote path '{}' does not exist, creating it...", remote_path.display());
+
+                    Self::make_dirs(sftp_session.as_ref(), remote_path, self.remote_dir_mode)?;
+                }
+            },
+            None => self.make_dirs_via_shell(remote_path)?,
+        }
+
+        Ok(())
+    }
+
+    /// Creates a remote directory and its parents with `mkdir -p`, for servers without SFTP.
+    ///
+    /// `mkdir -p -m` would only give the last directory the mode, so the parents get it through the umask instead.
+    fn make_dirs_via_shell(&self, remote_path: &Path) -> Result<(), Error> {
+        let path = shell::quote(&remote_path.to_string_lossy());
+        let command = format!(
+            "if [ -e {0} ] && [ ! -d {0} ]; then exit 2; fi; umask {1:03o} && mkdir -p {0}",
+            path,
+            !self.remote_dir_mode & 0o777,
+        );
+
+        match self.run_remote_command(&command)? {
+            (0, _) => Ok(()),
+            (2, _) => Err(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' is not a directory!", remote_path.display()))),
+            (_, output) => Err(Error::other(format!("Failed to create the remote path '{}': {}", remote_path.display(), output.trim()))),
+        }
+    }
+
+    /// Creates a remote directory and its parents with the given mode, failing if one of them is a file or can't be created.
+    fn make_dirs<C: DirectoryCreator>(creator: &C, remote_path: &Path, mode: i32) -> Result<(), SbsError> {
+        let mut path = PathBuf::new();
+
+        for component in remote_path.components() {
+            path.push(component.as_os_str());
+
+            match creator.is_dir(&path) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(SbsError::Transfer(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' is not a directory!", path.display()))));
+                }
+                Err(_) => {
+                    Self::create_dir(creator, &path, mode).map_err(|err| {
+                        let err = Error::from(err);
+
+                        SbsError::Transfer(Error::new(err.kind(), format!("Failed to create the remote directory '{}': {}", path.display(), err)))
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a single remote directory, treating one that already exists as success.
+    ///
+    /// Another process (or transfer worker) may create the directory between our `stat` and `mkdir`,
+    /// in which case the server reports a generic failure. The path is then checked again,
+    /// and the error is only returned if it still isn't a directory.
+    fn create_dir<C: DirectoryCreator>(creator: &C, path: &Path, mode: i32) -> Result<(), ssh2::Error> {
+        match creator.mkdir(path, mode) {
+            Ok(()) => Ok(()),
+            Err(err) if matches!(err.code(), ErrorCode::SFTP(SFTP_FAILURE) | ErrorCode::SFTP(SFTP_FILE_ALREADY_EXISTS)) => {
+                match creator.is_dir(path) {
+                    Ok(true) => Ok(()),
+                    _ => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Receives a directory recursively via SCP, returning what was received.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let local_path = Path::new("/path/to/local_dir");
+    /// let remote_path = Path::new("/path/to/remote_dir");
+    ///
+    /// let summary = sbs.receive_directory(&local_path, &remote_path).unwrap();
+    ///
+    /// for file in &summary.files {
+    ///     println!("Received '{}'.", file.display());
+    /// }
+    /// ```
+    pub fn receive_directory(&self, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, SbsError> {
+        self.receive_directory_with(&ScpBackend::new(self), local_path, remote_path)
+    }
+
+    /// Receives a directory recursively with the given transfer backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The transfer backend.
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let backend = SftpBackend::new(sbs.connection.sftp_handle().unwrap());
+    ///
+    /// sbs.receive_directory_with(&backend, Path::new("/path/to/local_dir"), Path::new("/path/to/remote_dir")).unwrap();
+    /// ```
+    pub fn receive_directory_with<B: TransferBackend>(&self, backend: &B, local_path: &Path, remote_path: &Path) -> Result<TransferSummary, SbsError> {
+        // Listing the remote files would already touch the server.
+        if self.dry_run {
+            println!("Would receive '{}' into '{}'.", remote_path.display(), local_path.display());
+
+            return Ok(TransferSummary::default());
+        }
+
+        self.receive_tree(backend, local_path, remote_path, None, ProgressTracker::none()).map_err(SbsError::Transfer)
+    }
+
+    /// Receives a directory recursively via SCP, reporting the progress after every file.
+    ///
+    /// The remote directory is listed once up front, to count the total bytes.
+    /// Files skipped by an incremental download count as transferred.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    /// * `on_progress` - Called with the progress after every file.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.receive_directory_with_progress(Path::new("/path/to/local_dir"), Path::new("/path/to/remote_dir"), |progress| {
+    ///     eprintln!("{} of {:?} bytes, '{}'", progress.bytes_done, progress.total_bytes, progress.current_file.display());
+    /// }).unwrap();
+    /// ```
+    pub fn receive_directory_with_progress(&self, local_path: &Path, remote_path: &Path, mut on_progress: impl FnMut(TransferProgress)) -> Result<TransferSummary, SbsError> {
+        let backend = ScpBackend::new(self);
+        // Sizing up the remote files would already touch the server.
+        if self.dry_run {
+            return self.receive_directory_with(&backend, local_path, remote_path);
+        }
+
+        let total_bytes = transfer::remote_tree_size(&backend, remote_path).ok();
+        let progress = ProgressTracker::new(&mut on_progress, total_bytes);
+
+        self.receive_tree(&backend, local_path, remote_path, None, progress).map_err(SbsError::Transfer)
+    }
+
+    /// Receives only the files of a remote directory matching glob patterns, via SCP.
+    ///
+    /// The patterns work like `.gitignore` patterns, relative to the remote directory, and a matching directory is received whole.
+    /// Every directory is still walked, but local directories are only created for files that are received.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    /// * `patterns` - The patterns of the files to receive.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let patterns = vec!["/release/myapp".to_string(), "*.so".to_string()];
+    ///
+    /// sbs.receive_matching(Path::new("/path/to/local_dir"), Path::new("/path/to/target"), &patterns).unwrap();
+    /// ```
+    pub fn receive_matching(&self, local_path: &Path, remote_path: &Path, patterns: &[String]) -> Result<TransferSummary, SbsError> {
+        self.receive_matching_with(&ScpBackend::new(self), local_path, remote_path, patterns)
+    }
+
+    /// Receives only the files of a remote directory matching glob patterns with the given transfer backend, see `receive_matching`.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The transfer backend.
+    /// * `local_path` - The local path.
+    /// * `remote_path` - The remote path.
+    /// * `patterns` - The patterns of the files to receive.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let backend = SftpBackend::new(sbs.connection.sftp_handle().unwrap());
+    ///
+    /// sbs.receive_matching_with(&backend, Path::new("/path/to/local_dir"), Path::new("/path/to/target"), &["*.so".to_string()]).unwrap();
+    /// ```
+    pub fn receive_matching_with<B: TransferBackend>(&self, backend: &B, local_path: &Path, remote_path: &Path, patterns: &[String]) -> Result<TransferSummary, SbsError> {
+        if self.dry_run {
+            println!("Would receive the files of '{}' matching {} into '{}'.", remote_path.display(), patterns.join(", "), local_path.display());
+
+            return Ok(TransferSummary::default());
+        }
+
+        let selection = IgnoreRules::parse(patterns.iter().map(String::as_str));
+
+        self.receive_tree(backend, local_path, remote_path, Some(&selection), ProgressTracker::none()).map_err(SbsError::Transfer)
+    }
+
+    /// Receives a directory recursively, see `receive_directory_with`, and returns what was received.
+    ///
+    /// With a `selection`, only the files matching it are received, see `receive_matching`.
+    /// A missing remote directory is reported before anything is created locally, since it usually means the build failed.
+    fn receive_tree<B: TransferBackend>(
+        &self,
+        backend: &B,
+        local_path: &Path,
+        remote_path: &Path,
+        selection: Option<&IgnoreRules>,
+        progress: ProgressTracker,
+    ) -> Result<TransferSummary, Error> {
+        match backend.stat(remote_path)? {
+            Some(stat) if stat.is_dir() => {}
+            Some(_) => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' isn't a directory!", remote_path.display())));
+            }
+            None => {
+                return Err(Error::new(ErrorKind::NotFound, format!("The remote directory '{}' wasn't found, did the build succeed?", remote_path.display())));
+            }
+        }
+
+        let mut progress = progress;
+        self.receive_subtree(backend, local_path, remote_path, selection, &mut Vec::new(), &mut progress)?;
+
+        Ok(progress.into_summary())
+    }
+
+    /// Receives a directory recursively, see `receive_tree`.
+    ///
+    /// The remote directories being received are kept in `ancestors`, so a followed symlink back into one of them can be told apart.
+    fn receive_subtree<B: TransferBackend>(
+        &self,
+        backend: &B,
+        local_path: &Path,
+        remote_path: &Path,
+        selection: Option<&IgnoreRules>,
+        ancestors: &mut Vec<PathBuf>,
+        progress: &mut ProgressTracker,
+    ) -> Result<(), Error> {
+        ancestors.push(remote_path.to_path_buf());
+
+        // Create the local directory, or leave it to the first selected file.
+        if selection.is_none() {
+            std::fs::create_dir_all(local_path)?;
+        }
+
+        // Retrieve the directory contents.
+        let mut remote_files = backend.read_dir(remote_path)?;
+        if self.deterministic_order {
+            remote_files.sort_by(|(a, _), (b, _)| a.file_name().cmp(&b.file_name()));
+        }
+
+        // Iterate over the remote files.
+        for (path_buf, file_stat) in remote_files {
+            let remote_filename = match path_buf.file_name() {
+                Some(filename) => filename,
+                None => continue,
+            };
+
+            let remote_file_path = remote_path.join(remote_filename);
+            let local_file_path = local_path.join(remote_filename);
+            let relative_path = ancestors.first()
+                .and_then(|root| remote_file_path.strip_prefix(root).ok())
+                .unwrap_or(&remote_file_path)
+                .to_path_buf();
+
+            let file_stat = if file_stat.file_type().is_symlink() {
+                match self.symlinks {
+                    SymlinkPolicy::Skip => {
+                        debug!("Skipping the symlink '{}'.", remote_file_path.display());
+
+                        continue;
+                    }
+                    SymlinkPolicy::Recreate => {
+                        if selection.is_some_and(|selection| !selection.matches(&relative_path, false)) {
+                            continue;
+                        }
+
+                        std::fs::create_dir_all(local_path)?;
+                        Self::recreate_local_symlink(&backend.read_link(&remote_file_path)?, &local_file_path, &remote_file_path)?;
+
+                        continue;
+                    }
+                    SymlinkPolicy::Follow => match backend.stat(&remote_file_path)? {
+                        None => {
+                            warn!("Skipping the symlink '{}', since it points at nothing.", remote_file_path.display());
+
+                            continue;
+                        }
+                        Some(target_stat) if target_stat.is_dir() && self.remote_link_loops(backend, &remote_file_path, ancestors)? => {
+                            warn!("Skipping the symlink '{}', since it points at a directory containing it.", remote_file_path.display());
+
+                            continue;
+                        }
+                        Some(target_stat) => target_stat,
+                    },
+                }
+            } else {
+                file_stat
+            };
+
+            if file_stat.is_dir() {
+                // A selected directory is received whole.
+                let selection = selection.filter(|selection| !selection.matches(&relative_path, true));
+
+                // Receive the subdirectory recursively.
+                self.receive_subtree(backend, &local_file_path, &remote_file_path, selection, ancestors, progress)?;
+            } else {
+                if selection.is_some_and(|selection| !selection.matches(&relative_path, false)) {
+                    continue;
+                }
+
+                // Skip files that haven't changed since the last download.
+                if self.incremental_download && Self::is_unchanged(&local_file_path, &file_stat) {
+                    debug!("Skipping the unchanged file '{}'.", remote_file_path.display());
+                    progress.file_unchanged(&local_file_path, file_stat.size.unwrap_or(0));
+
+                    continue;
+                }
+
+                // Receive the file.
+                debug!("Receiving '{}'...", remote_file_path.display());
+                std::fs::create_dir_all(local_path)?;
+                backend.receive_file(&remote_file_path, &local_file_path, file_stat.size)?;
+
+                // Carry over the remote modification time, so the next incremental download can compare against it.
+                if let (true, Some(mtime)) = (self.incremental_download, file_stat.mtime) {
+                    File::options().write(true).open(&local_file_path)?
+                        .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+                }
+
+                // Applied last, since a read-only mode would keep the modification time from being set.
+                Self::apply_remote_mode(&local_file_path, &file_stat)?;
+
+                progress.file_done(&local_file_path, file_stat.size.unwrap_or(0));
+
+                self.receive_xattrs(&local_file_path, &remote_file_path);
+            }
+        }
+
+        // Nothing might have been selected in the directory.
+        if local_path.exists() {
+            self.receive_xattrs(local_path, remote_path);
+        }
+        ancestors.pop();
+
+        Ok(())
+    }
+
+    /// Checks whether a remote symlink to a directory points at a directory being received, or one above them.
+    ///
+    /// The paths are only resolved here, since most trees contain no links to follow.
+    fn remote_link_loops<B: TransferBackend>(&self, backend: &B, remote_link_path: &Path, ancestors: &[PathBuf]) -> Result<bool, Error> {
+        let target = backend.real_path(remote_link_path)?;
+
+        let above = ancestors.first().map(|root| root.ancestors().skip(1)).into_iter().flatten();
+        for ancestor in ancestors.iter().map(PathBuf::as_path).chain(above) {
+            if !ancestor.as_os_str().is_empty() && backend.real_path(ancestor)? == target {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Re-creates a remote symlink locally, replacing a file or symlink that's in the way.
+    #[cfg(unix)]
+    fn recreate_local_symlink(target: &Path, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        debug!("Linking '{}' to '{}'...", remote_path.display(), target.display());
+
+        if local_path.symlink_metadata().is_ok_and(|metadata| !metadata.is_dir()) {
+            std::fs::remove_file(local_path)?;
+        }
+
+        std::os::unix::fs::symlink(target, local_path)
+    }
+
+    /// Re-creates a remote symlink locally, see the unix version.
+    #[cfg(not(unix))]
+    fn recreate_local_symlink(_target: &Path, _local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        warn!("Skipping the symlink '{}', since symlinks can't be re-created on this platform.", remote_path.display());
+
+        Ok(())
+    }
+
+    /// Checks whether a local file has the same size and modification time (in whole seconds) as a remote file.
+    fn is_unchanged(local_path: &Path, remote_stat: &FileStat) -> bool {
+        let metadata = match local_path.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+
+        let local_mtime = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+
+        remote_stat.size == Some(metadata.len()) && remote_stat.mtime.is_some() && remote_stat.mtime == local_mtime
+    }
+
+    /// Uploads one directory over this connection while downloading another over a second one.
+    ///
+    /// This overlaps both transfers, e.g. to fetch the artifacts of a previous build while uploading new source.
+    /// A session only does one thing at a time, so the download needs a connection of its own to really run alongside the upload.
+    /// The two transfers must not touch each other, so neither the local nor the remote paths may overlap.
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - The SBS instance to download with, connected to the same server.
+    /// * `send_local_path` - The local path to upload.
+    /// * `send_remote_path` - The remote path to upload to.
+    /// * `receive_local_path` - The local path to download to.
+    /// * `receive_remote_path` - The remote path to download.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    /// let receiver = Sbs::new(another_session); // Another SBS instance, connected to the same server.
+    ///
+    /// sbs.send_and_receive_directories(
+    ///     &receiver,
+    ///     Path::new("/path/to/local_src"),
+    ///     Path::new("/path/to/remote_src"),
+    ///     Path::new("/path/to/local_artifacts"),
+    ///     Path::new("/path/to/remote_artifacts"),
+    /// ).unwrap();
+    /// ```
+    pub fn send_and_receive_directories(&self, receiver: &Sbs, send_local_path: &Path, send_remote_path: &Path, receive_local_path: &Path, receive_remote_path: &Path) -> Result<(), SbsError> {
+        if paths_overlap(send_local_path, receive_local_path) {
+            return Err(SbsError::Transfer(Error::new(ErrorKind::InvalidInput, format!("The local paths '{}' and '{}' overlap!", send_local_path.display(), receive_local_path.display()))));
+        }
+
+        if paths_overlap(send_remote_path, receive_remote_path) {
+            return Err(SbsError::Transfer(Error::new(ErrorKind::InvalidInput, format!("The remote paths '{}' and '{}' overlap!", send_remote_path.display(), receive_remote_path.display()))));
+        }
+
+        thread::scope(|scope| {
+            let upload = scope.spawn(|| self.send_directory(send_local_path, send_remote_path));
+            let download = receiver.receive_directory(receive_local_path, receive_remote_path);
+
+            let upload = upload.join()
+                .unwrap_or_else(|_| Err(SbsError::Transfer(Error::other("The upload thread panicked!"))));
+
+            upload.and(download).map(|_| ())
+        })
+    }
+
+    /// Creates (or truncates) a local file, creating its parent directories first.
+    pub(crate) fn create_local_file(path: &Path) -> Result<File, Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // A read-only file from an earlier download can't be truncated, but it can be replaced.
+        match File::create(path) {
+            Err(err) if err.kind() == ErrorKind::PermissionDenied && path.is_file() => {
+                std::fs::remove_file(path)?;
+
+                File::create(path)
+            }
+            result => result,
+        }
+    }
+
+    /// Runs a single shell command on the SSH server and returns its exit code and merged output.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let (exit_code, output) = sbs.run_remote_command("uname -a").unwrap();
+    /// ```
+    pub fn run_remote_command(&self, command: &str) -> Result<(i32, String), SbsError> {
+        if self.dry_run {
+            println!("Would run `{}`.", command);
+
+            return Ok((0, String::new()));
+        }
+
+        let mut channel = self.connection.channel()?;
+        channel.handle_extended_data(ExtendedData::Merge)?;
+        channel.exec(command)?;
+
+        let mut output = Vec::new();
+        channel.read_to_end(&mut output)?;
+
+        channel.wait_close()?;
+
+        Ok((channel.exit_status()?, encoding::decode(&output, self.output_encoding)))
+    }
+
+    /// Runs a command on the SSH server and uses its output as a remote path.
+    ///
+    /// Only stdout is used. The command has to succeed and print a non-empty, absolute path.
+    /// A dry run can't know the path, so it fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let remote_project_root = sbs.resolve_remote_path("echo /builds/$(whoami)").unwrap();
+    /// ```
+    pub fn resolve_remote_path(&self, command: &str) -> Result<String, SbsError> {
+        if self.dry_run {
+            return Err(SbsError::Io(Error::new(ErrorKind::Unsupported, format!("A dry run can't resolve a remote path with '{}'!", command))));
+        }
+
+        let mut channel = self.connection.channel()?;
+        channel.handle_extended_data(ExtendedData::Ignore)?;
+        channel.exec(command)?;
+
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+
+        channel.wait_close()?;
+
+        let exit_code = channel.exit_status()?;
+        if exit_code != 0 {
+            return Err(SbsError::CommandFailed { command: Some(command.to_string()), description: None, exit_code, stderr: String::new() });
+        }
+
+        let path = output.trim();
+        if path.is_empty() || !path.starts_with('/') {
+            return Err(SbsError::Io(Error::new(ErrorKind::InvalidData, format!("The command '{}' returned '{}', which isn't an absolute path!", command, path))));
+        }
+
+        Ok(path.to_string())
+    }
+
+    /// Copies the extended attributes of a local file to its remote counterpart, if enabled.
+    ///
+    /// Failures are reported as warnings, since not every server supports extended attributes.
+    fn send_xattrs(&self, local_path: &Path, remote_path: &Path) {
+        if !self.preserve_xattrs || self.xattrs_unsupported.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let attributes = match xattrs::read_local(local_path) {
+            Ok(attributes) => attributes,
+            Err(err) => {
+                warn!("Failed to read extended attributes of '{}': {}", local_path.display(), err);
+
+                return;
+            }
+        };
+
+        if attributes.is_empty() {
+            return;
+        }
+
+        match self.run_remote_command(&xattrs::set_command(remote_path, &attributes)) {
+            Ok((0, _)) => {}
+            Ok((127, _)) => {
+                warn!("'setfattr' is not available on the remote, extended attributes will not be preserved!");
+
+                self.xattrs_unsupported.store(true, Ordering::Relaxed);
+            }
+            Ok((_, output)) => warn!("Failed to set extended attributes on '{}': {}", remote_path.display(), output.trim()),
+            Err(err) => warn!("Failed to set extended attributes on '{}': {}", remote_path.display(), err),
+        }
+    }
+
+    /// Copies the extended attributes of a remote file to its local counterpart, if enabled.
+    ///
+    /// Failures are reported as warnings, since not every server supports extended attributes.
+    fn receive_xattrs(&self, local_path: &Path, remote_path: &Path) {
+        if !self.preserve_xattrs || self.xattrs_unsupported.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let attributes = match self.run_remote_command(&xattrs::get_command(remote_path)) {
+            Ok((0, output)) => xattrs::parse_get_output(&output),
+            Ok((127, _)) => {
+                warn!("'getfattr' is not available on the remote, extended attributes will not be preserved!");
+
+                self.xattrs_unsupported.store(true, Ordering::Relaxed);
+
+                return;
+            }
+            Ok((_, output)) => {
+                warn!("Failed to read extended attributes of '{}': {}", remote_path.display(), output.trim());
+
+                return;
+            }
+            Err(err) => {
+                warn!("Failed to read extended attributes of '{}': {}", remote_path.display(), err);
+
+                return;
+            }
+        };
+
+        if let Err(err) = xattrs::write_local(local_path, &attributes) {
+            warn!("Failed to set extended attributes on '{}': {}", local_path.display(), err);
+        }
+    }
+
+    /// Walks a remote directory recursively via SFTP.
+    ///
+    /// Every entry below the remote path is returned, with directories listed before their contents.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// for (path, stat) in sbs.walk_remote_directory(Path::new("/path/to/remote_dir")).unwrap() {
+    ///     println!("{} ({} bytes)", path.display(), stat.size.unwrap_or(0));
+    /// }
+    /// ```
+    pub fn walk_remote_directory(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, SbsError> {
+        self.walk_remote_tree(remote_path).map_err(SbsError::Transfer)
+    }
+
+    /// Lists a remote directory recursively, see `walk_remote_directory`.
+    fn walk_remote_tree(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
+        let sftp_session = self.connection.sftp_handle()?;
+
+        let mut entries = Vec::new();
+        self.walk_remote(&sftp_session, remote_path, &mut entries)?;
+
+        Ok(entries)
+    }
+
+    fn walk_remote(&self, sftp_session: &SftpHandle, remote_path: &Path, entries: &mut Vec<(PathBuf, FileStat)>) -> Result<(), Error> {
+        for (path_buf, file_stat) in self.read_remote_dir(sftp_session, remote_path)? {
+            let remote_filename = match path_buf.file_name() {
+                Some(filename) => filename,
+                None => continue,
+            };
+
+            let remote_file_path = remote_path.join(remote_filename);
+            let is_dir = file_stat.is_dir();
+
+            entries.push((remote_file_path.clone(), file_stat));
+
+            if is_dir {
+                self.walk_remote(sftp_session, &remote_file_path, entries)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists a remote directory via SFTP, sorted by name if `deterministic_order` is set.
+    fn read_remote_dir(&self, sftp_session: &SftpHandle, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
+        let mut entries = sftp_session.call(|sftp| sftp.readdir(remote_path))?;
+        if self.deterministic_order {
+            entries.sort_by(|(a, _), (b, _)| a.file_name().cmp(&b.file_name()));
+        }
+
+        Ok(entries)
+    }
+
+    /// Summarizes what removing a remote directory would delete.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let preview = sbs.preview_removal(Path::new("/path/to/remote_dir")).unwrap();
+    ///
+    /// println!("{}", preview);
+    /// ```
+    pub fn preview_removal(&self, remote_path: &Path) -> Result<RemovalPreview, SbsError> {
+        let entries = self.walk_remote_directory(remote_path)?;
+
+        let mut preview = RemovalPreview {
+            path: remote_path.to_path_buf(),
+            whole_directory: true,
+            file_count: 0,
+            directory_count: 0,
+            total_size: 0,
+            sample_paths: Vec::new(),
+        };
+
+        for (path, stat) in entries {
+            if stat.is_dir() {
+                preview.directory_count += 1;
+            } else {
+                preview.file_count += 1;
+                preview.total_size += stat.size.unwrap_or(0);
+
+                if preview.sample_paths.len() < guard::SAMPLE_PATH_COUNT {
+                    preview.sample_paths.push(path);
+                }
+            }
+        }
+
+        Ok(preview)
+    }
+
+    /// Removes a remote directory recursively via SFTP.
+    ///
+    /// A preview of what will be deleted is printed first and the user has to confirm it,
+    /// unless `assume_yes` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut sbs = Sbs::new(session); // Your SBS instance.
+    /// sbs.assume_yes = true;
+    ///
+    /// sbs.remove_directory(Path::new("/path/to/remote_dir")).unwrap();
+    /// ```
+    pub fn remove_directory(&self, remote_path: &Path) -> Result<(), SbsError> {
+        if self.dry_run {
+            println!("Would delete '{}'.", remote_path.display());
+
+            return Ok(());
+        }
+
+        let preview = self.preview_removal(remote_path)?;
+        guard::confirm_removal(&preview, self.assume_yes)?;
+
+        self.remove_tree(remote_path).map_err(SbsError::Transfer)
+    }
+
+    /// Removes a remote project directory, e.g. before it's uploaded again so no stale files linger in it.
+    ///
+    /// On top of the preview and confirmation of `remove_directory`, it refuses paths that are, or resolve to,
+    /// the root or home directory. A missing directory is left as is, and a symlink is removed without touching what it points at.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote_path` - The remote path.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// sbs.wipe_directory(Path::new("/builds/project")).unwrap();
+    /// ```
+    pub fn wipe_directory(&self, remote_path: &Path) -> Result<(), SbsError> {
+        guard::check_wipeable(remote_path).map_err(SbsError::Transfer)?;
+        if self.dry_run {
+            println!("Would wipe '{}'.", remote_path.display());
+
+            return Ok(());
+        }
+
+        let sftp_session = self.connection.sftp_handle()?;
+        let Ok(stat) = sftp_session.call(|sftp| sftp.lstat(remote_path)) else {
+            return Ok(());
+        };
+        if !stat.is_dir() {
+            return sftp_session.call(|sftp| sftp.unlink(remote_path)).map_err(SbsError::Transfer);
+        }
+
+        let resolved_path = sftp_session.call(|sftp| sftp.realpath(remote_path))?;
+        let home_directory = sftp_session.call(|sftp| sftp.realpath(Path::new(".")))?;
+        if resolved_path.parent().is_none() || resolved_path == home_directory {
+            return Err(SbsError::Transfer(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("Refusing to wipe '{}', it resolves to '{}'!", remote_path.display(), resolved_path.display()),
+            )));
+        }
+
+        info!("Wiping the remote directory '{}'...", remote_path.display());
+        self.remove_directory(remote_path)
+    }
+
+    /// Removes a remote directory and its contents, without asking.
+    fn remove_tree(&self, remote_path: &Path) -> Result<(), Error> {
+        let sftp_session = self.connection.sftp_handle()?;
+
+        let mut entries = Vec::new();
+        self.walk_remote(&sftp_session, remote_path, &mut entries)?;
+
+        // Contents come after their directory, so deleting in reverse empties every directory before removing it.
+        for (path, stat) in entries.iter().rev() {
+            if stat.is_dir() {
+                sftp_session.call(|sftp| sftp.rmdir(path))?;
+            } else {
+                sftp_session.call(|sftp| sftp.unlink(path))?;
+            }
+        }
+
+        sftp_session.call(|sftp| sftp.rmdir(remote_path))?;
+
+        Ok(())
+    }
+}
+
+/// Reads what a stream of a non-blocking session has, `None` if it has nothing right now.
+fn read_available(stream: &mut impl Read, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+    match stream.read(buffer) {
+        Ok(read) => Ok(Some(read)),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Passes the complete lines of streamed output on, leaving out the markers the scripts echo for bookkeeping.
+///
+/// # Arguments
+///
+/// * `pending` - The output that hasn't been passed on yet. Complete lines are removed from it.
+/// * `finished` - Whether the output has ended, so an incomplete last line is passed on too.
+/// * `token` - The token the markers were made with.
+/// * `on_output` - Receives the lines.
+fn stream_lines(pending: &mut Vec<u8>, finished: bool, token: &str, on_output: &mut dyn FnMut(&[u8])) {
+    let end = match pending.iter().rposition(|&byte| byte == b'\n') {
+        _ if finished => pending.len(),
+        Some(position) => position + 1,
+        None => return,
+    };
+
+    let status_marker = format!("sbs-failed-{}-", token);
+    let warnings_marker = warnings::marker_prefix(token);
+    let pid_marker = timeout::pid_marker(token);
+    let ignored_marker = status::ignored_marker(token);
+
+    let lines = pending.drain(..end).collect::<Vec<_>>();
+    let lines = lines.split_inclusive(|&byte| byte == b'\n')
+        .filter(|line| [&status_marker, &warnings_marker, &pid_marker, &ignored_marker].iter().all(|marker| !line.starts_with(marker.as_bytes())))
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+
+    if !lines.is_empty() {
+        on_output(&lines);
+    }
+}
+
+/// Compiles commands into a single script, exporting the environment variables ahead of them.
+///
+/// Every command changes into its working directory first, so none depends on where the one before it left off.
+///
+/// # Arguments
+///
+/// * `environment` - The environment variables.
+/// * `working_directory` - The directory of commands without a `working_dir`, if any.
+/// * `commands` - The commands.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::settings::Settings;
+/// # use ssh_build_server::util::ssh::compile_script;
+/// # let settings = Settings::new("").unwrap();
+/// let script = compile_script(&[("PROFILE".to_string(), "release".to_string())], Some("/builds/project"), &settings.commands);
+/// ```
+pub fn compile_script(environment: &[(String, String)], working_directory: Option<&str>, commands: &[Command]) -> String {
+    let mut compiled = String::new();
+
+    for (name, value) in environment {
+        compiled.push_str(&format!("export {}={}\n", name, shell::quote(value)));
+    }
+
+    // Commands that check for warnings need their own output and timeouts need to know the running command,
+    // so every command is preceded by a marker.
+    let mark_commands = warnings::needs_markers(commands);
+    if commands.iter().any(|command| command.timeout_secs.is_some()) {
+        compiled.push_str(&timeout::pid_snippet(temp::run_token()));
+    }
+
+    for (index, command) in commands.iter().enumerate() {
+        if mark_commands {
+            compiled.push_str(&format!("echo {}\n", warnings::marker(temp::run_token(), index)));
+        }
+
+        // A directory that can't be entered fails the command itself.
+        let working_dir = command.resolve_working_dir(working_directory);
+        if let Some(working_dir) = &working_dir {
+            compiled.push_str(&format!("cd {} && ", shell::quote_path(working_dir)));
+        }
+
+        // The variables of a command live in a subshell, so they don't leak into the commands after it.
+        let (open, close) = if command.env.is_empty() { ("{", "}") } else { ("(", ")") };
+        let mut body = String::new();
+        for (name, value) in &command.env {
+            body.push_str(&format!("export {}={}\n", name, shell::quote(value)));
+        }
+        body.push_str(&command.command);
+
+        if command.warnings_as_errors {
+            // Compilers print their warnings to stderr.
+            compiled.push_str(&format!("{} {}\n{} 2>&1\n", open, body, close));
+        } else if working_dir.is_some() || !command.env.is_empty() {
+            compiled.push_str(&format!("{} {}\n{}\n", open, body, close));
+        } else {
+            compiled.push_str(command.command.as_str());
+            compiled.push('\n');
+        }
+
+        compiled.push_str(&status::check_snippet(command, temp::run_token(), index));
+    }
+
+    // Every command succeeded, even if the last one did with a nonzero code.
+    compiled.push_str("exit 0\n");
+
+    compiled
+}
+
+/// Checks whether one path is the same as, or nested inside, the other.
+fn paths_overlap(a: &Path, b: &Path) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    /// An in-memory remote filesystem that behaves like an SFTP server for `mkdir`.
+    #[derive(Default)]
+    struct MockRemote {
+        directories: Mutex<HashSet<PathBuf>>,
+        files: HashSet<PathBuf>,
+    }
+
+    impl DirectoryCreator for MockRemote {
+        fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error> {
+            if self.files.contains(path) {
+                return Ok(false);
+            }
+
+            match self.directories.lock().unwrap().contains(path) {
+                true => Ok(true),
+                false => Err(ssh2::Error::new(ErrorCode::SFTP(2), "no such file")),
+            }
+        }
+
+        fn mkdir(&self, path: &Path, _mode: i32) -> Result<(), ssh2::Error> {
+            if self.files.contains(path) || !self.directories.lock().unwrap().insert(path.to_path_buf()) {
+                return Err(ssh2::Error::new(ErrorCode::SFTP(SFTP_FAILURE), "failure"));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn create_dir_tolerates_concurrent_creators() {
+        const WORKERS: usize = 8;
+
+        let remote = Arc::new(MockRemote::default());
+        let barrier = Arc::new(Barrier::new(WORKERS));
+        let path = Path::new("/remote/project/src");
+
+        let workers = (0..WORKERS)
+            .map(|_| {
+                let remote = Arc::clone(&remote);
+                let barrier = Arc::clone(&barrier);
+
+                thread::spawn(move || {
+                    // Make every worker race for the same directory.
+                    barrier.wait();
+
+                    Sbs::create_dir(remote.as_ref(), path, 0o755)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for worker in workers {
+            assert!(worker.join().unwrap().is_ok());
+        }
+
+        assert!(remote.directories.lock().unwrap().contains(path));
+    }
+
+    #[test]
+    fn dry_runs_need_no_connection() {
+        let directory = std::env::temp_dir().join(format!("sbs-dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("main.rs"), "fn main() {}").unwrap();
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.dry_run = true;
+        let commands = [Command::new("make", "Build.")];
+        let remote_path = Path::new("/builds/project");
+
+        let results = [
+            sbs.send_directory_with_progress(&directory, remote_path, |_| {}).map(|_| ()),
+            sbs.receive_directory_with_progress(&directory.join("out"), remote_path, |_| {}).map(|_| ()),
+            sbs.mirror_directory_structure(&directory, remote_path),
+            sbs.send_file(&directory.join("main.rs"), &remote_path.join("main.rs")),
+            sbs.send_bytes(b"answer = 42\n", &remote_path.join("generated.toml"), 0o644),
+            sbs.run_remote_command("uname -a").map(|_| ()),
+            sbs.execute_commands(&commands, false).map(|_| ()),
+            sbs.provision(&commands).map(|_| ()),
+            sbs.remove_directory(remote_path),
+            sbs.wipe_directory(remote_path),
+        ];
+        let created_output = directory.join("out").exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        for result in results {
+            result.unwrap();
+        }
+        assert!(!created_output);
+        assert!(sbs.resolve_remote_path("pwd").is_err());
+    }
+
+    #[test]
+    fn reading_nothing_yet_is_not_an_error() {
+        struct Waiting;
+
+        impl Read for Waiting {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(Error::from(ErrorKind::WouldBlock))
+            }
+        }
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(read_available(&mut Waiting, &mut buffer).unwrap(), None);
+        assert_eq!(read_available(&mut &b"output"[..], &mut buffer).unwrap(), Some(6));
+    }
+
+    #[test]
+    fn stream_lines_passes_complete_lines_without_markers() {
+        let mut streamed = Vec::new();
+        let mut pending = b"sbs-command-t-0\nCompiling\nsbs-failed-t-0-1\nerr".to_vec();
+
+        stream_lines(&mut pending, false, "t", &mut |lines| streamed.extend_from_slice(lines));
+        assert_eq!(streamed, b"Compiling\n");
+        assert_eq!(pending, b"err");
+
+        stream_lines(&mut pending, true, "t", &mut |lines| streamed.extend_from_slice(lines));
+        assert_eq!(streamed, b"Compiling\nerr");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn commands_start_in_their_working_directory() {
+        let root = std::env::temp_dir().join(format!("sbs-working-dir-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("native")).unwrap();
+        let root = std::fs::canonicalize(&root).unwrap();
+        let command = |command: &str, working_dir: Option<&str>| Command {
+            working_dir: working_dir.map(String::from),
+            ..Command::new(command, "Print the directory.")
+        };
+
+        let commands = [command("cd / && pwd", None), command("pwd", None), command("pwd", Some("native"))];
+        let script = compile_script(&[], Some(&root.to_string_lossy()), &commands);
+        let output = std::process::Command::new("sh").arg("-c").arg(&script).output().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let expected = format!("/\n{}\n{}\n", root.display(), root.join("native").display());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    #[test]
+    fn command_variables_stay_with_their_command() {
+        let command = |command: &str, env: &[(&str, &str)]| Command {
+            env: env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            ..Command::new(command, "Print the variables.")
+        };
+
+        let commands = [command("echo \"$PROFILE $FLAGS\"", &[("FLAGS", "-C 'opt'")]), command("echo \"$PROFILE $FLAGS\"", &[])];
+        let script = compile_script(&[("PROFILE".to_string(), "release".to_string())], None, &commands);
+        let output = std::process::Command::new("sh").arg("-c").arg(&script).output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "release -C 'opt'\nrelease \n");
+    }
+
+    /// Builds a named command of a phase with every other setting left at its default.
+    fn plain_command(name: Option<&str>, command: &str, execute_after_compilation: bool) -> Command {
+        Command {
+            name: name.map(String::from),
+            execute_after_compilation,
+            ..Command::new(command, "A test command.")
+        }
+    }
+
+    #[test]
+    fn compile_commands_exports_the_environment_and_stops_at_a_failure() {
+        let root = std::fs::canonicalize(std::env::temp_dir()).unwrap();
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.environment = vec![("PROFILE".to_string(), "it's release".to_string())];
+        sbs.working_directory = Some(root.to_string_lossy().into_owned());
+
+        let commands = [
+            plain_command(None, "echo \"$PROFILE\"", false),
+            plain_command(None, "pwd", false),
+            plain_command(None, "(exit 3)", false),
+            plain_command(None, "echo unreachable", false),
+        ];
+        let script = sbs.compile_commands(&commands);
+        let output = std::process::Command::new("sh").arg("-c").arg(&script).output().unwrap();
+
+        assert!(script.starts_with("export PROFILE='it'\\''s release'\n"));
+        // The failure is announced with the index and exit code of the command.
+        let expected = format!("it's release\n{}\nsbs-failed-{}-2-3\n", root.display(), temp::run_token());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn phase_commands_are_filtered_by_phase_and_name() {
+        let commands = [
+            plain_command(Some("lint"), "cargo clippy", false),
+            plain_command(Some("build"), "cargo build", false),
+            plain_command(None, "cargo test", false),
+            plain_command(Some("package"), "tar -czf out.tar.gz target", true),
+        ];
+        let selected = |only: &[&str], skip: &[&str], is_after_compilation| {
+            let mut sbs = Sbs::new(Session::new().unwrap());
+            sbs.command_filter = CommandFilter {
+                only: only.iter().map(|name| name.to_string()).collect(),
+                skip: skip.iter().map(|name| name.to_string()).collect(),
+            };
+
+            sbs.phase_commands(&commands, is_after_compilation).into_iter().map(|command| command.command).collect::<Vec<_>>()
+        };
+
+        assert_eq!(selected(&[], &[], false), vec!["cargo clippy", "cargo build", "cargo test"]);
+        assert_eq!(selected(&[], &[], true), vec!["tar -czf out.tar.gz target"]);
+        assert_eq!(selected(&["build"], &[], false), vec!["cargo build"]);
+        assert_eq!(selected(&[], &["lint"], false), vec!["cargo build", "cargo test"]);
+        assert_eq!(selected(&["lint", "build"], &["lint"], false), vec!["cargo build"]);
+        assert!(selected(&["lint"], &[], true).is_empty());
+    }
+
+    #[test]
+    fn phase_commands_fall_back_to_the_default_timeout() {
+        let mut commands = [plain_command(None, "make", false), plain_command(None, "make check", false), plain_command(None, "make docs", false)];
+        commands[1].timeout_secs = Some(60);
+        commands[2].timeout_secs = Some(0);
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        let timeouts = |sbs: &Sbs| sbs.phase_commands(&commands, false).into_iter().map(|command| command.timeout_secs).collect::<Vec<_>>();
+
+        assert_eq!(timeouts(&sbs), vec![None, Some(60), None]);
+
+        sbs.command_timeout = Some(Duration::from_secs(600));
+
+        assert_eq!(timeouts(&sbs), vec![Some(600), Some(60), None]);
+    }
+
+    #[test]
+    fn copy_exact_detects_length_mismatch() {
+        let path = Path::new("file");
+        let mut written = Vec::new();
+
+        assert!(Sbs::copy_exact(&mut &b"hello"[..], &mut written, 5, path).is_ok());
+        assert!(Sbs::copy_exact(&mut &b"hell"[..], &mut Vec::new(), 5, path).is_err());
+        assert!(Sbs::copy_exact(&mut &b"hello!"[..], &mut Vec::new(), 5, path).is_err());
+        assert_eq!(written, b"hello");
+    }
+
+    #[test]
+    fn create_dir_fails_when_path_is_a_file() {
+        let remote = MockRemote {
+            files: HashSet::from([PathBuf::from("/remote/file")]),
+            ..Default::default()
+        };
+
+        assert!(Sbs::create_dir(&remote, Path::new("/remote/file"), 0o755).is_err());
+    }
+
+    #[test]
+    fn make_dirs_reports_a_file_in_the_way() {
+        let remote = MockRemote {
+            files: HashSet::from([PathBuf::from("/remote/file")]),
+            ..Default::default()
+        };
+
+        assert!(Sbs::make_dirs(&remote, Path::new("/remote/project/src"), 0o755).is_ok());
+        assert!(remote.directories.lock().unwrap().contains(Path::new("/remote/project/src")));
+
+        let err = Sbs::make_dirs(&remote, Path::new("/remote/file/src"), 0o755).unwrap_err();
+        assert!(matches!(err, SbsError::Transfer(err) if err.kind() == ErrorKind::InvalidInput));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("sbs-mode-{}.sh", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        let mode = Sbs::upload_mode(&path.metadata().unwrap());
+
+        let stat = FileStat { size: None, uid: None, gid: None, perm: Some(0o100440), atime: None, mtime: None };
+        Sbs::apply_remote_mode(&path, &stat).unwrap();
+        let downloaded = path.metadata().unwrap().permissions().mode() & 0o777;
+
+        // A read-only file is replaced when it's downloaded again.
+        let replaced = Sbs::create_local_file(&path).is_ok();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode, 0o750);
+        assert_eq!(downloaded, 0o440);
+        assert!(replaced);
     }
 }
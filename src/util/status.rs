@@ -0,0 +1,154 @@
+use log::warn;
+
+use crate::util::error::SbsError;
+use crate::util::settings::Command;
+use crate::util::ssh::CommandOutput;
+
+/// Gets the shell snippet that follows a command, stopping the script unless it exited with a success code.
+///
+/// The failure is announced with a marker on stdout, so it can be tied back to the command.
+/// A command with `continue_on_error` announces its failure with a different marker and lets the script go on.
+///
+/// # Arguments
+///
+/// * `command` - The command.
+/// * `token` - A token unique to the run, so the marker can't be mistaken for real output.
+/// * `index` - The index of the command.
+pub fn check_snippet(command: &Command, token: &str, index: usize) -> String {
+    // Without any success codes, the usual convention applies.
+    let success_codes = match command.success_codes.as_slice() {
+        [] => "0".to_string(),
+        codes => codes.iter()
+            .map(|code| code.to_string())
+            .collect::<Vec<_>>()
+            .join("|"),
+    };
+
+    if command.continue_on_error {
+        return format!("sbs_status=$?; case $sbs_status in {}) ;; *) echo {}{}-$sbs_status;; esac\n", success_codes, ignored_marker(token), index);
+    }
+
+    format!(
+        "sbs_status=$?; case $sbs_status in {}) ;; *) echo sbs-failed-{}-{}-$sbs_status; exit 1;; esac\n",
+        success_codes,
+        token,
+        index,
+    )
+}
+
+/// Gets the prefix of the line that announces the failure of a command with `continue_on_error`.
+///
+/// # Arguments
+///
+/// * `token` - The token of the run.
+pub fn ignored_marker(token: &str) -> String {
+    format!("sbs-ignored-{}-", token)
+}
+
+/// Checks how a script compiled with check snippets ended.
+///
+/// The error of a failed command carries its stderr, or its stdout if nothing was written to stderr.
+/// Failures of commands with `continue_on_error` are logged as warnings and removed from the output.
+///
+/// # Arguments
+///
+/// * `commands` - The commands that were run, in order.
+/// * `output` - The output and exit code of the script.
+/// * `token` - The token the markers were made with.
+///
+/// # Examples
+///
+/// ```ignore
+/// let output = check(&commands, output, temp::run_token()).unwrap();
+/// ```
+pub fn check(commands: &[Command], mut output: CommandOutput, token: &str) -> Result<CommandOutput, SbsError> {
+    let ignored_prefix = ignored_marker(token);
+    if output.stdout.contains(&ignored_prefix) {
+        for (index, code) in output.stdout.lines().filter_map(|line| parse_marker(line, &ignored_prefix)) {
+            match commands.get(index) {
+                Some(command) => warn!("The command `{}` ({}) exited with code {}, continuing anyway.", command.command, command.description, code),
+                None => warn!("A command exited with code {}, continuing anyway.", code),
+            }
+        }
+
+        output.stdout = output.stdout.split_inclusive('\n')
+            .filter(|line| !line.starts_with(&ignored_prefix))
+            .collect();
+    }
+
+    let prefix = format!("sbs-failed-{}-", token);
+
+    let failure = output.stdout.lines().find_map(|line| parse_marker(line, &prefix));
+    if failure.is_none() && output.exit_code == 0 {
+        return Ok(output);
+    }
+
+    let stderr = if output.stderr.trim().is_empty() {
+        output.stdout.lines().filter(|line| !line.starts_with(&prefix)).collect::<Vec<_>>().join("\n")
+    } else {
+        output.stderr
+    };
+
+    match failure {
+        Some((index, code)) => Err(SbsError::CommandFailed {
+            command: commands.get(index).map(|command| command.command.clone()),
+            description: commands.get(index).map(|command| command.description.clone()),
+            exit_code: code,
+            stderr,
+        }),
+        None => Err(SbsError::CommandFailed { command: None, description: None, exit_code: output.exit_code, stderr }),
+    }
+}
+
+/// Parses the index of the command and its exit code out of a failure marker line.
+fn parse_marker(line: &str, prefix: &str) -> Option<(usize, i32)> {
+    let (index, code) = line.trim_end().strip_prefix(prefix)?.split_once('-')?;
+
+    Some((index.parse().ok()?, code.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(success_codes: Vec<i32>) -> Command {
+        Command {
+            success_codes,
+            ..Command::new("diff a b", "Compare the files.")
+        }
+    }
+
+    #[test]
+    fn snippet_lists_the_success_codes() {
+        let snippet = check_snippet(&command(vec![0, 1]), "t", 3);
+
+        assert_eq!(snippet, "sbs_status=$?; case $sbs_status in 0|1) ;; *) echo sbs-failed-t-3-$sbs_status; exit 1;; esac\n");
+    }
+
+    #[test]
+    fn check_reports_the_failed_command() {
+        let commands = [command(vec![0]), command(vec![0, 1])];
+
+        let output = |stdout: &str, exit_code| CommandOutput { stdout: stdout.to_string(), stderr: String::new(), exit_code };
+
+        let err = check(&commands, output("output\nsbs-failed-t-1-2\n", 1), "t").unwrap_err();
+
+        assert!(matches!(err, SbsError::CommandFailed { exit_code: 2, .. }));
+        assert_eq!(err.to_string(), "The command `diff a b` (Compare the files.) exited with code 2!\noutput");
+        assert_eq!(check(&commands, output("output\n", 0), "t").unwrap().stdout, "output\n");
+    }
+
+    #[test]
+    fn ignored_failures_dont_stop_the_script() {
+        let mut cleanup = command(vec![0]);
+        cleanup.continue_on_error = true;
+
+        let snippet = check_snippet(&cleanup, "t", 0);
+        let script = format!("false\n{}echo after\n", snippet);
+        let stdout = String::from_utf8(std::process::Command::new("sh").arg("-c").arg(&script).output().unwrap().stdout).unwrap();
+        assert_eq!(stdout, "sbs-ignored-t-0-1\nafter\n");
+
+        let output = CommandOutput { stdout, stderr: String::new(), exit_code: 0 };
+        assert_eq!(check(&[cleanup], output, "t").unwrap().stdout, "after\n");
+    }
+}
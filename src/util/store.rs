@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use crate::util::ssh::Sbs;
+use crate::util::verify::{self, FileSummary};
+use crate::util::{shell, temp};
+
+/// How many files are looked up or placed per remote command, to keep command lines short.
+const BATCH_SIZE: usize = 200;
+
+/// What an upload through the content store did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StoreUpload {
+    /// The files whose contents weren't in the store yet and were sent.
+    pub sent: usize,
+    /// The files whose contents were already in the store.
+    pub reused: usize,
+}
+
+/// Gets the name a file is stored under: its hash and its mode, since copies from the store keep the mode.
+///
+/// # Arguments
+///
+/// * `hash` - The hex-encoded SHA-256 of the contents.
+/// * `mode` - The permissions of the uploaded file.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::store::store_key;
+/// assert_eq!(store_key("e3b0c442...", 0o644), "e3b0c442....644");
+/// ```
+pub fn store_key(hash: &str, mode: i32) -> String {
+    format!("{}.{:o}", hash, mode)
+}
+
+/// Uploads a directory through a content-addressed store on the server.
+///
+/// Every file is stored once under its hash in the store, which may be shared between projects,
+/// and the project tree is rebuilt from copies out of the store. Files whose contents are
+/// already stored aren't sent again, no matter which project or branch stored them.
+///
+/// # Arguments
+///
+/// * `sbs` - A connected SBS instance.
+/// * `local_path` - The local directory.
+/// * `remote_path` - The remote directory.
+/// * `store_path` - The remote directory of the store.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sbs = Sbs::new(session); // Your connected SBS instance.
+///
+/// let upload = upload(&sbs, Path::new("/path/to/project"), Path::new("/remote/project"), Path::new("/remote/store")).unwrap();
+/// ```
+pub fn upload(sbs: &Sbs, local_path: &Path, remote_path: &Path, store_path: &Path) -> Result<StoreUpload, Error> {
+    let mut files = BTreeMap::new();
+    verify::local_tree(local_path, Path::new(""), &sbs.ignore, true, &mut files)?;
+
+    let keys = files.iter()
+        .map(|(relative_path, summary)| Ok((relative_path.clone(), key_of(sbs, &local_path.join(relative_path), summary)?)))
+        .collect::<Result<BTreeMap<PathBuf, String>, Error>>()?;
+
+    // Create the tree (including empty directories) and the store up front.
+    sbs.mirror_directory_structure(local_path, remote_path)?;
+    run_script(sbs, &format!("mkdir -p {}", quote(store_path)))?;
+
+    // Send the contents the store is missing, each one once.
+    let stored = stored_keys(sbs, store_path, &keys.values().cloned().collect())?;
+    let mut upload = StoreUpload::default();
+    let mut sent_keys = BTreeSet::new();
+    for (relative_path, key) in &keys {
+        if stored.contains(key) || !sent_keys.insert(key.clone()) {
+            upload.reused += 1;
+
+            continue;
+        }
+
+        // Stage the file next to its final name, so an interrupted upload never leaves a truncated file in the store.
+        let stored_path = store_path.join(key);
+        let staging_path = temp::staging_path(&stored_path);
+        sbs.send_file(&local_path.join(relative_path), &staging_path)?;
+        run_script(sbs, &format!("mv -f {} {}", quote(&staging_path), quote(&stored_path)))?;
+
+        upload.sent += 1;
+    }
+
+    // Rebuild the tree from the store. Copies instead of links, so a build writing to a file can't corrupt the store.
+    let placements = keys.iter()
+        .map(|(relative_path, key)| format!("cp {} {}", quote(&store_path.join(key)), quote(&remote_path.join(relative_path))))
+        .collect::<Vec<_>>();
+    for batch in placements.chunks(BATCH_SIZE) {
+        run_script(sbs, &format!("set -e\n{}", batch.join("\n")))?;
+    }
+
+    Ok(upload)
+}
+
+/// Gets the store key of a local file.
+fn key_of(sbs: &Sbs, path: &Path, summary: &FileSummary) -> Result<String, Error> {
+    let hash = summary.hash.as_deref().unwrap_or_default();
+
+    Ok(store_key(hash, sbs.file_mode(&path.metadata()?)))
+}
+
+/// Finds out which of the keys are already in the store.
+fn stored_keys(sbs: &Sbs, store_path: &Path, keys: &BTreeSet<String>) -> Result<BTreeSet<String>, Error> {
+    let keys = keys.iter().cloned().collect::<Vec<_>>();
+
+    let mut stored = BTreeSet::new();
+    for batch in keys.chunks(BATCH_SIZE) {
+        let script = format!(
+            "cd {} && for key in {}; do [ -e \"$key\" ] && echo \"$key\"; done; true",
+            quote(store_path),
+            batch.join(" "),
+        );
+
+        stored.extend(run_script(sbs, &script)?.lines().map(String::from));
+    }
+
+    Ok(stored)
+}
+
+/// Runs a script on the server, failing unless it succeeds.
+fn run_script(sbs: &Sbs, script: &str) -> Result<String, Error> {
+    match sbs.run_remote_command(script)? {
+        (0, output) => Ok(output),
+        (exit_code, output) => Err(Error::other(format!("The content store command failed with exit code {}: {}", exit_code, output.trim()))),
+    }
+}
+
+fn quote(path: &Path) -> String {
+    shell::quote(&path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_key_includes_the_mode() {
+        assert_ne!(store_key("abc", 0o644), store_key("abc", 0o755));
+        assert_eq!(store_key("abc", 0o755), "abc.755");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The remote directory scratch files and directories are created in.
+const REMOTE_TEMP_DIRECTORY: &str = "/tmp";
+
+/// Counts the temporary names handed out by this run, so no two of them are the same.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Gets the token that identifies this run, made of the PID, the start time and a random number.
+///
+/// Two runs never share a token, even on different machines building against the same server.
+pub fn run_token() -> &'static str {
+    static TOKEN: OnceLock<String> = OnceLock::new();
+
+    TOKEN.get_or_init(|| {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+
+        // The standard library seeds every `RandomState` randomly, which is all the randomness needed here.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(timestamp);
+
+        format!("{}-{:x}-{:08x}", std::process::id(), timestamp, hasher.finish() as u32)
+    })
+}
+
+/// Generates a unique name for a temporary file or directory.
+///
+/// # Arguments
+///
+/// * `purpose` - What the name is used for, to make stray files recognizable.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::temp::unique_name;
+/// let name = unique_name("benchmark"); // E.g. "sbs-benchmark-4242-17a0c3f2e5b1d000-9f86d081-0".
+/// ```
+pub fn unique_name(purpose: &str) -> String {
+    format!("sbs-{}-{}-{}", purpose, run_token(), COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Generates a unique path in the remote temporary directory.
+///
+/// # Arguments
+///
+/// * `purpose` - What the path is used for.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::temp::remote_temp_path;
+/// let scratch_directory = remote_temp_path("benchmark");
+/// ```
+pub fn remote_temp_path(purpose: &str) -> PathBuf {
+    Path::new(REMOTE_TEMP_DIRECTORY).join(unique_name(purpose))
+}
+
+/// Generates a unique path next to a file, for staging it before it's moved into place.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::Path;
+/// # use ssh_build_server::util::temp::staging_path;
+/// let staging_path = staging_path(Path::new("/builds/project/main.rs")); // E.g. "/builds/project/.main.rs.sbs-part-...".
+/// ```
+pub fn staging_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.{}", name, unique_name("part")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique_within_a_run() {
+        assert_ne!(unique_name("test"), unique_name("test"));
+    }
+
+    #[test]
+    fn staging_path_is_a_hidden_sibling() {
+        let staging_path = staging_path(Path::new("/builds/project/main.rs"));
+
+        assert_eq!(staging_path.parent(), Some(Path::new("/builds/project")));
+        assert!(staging_path.file_name().unwrap().to_string_lossy().starts_with(".main.rs.sbs-part-"));
+    }
+}
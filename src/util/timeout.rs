@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+
+use crate::util::settings::Command;
+use crate::util::warnings;
+
+/// Gets the prefix of the line a script announces its process ID with, so a command that times out can be killed.
+///
+/// # Arguments
+///
+/// * `token` - A token unique to the run, so the marker can't be mistaken for real output.
+pub fn pid_marker(token: &str) -> String {
+    format!("sbs-pid-{}-", token)
+}
+
+/// Gets the shell snippet that announces the process ID of the script, see [`pid_marker`].
+///
+/// # Arguments
+///
+/// * `token` - The token of the run.
+pub fn pid_snippet(token: &str) -> String {
+    format!("echo {}$$\n", pid_marker(token))
+}
+
+/// Gets the command that kills a script and everything it started, by its process group.
+///
+/// # Arguments
+///
+/// * `pid` - The process ID the script announced.
+///
+/// # Examples
+///
+/// ```ignore
+/// sbs.run_remote_command(&kill_command(4242)).unwrap();
+/// ```
+pub fn kill_command(pid: u32) -> String {
+    format!("kill -TERM -$(ps -o pgid= -p {0} | tr -d ' ') 2>/dev/null || kill -TERM {0}", pid)
+}
+
+/// Removes the line with the process ID from the output of a script.
+///
+/// # Arguments
+///
+/// * `output` - The output of the script.
+/// * `token` - The token of the run.
+pub fn strip_pid_marker(output: String, token: &str) -> String {
+    let prefix = pid_marker(token);
+    if !output.contains(&prefix) {
+        return output;
+    }
+
+    output.split_inclusive('\n')
+        .filter(|line| !line.starts_with(&prefix))
+        .collect()
+}
+
+/// Follows a running script through the markers in its output, to know which command runs and when it runs out of time.
+///
+/// The script has to be compiled with markers, see [`warnings::needs_markers`].
+pub struct CommandClock<'a> {
+    commands: &'a [Command],
+    token: &'a str,
+    current: usize,
+    started: Instant,
+    scanned: usize,
+    pid: Option<u32>,
+}
+
+impl<'a> CommandClock<'a> {
+    /// Starts the clock of the first command.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - The commands of the script, in order.
+    /// * `token` - The token the markers were made with.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut clock = CommandClock::new(&commands, temp::run_token());
+    /// ```
+    pub fn new(commands: &'a [Command], token: &'a str) -> Self {
+        Self {
+            commands,
+            token,
+            current: 0,
+            started: Instant::now(),
+            scanned: 0,
+            pid: None,
+        }
+    }
+
+    /// Looks for markers in the complete lines of the output that weren't looked at yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - Everything the script printed so far.
+    pub fn observe(&mut self, output: &[u8]) {
+        let end = output.iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+        if end <= self.scanned {
+            return;
+        }
+
+        let command_marker = warnings::marker_prefix(self.token);
+        let pid_marker = pid_marker(self.token);
+        for line in output[self.scanned..end].split(|&byte| byte == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end();
+
+            if let Some(index) = line.strip_prefix(&command_marker).and_then(|index| index.parse().ok()) {
+                self.current = index;
+                self.started = Instant::now();
+            } else if let Some(pid) = line.strip_prefix(&pid_marker).and_then(|pid| pid.parse().ok()) {
+                self.pid = Some(pid);
+            }
+        }
+
+        self.scanned = end;
+    }
+
+    /// Gets the command that's running.
+    pub fn current(&self) -> Option<&'a Command> {
+        self.commands.get(self.current)
+    }
+
+    /// Gets the process ID the script announced, if it did yet.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Gets how long the running command may still take, `None` if it has no timeout.
+    pub fn remaining(&self) -> Option<Duration> {
+        let timeout = Duration::from_secs(self.current()?.timeout_secs?);
+
+        Some(timeout.saturating_sub(self.started.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(timeout_secs: Option<u64>) -> Command {
+        Command {
+            timeout_secs,
+            ..Command::new("cargo build", "Build the project.")
+        }
+    }
+
+    #[test]
+    fn clock_follows_the_markers() {
+        let commands = [command(None), command(Some(60))];
+        let mut clock = CommandClock::new(&commands, "t");
+        assert_eq!(clock.remaining(), None);
+
+        clock.observe(b"sbs-pid-t-4242\nsbs-command-t-0\nCompiling\nsbs-command-t-1\nsbs-comm");
+        assert_eq!(clock.pid(), Some(4242));
+        assert!(clock.remaining().is_some_and(|remaining| remaining > Duration::from_secs(59)));
+
+        assert_eq!(strip_pid_marker("sbs-pid-t-4242\nCompiling\n".to_string(), "t"), "Compiling\n");
+    }
+}
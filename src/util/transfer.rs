@@ -0,0 +1,627 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::warn;
+use ssh2::{FileStat, OpenFlags, OpenType};
+
+use crate::util::connection::SftpHandle;
+use crate::util::guard::format_size;
+use crate::util::ssh::{DEFAULT_REMOTE_DIR_MODE, Sbs};
+
+/// The progress of a directory transfer, reported after every file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// The local path of the file that was just transferred.
+    pub current_file: PathBuf,
+    /// The bytes transferred so far, including the current file.
+    pub bytes_done: u64,
+    /// The bytes of the whole transfer, if they were counted up front.
+    pub total_bytes: Option<u64>,
+}
+
+/// The files a directory transfer moved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferSummary {
+    /// The local paths of the transferred files, in the order they were transferred.
+    pub files: Vec<PathBuf>,
+    /// The bytes of the transferred files.
+    pub bytes: u64,
+    /// How many files an incremental transfer left alone, since they were unchanged.
+    pub unchanged: usize,
+}
+
+impl fmt::Display for TransferSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} file(s) ({})", self.files.len(), format_size(self.bytes))?;
+
+        if self.unchanged > 0 {
+            write!(f, ", {} unchanged", self.unchanged)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds up the transferred files and reports them to an optional callback.
+pub(crate) struct ProgressTracker<'a> {
+    on_progress: Option<&'a mut dyn FnMut(TransferProgress)>,
+    bytes_done: u64,
+    total_bytes: Option<u64>,
+    summary: TransferSummary,
+}
+
+impl<'a> ProgressTracker<'a> {
+    /// Creates a tracker that reports nothing.
+    pub(crate) fn none() -> Self {
+        Self { on_progress: None, bytes_done: 0, total_bytes: None, summary: TransferSummary::default() }
+    }
+
+    /// Creates a tracker reporting to a callback.
+    pub(crate) fn new(on_progress: &'a mut dyn FnMut(TransferProgress), total_bytes: Option<u64>) -> Self {
+        Self { on_progress: Some(on_progress), bytes_done: 0, total_bytes, summary: TransferSummary::default() }
+    }
+
+    /// Records a transferred file.
+    pub(crate) fn file_done(&mut self, local_path: &Path, size: u64) {
+        self.summary.files.push(local_path.to_path_buf());
+        self.summary.bytes += size;
+
+        self.report(local_path, size);
+    }
+
+    /// Records a file left alone by an incremental transfer, which counts as done for the progress.
+    pub(crate) fn file_unchanged(&mut self, local_path: &Path, size: u64) {
+        self.summary.unchanged += 1;
+
+        self.report(local_path, size);
+    }
+
+    /// Gets what was transferred.
+    pub(crate) fn into_summary(self) -> TransferSummary {
+        self.summary
+    }
+
+    /// Reports a finished file to the callback.
+    fn report(&mut self, local_path: &Path, size: u64) {
+        self.bytes_done += size;
+
+        if let Some(on_progress) = self.on_progress.as_mut() {
+            on_progress(TransferProgress {
+                current_file: local_path.to_path_buf(),
+                bytes_done: self.bytes_done,
+                total_bytes: self.total_bytes,
+            });
+        }
+    }
+}
+
+/// Sums up the sizes of the files below a remote directory, as listed by a backend.
+pub(crate) fn remote_tree_size<B: TransferBackend>(backend: &B, remote_path: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    let mut directories = vec![remote_path.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for (path, stat) in backend.read_dir(&directory)? {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+
+            if stat.is_dir() {
+                directories.push(directory.join(name));
+            } else {
+                size += stat.size.unwrap_or(0);
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// The wire protocol used to move single files and directories, separate from walking directory trees.
+///
+/// `Sbs::send_directory_with` and `Sbs::receive_directory_with` work with any backend,
+/// so library consumers can supply their own, e.g. for a custom protocol or as a mock in tests.
+pub trait TransferBackend {
+    /// Sends a local file to a remote path, whose parent directory exists.
+    fn send_file(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error>;
+
+    /// Receives a remote file into a local path, whose parent directory exists.
+    ///
+    /// `size` is the size of the remote file according to its directory listing, if known.
+    fn receive_file(&self, remote_path: &Path, local_path: &Path, size: Option<u64>) -> Result<(), Error>;
+
+    /// Makes sure a remote directory exists, creating it and its parents if necessary.
+    fn make_dir(&self, remote_path: &Path) -> Result<(), Error>;
+
+    /// Gets the stat of a remote path, or `None` if it doesn't exist.
+    fn stat(&self, remote_path: &Path) -> Result<Option<FileStat>, Error>;
+
+    /// Lists the entries of a remote directory, in any order.
+    fn read_dir(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error>;
+
+    /// Removes a remote file or empty directory.
+    fn remove(&self, remote_path: &Path) -> Result<(), Error>;
+
+    /// Creates a remote symlink pointing at a target, replacing a file or symlink that's in the way.
+    fn symlink(&self, target: &Path, remote_path: &Path) -> Result<(), Error> {
+        let _ = target;
+
+        Err(Error::new(ErrorKind::Unsupported, format!("The backend can't create the symlink '{}'!", remote_path.display())))
+    }
+
+    /// Gets the target of a remote symlink.
+    fn read_link(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        Err(Error::new(ErrorKind::Unsupported, format!("The backend can't read the symlink '{}'!", remote_path.display())))
+    }
+
+    /// Resolves a remote path to its canonical form, following every symlink in it.
+    fn real_path(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        Err(Error::new(ErrorKind::Unsupported, format!("The backend can't resolve '{}'!", remote_path.display())))
+    }
+}
+
+/// The default backend: files are moved with SCP, directories are handled with SFTP (or the shell, without SFTP).
+pub struct ScpBackend<'a> {
+    sbs: &'a Sbs,
+}
+
+impl<'a> ScpBackend<'a> {
+    /// Creates an SCP backend using the connection of an SBS instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `sbs` - The SBS instance.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let backend = ScpBackend::new(&sbs);
+    /// ```
+    pub fn new(sbs: &'a Sbs) -> Self {
+        Self { sbs }
+    }
+}
+
+impl TransferBackend for ScpBackend<'_> {
+    fn send_file(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        self.sbs.send_file(local_path, remote_path).map_err(Error::from)
+    }
+
+    fn receive_file(&self, remote_path: &Path, local_path: &Path, size: Option<u64>) -> Result<(), Error> {
+        let (mut channel, _) = self.sbs.connection.scp_recv(remote_path)?;
+        let mut local_file = Sbs::create_local_file(local_path)?;
+
+        let received = io::copy(&mut channel, &mut local_file)?;
+
+        // Some servers announce a bogus (e.g. zero) size over SCP, which truncates the download.
+        // The size from the directory listing is trusted instead, re-reading the file over SFTP if they disagree.
+        if let Some(expected) = size.filter(|&expected| expected != received) {
+            warn!(
+                "Received {} of {} bytes of '{}' via SCP, re-reading it via SFTP...",
+                received,
+                expected,
+                remote_path.display(),
+            );
+
+            SftpBackend::new(self.sbs.connection.sftp_handle()?).receive_file(remote_path, local_path, size)?;
+        }
+
+        Ok(())
+    }
+
+    fn make_dir(&self, remote_path: &Path) -> Result<(), Error> {
+        self.sbs.ensure_remote_directory(remote_path)
+    }
+
+    fn stat(&self, remote_path: &Path) -> Result<Option<FileStat>, Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).stat(remote_path)
+    }
+
+    fn read_dir(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).read_dir(remote_path)
+    }
+
+    fn remove(&self, remote_path: &Path) -> Result<(), Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).remove(remote_path)
+    }
+
+    fn symlink(&self, target: &Path, remote_path: &Path) -> Result<(), Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).symlink(target, remote_path)
+    }
+
+    fn read_link(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).read_link(remote_path)
+    }
+
+    fn real_path(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        SftpBackend::new(self.sbs.connection.sftp_handle()?).real_path(remote_path)
+    }
+}
+
+/// A backend that does everything over a single SFTP session, for servers without SCP.
+///
+/// Sent files keep their local modification time.
+pub struct SftpBackend<'a> {
+    sftp: SftpHandle<'a>,
+    dir_mode: i32,
+    file_mode: Option<i32>,
+}
+
+impl<'a> SftpBackend<'a> {
+    /// Creates an SFTP backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `sftp` - The SFTP session.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sbs = Sbs::new(session); // Your SBS instance.
+    ///
+    /// let backend = SftpBackend::new(sbs.connection.sftp_handle().unwrap());
+    /// ```
+    pub fn new(sftp: SftpHandle<'a>) -> Self {
+        Self {
+            sftp,
+            dir_mode: DEFAULT_REMOTE_DIR_MODE,
+            file_mode: None,
+        }
+    }
+
+    /// Sets the permissions of the directories and files it creates, like `remote_dir_mode` and `remote_file_mode` of SBS.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let backend = SftpBackend::new(sbs.connection.sftp_handle().unwrap()).with_modes(0o700, Some(0o600));
+    /// ```
+    pub fn with_modes(mut self, dir_mode: i32, file_mode: Option<i32>) -> Self {
+        self.dir_mode = dir_mode;
+        self.file_mode = file_mode;
+        self
+    }
+}
+
+impl TransferBackend for SftpBackend<'_> {
+    fn send_file(&self, local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+        let metadata = local_path.metadata()?;
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let mut remote_file = self.sftp.call(|sftp| sftp.open_mode(remote_path, flags, self.file_mode.unwrap_or_else(|| Sbs::upload_mode(&metadata)), OpenType::File))?;
+
+        let sent = io::copy(&mut File::open(local_path)?, &mut remote_file)?;
+        if sent != metadata.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "The local file '{}' changed while it was being sent ({} bytes instead of {})!",
+                local_path.display(),
+                sent,
+                metadata.len(),
+            )));
+        }
+
+        let mtime = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+        if mtime.is_some() {
+            remote_file.setstat(FileStat { size: None, uid: None, gid: None, perm: None, atime: mtime, mtime })?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_file(&self, remote_path: &Path, local_path: &Path, size: Option<u64>) -> Result<(), Error> {
+        let mut remote_file = self.sftp.call(|sftp| sftp.open(remote_path))?;
+        let mut local_file = Sbs::create_local_file(local_path)?;
+
+        let received = io::copy(&mut remote_file, &mut local_file)?;
+        if let Some(expected) = size.filter(|&expected| expected != received) {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!(
+                "Received {} of {} bytes of the remote file '{}'!",
+                received,
+                expected,
+                remote_path.display(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn make_dir(&self, remote_path: &Path) -> Result<(), Error> {
+        let mut path = PathBuf::new();
+
+        for component in remote_path.components() {
+            path.push(component.as_os_str());
+
+            match self.stat(&path)? {
+                Some(stat) if stat.is_dir() => {}
+                Some(_) => return Err(Error::new(ErrorKind::InvalidInput, format!("The remote path '{}' is not a directory!", path.display()))),
+                None => {
+                    // Another process may create the directory at the same time, which is fine.
+                    if let Err(err) = self.sftp.call(|sftp| sftp.mkdir(&path, self.dir_mode)) {
+                        if !self.stat(&path)?.is_some_and(|stat| stat.is_dir()) {
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stat(&self, remote_path: &Path) -> Result<Option<FileStat>, Error> {
+        match self.sftp.call(|sftp| sftp.stat(remote_path)) {
+            Ok(stat) => Ok(Some(stat)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_dir(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
+        self.sftp.call(|sftp| sftp.readdir(remote_path))
+    }
+
+    fn remove(&self, remote_path: &Path) -> Result<(), Error> {
+        match self.stat(remote_path)? {
+            Some(stat) if stat.is_dir() => self.sftp.call(|sftp| sftp.rmdir(remote_path)),
+            Some(_) => self.sftp.call(|sftp| sftp.unlink(remote_path)),
+            None => Ok(()),
+        }
+    }
+
+    fn symlink(&self, target: &Path, remote_path: &Path) -> Result<(), Error> {
+        // `lstat`, so a symlink to a directory is replaced rather than taken for one.
+        if self.sftp.call(|sftp| sftp.lstat(remote_path)).is_ok_and(|stat| !stat.is_dir()) {
+            self.sftp.call(|sftp| sftp.unlink(remote_path))?;
+        }
+
+        // Creates the link at the second path, pointing at the first.
+        self.sftp.call(|sftp| sftp.symlink(target, remote_path))
+    }
+
+    fn read_link(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        self.sftp.call(|sftp| sftp.readlink(remote_path))
+    }
+
+    fn real_path(&self, remote_path: &Path) -> Result<PathBuf, Error> {
+        self.sftp.call(|sftp| sftp.realpath(remote_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ssh2::Session;
+
+    use super::*;
+    use crate::util::error::SbsError;
+    use crate::util::settings::SymlinkPolicy;
+    use crate::util::ignore::IgnoreRules;
+
+    /// Records what would be transferred, without a connection.
+    #[derive(Default)]
+    struct MockBackend {
+        calls: RefCell<Vec<String>>,
+        /// The remote files that already exist.
+        existing: Vec<(PathBuf, FileStat)>,
+    }
+
+    impl TransferBackend for MockBackend {
+        fn send_file(&self, _local_path: &Path, remote_path: &Path) -> Result<(), Error> {
+            self.calls.borrow_mut().push(format!("send {}", remote_path.display()));
+
+            Ok(())
+        }
+
+        fn receive_file(&self, remote_path: &Path, local_path: &Path, _size: Option<u64>) -> Result<(), Error> {
+            self.calls.borrow_mut().push(format!("receive {}", remote_path.display()));
+
+            std::fs::write(local_path, "")
+        }
+
+        fn make_dir(&self, remote_path: &Path) -> Result<(), Error> {
+            self.calls.borrow_mut().push(format!("mkdir {}", remote_path.display()));
+
+            Ok(())
+        }
+
+        fn stat(&self, remote_path: &Path) -> Result<Option<FileStat>, Error> {
+            Ok(self.existing.iter().find(|(path, _)| path == remote_path).map(|(_, stat)| stat.clone()))
+        }
+
+        fn read_dir(&self, remote_path: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
+            Ok(self.existing.iter().filter(|(path, _)| path.parent() == Some(remote_path)).cloned().collect())
+        }
+
+        fn remove(&self, _remote_path: &Path) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn symlink(&self, target: &Path, remote_path: &Path) -> Result<(), Error> {
+            self.calls.borrow_mut().push(format!("link {} -> {}", remote_path.display(), target.display()));
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_directory_walks_the_tree_through_the_backend() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("src")).unwrap();
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(directory.join("Cargo.toml"), "").unwrap();
+        std::fs::write(directory.join("Cargo.lock"), "").unwrap();
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.deterministic_order = true;
+        sbs.ignore = IgnoreRules::parse(["*.lock"]);
+
+        let backend = MockBackend::default();
+        let result = sbs.send_directory_with(&backend, &directory, Path::new("/remote"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        result.unwrap();
+        assert_eq!(backend.calls.into_inner(), vec![
+            "mkdir /remote",
+            "send /remote/Cargo.toml",
+            "mkdir /remote/src",
+            "send /remote/src/main.rs",
+        ]);
+    }
+
+    #[test]
+    fn incremental_upload_skips_unchanged_files() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-incremental-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("unchanged.rs"), "fn a() {}").unwrap();
+        std::fs::write(directory.join("changed.rs"), "fn b() {}").unwrap();
+
+        let mtime = directory.join("unchanged.rs").metadata().unwrap().modified().unwrap()
+            .duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let stat = |size| FileStat { size: Some(size), uid: None, gid: None, perm: None, atime: None, mtime: Some(mtime) };
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.incremental_upload = true;
+
+        let backend = MockBackend {
+            existing: vec![(PathBuf::from("/remote/unchanged.rs"), stat(9)), (PathBuf::from("/remote/changed.rs"), stat(1))],
+            ..Default::default()
+        };
+        let result = sbs.send_directory_with(&backend, &directory, Path::new("/remote"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(backend.calls.into_inner(), vec!["mkdir /remote", "send /remote/changed.rs"]);
+        assert_eq!(result.unwrap(), TransferSummary { files: vec![directory.join("changed.rs")], bytes: 9, unchanged: 1 });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_follow_the_policy() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-symlinks-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink("a.txt", directory.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(".", directory.join("loop")).unwrap();
+        std::os::unix::fs::symlink("missing", directory.join("missing.txt")).unwrap();
+
+        let send = |symlinks| {
+            let mut sbs = Sbs::new(Session::new().unwrap());
+            sbs.symlinks = symlinks;
+
+            let backend = MockBackend::default();
+            sbs.send_directory_with(&backend, &directory, Path::new("/remote")).map(|_| backend.calls.into_inner())
+        };
+        let followed = send(SymlinkPolicy::Follow);
+        let recreated = send(SymlinkPolicy::Recreate);
+        let skipped = send(SymlinkPolicy::Skip);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(followed.unwrap(), vec!["mkdir /remote", "send /remote/a.txt", "send /remote/link.txt"]);
+        assert_eq!(recreated.unwrap(), vec![
+            "mkdir /remote",
+            "send /remote/a.txt",
+            "link /remote/link.txt -> a.txt",
+            "link /remote/loop -> .",
+            "link /remote/missing.txt -> missing",
+        ]);
+        assert_eq!(skipped.unwrap(), vec!["mkdir /remote", "send /remote/a.txt"]);
+    }
+
+    #[test]
+    fn receive_matching_only_receives_selected_files() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-matching-{}", std::process::id()));
+        let stat = |perm| FileStat { size: Some(0), uid: None, gid: None, perm: Some(perm), atime: None, mtime: None };
+        let (dir, file) = (stat(0o040755), stat(0o100644));
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.deterministic_order = true;
+
+        let backend = MockBackend {
+            existing: vec![
+                (PathBuf::from("/remote"), dir.clone()),
+                (PathBuf::from("/remote/release"), dir.clone()),
+                (PathBuf::from("/remote/release/myapp"), file.clone()),
+                (PathBuf::from("/remote/release/myapp.d"), file.clone()),
+                (PathBuf::from("/remote/release/deps"), dir.clone()),
+                (PathBuf::from("/remote/release/deps/libfoo.rlib"), file.clone()),
+                (PathBuf::from("/remote/release/deps/libbar.so"), file.clone()),
+                (PathBuf::from("/remote/doc"), dir.clone()),
+                (PathBuf::from("/remote/doc/index.html"), file.clone()),
+                (PathBuf::from("/remote/build"), dir),
+            ],
+            ..Default::default()
+        };
+        let patterns = ["/release/myapp", "*.so", "doc/"].map(String::from);
+        let received = sbs.receive_matching_with(&backend, &directory, Path::new("/remote"), &patterns);
+        let created_build = directory.join("build").exists();
+        let _ = std::fs::remove_dir_all(&directory);
+
+        assert!(!created_build);
+        assert_eq!(backend.calls.into_inner(), vec![
+            "receive /remote/doc/index.html",
+            "receive /remote/release/deps/libbar.so",
+            "receive /remote/release/myapp",
+        ]);
+        assert_eq!(received.unwrap().files, vec![
+            directory.join("doc/index.html"),
+            directory.join("release/deps/libbar.so"),
+            directory.join("release/myapp"),
+        ]);
+    }
+
+    #[test]
+    fn missing_remote_directory_fails_before_creating_anything() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-missing-{}", std::process::id()));
+
+        let sbs = Sbs::new(Session::new().unwrap());
+
+        let received = sbs.receive_directory_with(&MockBackend::default(), &directory, Path::new("/remote/target/release"));
+        let created_output = directory.exists();
+
+        assert!(!created_output);
+        assert!(matches!(received, Err(SbsError::Transfer(err)) if err.kind() == ErrorKind::NotFound
+            && err.to_string() == "The remote directory '/remote/target/release' wasn't found, did the build succeed?"));
+    }
+
+    #[test]
+    fn dry_run_touches_nothing() {
+        let directory = std::env::temp_dir().join(format!("sbs-transfer-dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("main.rs"), "fn main() {}").unwrap();
+
+        let mut sbs = Sbs::new(Session::new().unwrap());
+        sbs.dry_run = true;
+
+        let backend = MockBackend::default();
+        let sent = sbs.send_directory_with(&backend, &directory, Path::new("/remote"));
+        let received = sbs.receive_directory_with(&backend, &directory.join("out"), Path::new("/remote/out"));
+        let created_output = directory.join("out").exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        sent.unwrap();
+        received.unwrap();
+        assert!(!created_output);
+        assert!(backend.calls.into_inner().is_empty());
+    }
+
+    #[test]
+    fn progress_adds_up_the_files() {
+        let mut reported = Vec::new();
+        let mut on_progress = |progress: TransferProgress| reported.push(progress);
+
+        let mut progress = ProgressTracker::new(&mut on_progress, Some(30));
+        progress.file_done(Path::new("a"), 10);
+        progress.file_done(Path::new("b"), 20);
+
+        assert_eq!(reported, vec![
+            TransferProgress { current_file: PathBuf::from("a"), bytes_done: 10, total_bytes: Some(30) },
+            TransferProgress { current_file: PathBuf::from("b"), bytes_done: 30, total_bytes: Some(30) },
+        ]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, warn};
+use ssh2::Channel;
+
+use crate::util::connection::{self, Connection};
+
+/// The longest the tunnel sleeps while neither side has anything to forward, in microseconds.
+const MAX_IDLE_SLEEP_MICROS: u64 = 12_800;
+
+/// How long closing the tunnel may take, so an unresponsive jump host can't hang the run.
+const CLOSE_TIMEOUT_MILLIS: u32 = 1_000;
+
+/// A jump host the server is reached through, like OpenSSH's `ProxyJump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpHost {
+    /// The user on the jump host, the server's user if not set.
+    pub username: Option<String>,
+    pub host: String,
+    pub port: u16,
+}
+
+impl JumpHost {
+    /// Parses a jump host in `[user@]host[:port]` form. IPv6 addresses with a port go in brackets, e.g. `[::1]:22`.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The jump host.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use ssh_build_server::util::tunnel::JumpHost;
+    /// let jump_host = JumpHost::parse("admin@bastion.example.com:2222").unwrap();
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (username, address) = match spec.rsplit_once('@') {
+            Some((username, address)) => (Some(username.to_string()), address),
+            None => (None, spec),
+        };
+
+        let (host, port) = if let Some(rest) = address.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or_else(|| format!("The jump host '{}' lacks a closing bracket!", spec))?;
+
+            (host, rest.strip_prefix(':'))
+        } else {
+            match address.split_once(':') {
+                // More than one colon is an IPv6 address without a port.
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                _ => (address, None),
+            }
+        };
+
+        let port = match port {
+            Some(port) => port.parse().ok().filter(|&port| port != 0).ok_or_else(|| format!("The jump host '{}' has an invalid port!", spec))?,
+            None => 22,
+        };
+        if host.is_empty() || username.as_deref().is_some_and(str::is_empty) {
+            return Err(format!("The jump host '{}' must look like [user@]host[:port]!", spec));
+        }
+
+        Ok(Self {
+            username,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Gets the address of the jump host, in `host:port` form.
+    pub fn address(&self) -> String {
+        connection::socket_address(&self.host, self.port)
+    }
+}
+
+/// Forwards a local TCP stream to a server through a `direct-tcpip` channel of the jump host.
+///
+/// libssh2 needs a socket to run a session over, so the server's session is given one end of a loopback connection,
+/// and a thread pumps the bytes between the other end and the channel. The tunnel closes when it's dropped.
+pub struct Tunnel {
+    stream: Option<TcpStream>,
+    stop: Arc<AtomicBool>,
+    pump: Option<JoinHandle<()>>,
+}
+
+impl Tunnel {
+    /// Opens a tunnel to the server through an authenticated connection to the jump host.
+    ///
+    /// # Arguments
+    ///
+    /// * `jump` - The connection to the jump host, which the tunnel takes over.
+    /// * `host` - The host of the server, as seen from the jump host.
+    /// * `port` - The port of the server.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut tunnel = Tunnel::open(jump, "build-server", 22).unwrap();
+    ///
+    /// session.set_tcp_stream(tunnel.take_stream().unwrap());
+    /// ```
+    pub fn open(jump: Connection, host: &str, port: u16) -> Result<Self, Error> {
+        let channel = jump.direct_tcpip(host, port)?;
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let (local, peer) = listener.accept()?;
+
+        // Anyone on the machine could have connected to the listener first.
+        if peer != stream.local_addr()? {
+            return Err(Error::new(ErrorKind::ConnectionRefused, "Another process connected to the tunnel!"));
+        }
+
+        local.set_nonblocking(true)?;
+        local.set_nodelay(true)?;
+        stream.set_nodelay(true)?;
+        jump.session().set_blocking(false);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let pump = {
+            let stop = Arc::clone(&stop);
+
+            thread::spawn(move || pump(jump, channel, local, &stop))
+        };
+
+        Ok(Self {
+            stream: Some(stream),
+            stop,
+            pump: Some(pump),
+        })
+    }
+
+    /// Takes the stream the server's session runs over. It's only there once.
+    pub fn take_stream(&mut self) -> Option<TcpStream> {
+        self.stream.take()
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(pump) = self.pump.take() {
+            let _ = pump.join();
+        }
+    }
+}
+
+/// Forwards bytes both ways until either side closes or the tunnel is stopped, then closes the jump host connection.
+fn pump(mut jump: Connection, mut channel: Channel, mut local: TcpStream, stop: &AtomicBool) {
+    if let Err(err) = forward(&mut channel, &mut local, stop) {
+        // Expected while a session is being torn down, and surfaces as an error of the server's session otherwise.
+        debug!("The tunnel to the server closed: {}", err);
+    }
+
+    jump.session().set_blocking(true);
+    jump.session().set_timeout(CLOSE_TIMEOUT_MILLIS);
+
+    let _ = channel.close();
+    if let Err(err) = jump.disconnect(None, "The tunnel was closed.", None) {
+        warn!("Failed to disconnect from the jump host: {}", err);
+    }
+}
+
+/// Copies between the channel and the local stream, both non-blocking, sleeping longer the longer both are idle.
+fn forward(channel: &mut Channel, local: &mut TcpStream, stop: &AtomicBool) -> Result<(), Error> {
+    let mut buffer = vec![0; 32 * 1024];
+    // What was read from one side but couldn't be written to the other yet.
+    let mut outgoing = Vec::new();
+    let mut incoming = Vec::new();
+    let mut idle_rounds = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut idle = true;
+
+        if outgoing.is_empty() {
+            match local.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => outgoing.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !outgoing.is_empty() {
+            match channel.write(&outgoing) {
+                Ok(written) => {
+                    outgoing.drain(..written);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if incoming.is_empty() {
+            match channel.read(&mut buffer) {
+                Ok(0) if channel.eof() => return Ok(()),
+                Ok(read) => incoming.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !incoming.is_empty() {
+            match local.write(&incoming) {
+                Ok(written) => {
+                    incoming.drain(..written);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if idle {
+            thread::sleep(Duration::from_micros((100 << idle_rounds).min(MAX_IDLE_SLEEP_MICROS)));
+            idle_rounds = (idle_rounds + 1).min(7);
+        } else {
+            idle_rounds = 0;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_hosts_are_parsed_like_proxy_jump() {
+        let jump_host = |username: Option<&str>, host: &str, port| JumpHost { username: username.map(String::from), host: host.to_string(), port };
+
+        assert_eq!(JumpHost::parse("bastion"), Ok(jump_host(None, "bastion", 22)));
+        assert_eq!(JumpHost::parse("admin@bastion:2222"), Ok(jump_host(Some("admin"), "bastion", 2222)));
+        assert_eq!(JumpHost::parse("[::1]:2222"), Ok(jump_host(None, "::1", 2222)));
+        assert_eq!(JumpHost::parse("fe80::1"), Ok(jump_host(None, "fe80::1", 22)));
+        assert_eq!(jump_host(None, "::1", 2222).address(), "[::1]:2222");
+
+        assert!(JumpHost::parse("bastion:ssh").is_err());
+        assert!(JumpHost::parse("@bastion").is_err());
+        assert!(JumpHost::parse("").is_err());
+    }
+}
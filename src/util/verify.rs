@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::util::ignore::IgnoreRules;
+use crate::util::shell;
+use crate::util::ssh::Sbs;
+
+/// What is known about a file when comparing trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSummary {
+    pub size: u64,
+    /// The hex-encoded SHA-256 of the contents, if hashes are compared.
+    pub hash: Option<String>,
+}
+
+/// The differences between a local tree and its uploaded copy, by relative path.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TreeDifference {
+    /// Files that exist locally, but not on the remote.
+    pub missing: Vec<PathBuf>,
+    /// Files that exist on the remote, but not locally.
+    pub extra: Vec<PathBuf>,
+    /// Files whose size or hash differs.
+    pub differing: Vec<PathBuf>,
+}
+
+impl TreeDifference {
+    /// Checks whether the trees match.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.differing.is_empty()
+    }
+}
+
+impl fmt::Display for TreeDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "The trees match.");
+        }
+
+        for (label, paths) in [("Missing on remote", &self.missing), ("Extra on remote", &self.extra), ("Differing", &self.differing)] {
+            for path in paths {
+                writeln!(f, "{}: {}", label, path.display())?;
+            }
+        }
+
+        write!(f, "{} missing, {} extra and {} differing file(s).", self.missing.len(), self.extra.len(), self.differing.len())
+    }
+}
+
+/// Compares two trees of files.
+///
+/// # Arguments
+///
+/// * `local` - The local files, by relative path.
+/// * `remote` - The remote files, by relative path.
+///
+/// # Examples
+///
+/// ```ignore
+/// let difference = compare(&local, &remote);
+///
+/// println!("{}", difference);
+/// ```
+pub fn compare(local: &BTreeMap<PathBuf, FileSummary>, remote: &BTreeMap<PathBuf, FileSummary>) -> TreeDifference {
+    let mut difference = TreeDifference::default();
+
+    for (path, summary) in local {
+        match remote.get(path) {
+            None => difference.missing.push(path.clone()),
+            Some(remote_summary) if remote_summary != summary => difference.differing.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+
+    difference.extra = remote.keys()
+        .filter(|path| !local.contains_key(*path))
+        .cloned()
+        .collect();
+
+    difference
+}
+
+/// Checks that a remote directory mirrors a local one.
+///
+/// Sizes are always compared, hashes only if requested, since hashing reads every file on both ends.
+///
+/// # Arguments
+///
+/// * `sbs` - A connected SBS instance.
+/// * `local_path` - The local directory.
+/// * `remote_path` - The remote directory it was uploaded to.
+/// * `hashes` - Whether to compare SHA-256 hashes as well.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sbs = Sbs::new(session); // Your connected SBS instance.
+///
+/// let difference = verify_upload(&sbs, Path::new("/path/to/project"), Path::new("/remote/project"), false).unwrap();
+/// ```
+pub fn verify_upload(sbs: &Sbs, local_path: &Path, remote_path: &Path, hashes: bool) -> Result<TreeDifference, Error> {
+    let mut local = BTreeMap::new();
+    local_tree(local_path, Path::new(""), &sbs.ignore, hashes, &mut local)?;
+
+    let mut remote = BTreeMap::new();
+    for (path, stat) in sbs.walk_remote_directory(remote_path)? {
+        if stat.is_dir() {
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(remote_path).unwrap_or(&path).to_path_buf();
+        remote.insert(relative_path, FileSummary { size: stat.size.unwrap_or(0), hash: None });
+    }
+
+    if hashes {
+        for (path, hash) in remote_hashes(sbs, remote_path)? {
+            if let Some(summary) = remote.get_mut(&path) {
+                summary.hash = Some(hash);
+            }
+        }
+    }
+
+    Ok(compare(&local, &remote))
+}
+
+/// Checks that every file of a remote directory was downloaded intact, by comparing SHA-256 hashes.
+///
+/// Local files that don't exist on the remote are left alone, since the download doesn't remove them.
+/// The error lists the files that are missing or differ.
+///
+/// # Arguments
+///
+/// * `sbs` - A connected SBS instance.
+/// * `local_path` - The local directory it was downloaded to.
+/// * `remote_path` - The remote directory.
+/// * `patterns` - The patterns the download was limited to, see `Sbs::receive_matching`. Empty if everything was downloaded.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sbs = Sbs::new(session); // Your connected SBS instance.
+///
+/// verify_download(&sbs, Path::new("/path/to/output"), Path::new("/remote/project/target/release"), &[]).unwrap();
+/// ```
+pub fn verify_download(sbs: &Sbs, local_path: &Path, remote_path: &Path, patterns: &[String]) -> Result<(), Error> {
+    let selection = IgnoreRules::parse(patterns.iter().map(String::as_str));
+    let remote_hashes = remote_hashes(sbs, remote_path)?.into_iter()
+        .filter(|(path, _)| selection.is_empty() || selection.selects_file(path))
+        .collect();
+
+    let mismatches = mismatched_files(local_path, remote_hashes)?;
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+
+    let listed = mismatches.iter().map(|path| format!("\n{}", path.display())).collect::<String>();
+
+    Err(Error::new(ErrorKind::InvalidData, format!("{} downloaded file(s) don't match the remote checksums:{}", mismatches.len(), listed)))
+}
+
+/// Lists the files whose local copy is missing or doesn't have the remote hash.
+fn mismatched_files(local_path: &Path, remote_hashes: Vec<(PathBuf, String)>) -> Result<Vec<PathBuf>, Error> {
+    let mut mismatches = Vec::new();
+
+    for (path, remote_hash) in remote_hashes {
+        let matches = match hash_file(&local_path.join(&path)) {
+            Ok(local_hash) => local_hash == remote_hash,
+            Err(err) if err.kind() == ErrorKind::NotFound => false,
+            Err(err) => return Err(err),
+        };
+
+        if !matches {
+            mismatches.push(path);
+        }
+    }
+    mismatches.sort();
+
+    Ok(mismatches)
+}
+
+/// Collects the files below a local directory that aren't ignored, following symbolic links like the upload does.
+pub(crate) fn local_tree(root: &Path, relative_path: &Path, ignore: &IgnoreRules, hashes: bool, files: &mut BTreeMap<PathBuf, FileSummary>) -> Result<(), Error> {
+    for entry in root.join(relative_path).read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative_path = relative_path.join(entry.file_name());
+
+        if ignore.is_ignored(&relative_path, path.is_dir()) {
+            continue;
+        }
+
+        if path.is_dir() {
+            local_tree(root, &relative_path, ignore, hashes, files)?;
+        } else {
+            let hash = if hashes { Some(hash_file(&path)?) } else { None };
+
+            files.insert(relative_path, FileSummary { size: path.metadata()?.len(), hash });
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes the hex-encoded SHA-256 of a local file.
+pub fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Computes the SHA-256 of every file below a remote directory with `sha256sum`.
+fn remote_hashes(sbs: &Sbs, remote_path: &Path) -> Result<Vec<(PathBuf, String)>, Error> {
+    let command = format!("cd {} && find . -type f -exec sha256sum {{}} +", shell::quote(&remote_path.to_string_lossy()));
+
+    match sbs.run_remote_command(&command)? {
+        (0, output) => Ok(parse_sha256sum(&output)),
+        (_, output) => Err(Error::other(format!("Failed to hash the remote files: {}", output.trim()))),
+    }
+}
+
+/// Parses the output of `sha256sum` run on paths relative to the current directory.
+fn parse_sha256sum(output: &str) -> Vec<(PathBuf, String)> {
+    output.lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, path)| (PathBuf::from(path.strip_prefix("./").unwrap_or(path)), hash.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(size: u64) -> FileSummary {
+        FileSummary { size, hash: None }
+    }
+
+    #[test]
+    fn compare_reports_missing_extra_and_differing_files() {
+        let local = BTreeMap::from([
+            (PathBuf::from("same.rs"), summary(1)),
+            (PathBuf::from("missing.rs"), summary(2)),
+            (PathBuf::from("changed.rs"), summary(3)),
+        ]);
+        let remote = BTreeMap::from([
+            (PathBuf::from("same.rs"), summary(1)),
+            (PathBuf::from("changed.rs"), summary(4)),
+            (PathBuf::from("extra.rs"), summary(5)),
+        ]);
+
+        assert_eq!(compare(&local, &remote), TreeDifference {
+            missing: vec![PathBuf::from("missing.rs")],
+            extra: vec![PathBuf::from("extra.rs")],
+            differing: vec![PathBuf::from("changed.rs")],
+        });
+    }
+
+    #[test]
+    fn mismatched_files_lists_truncated_and_missing_files() {
+        let directory = std::env::temp_dir().join(format!("sbs-verify-download-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("intact"), "").unwrap();
+        std::fs::write(directory.join("truncated"), "").unwrap();
+
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string();
+        let remote_hashes = vec![
+            (PathBuf::from("truncated"), "0".repeat(64)),
+            (PathBuf::from("intact"), empty.clone()),
+            (PathBuf::from("missing"), empty),
+        ];
+        let mismatches = mismatched_files(&directory, remote_hashes);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(mismatches.unwrap(), vec![PathBuf::from("missing"), PathBuf::from("truncated")]);
+    }
+
+    #[test]
+    fn parses_sha256sum_output() {
+        let output = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./src/main.rs\n";
+
+        assert_eq!(parse_sha256sum(output), vec![(
+            PathBuf::from("src/main.rs"),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+        )]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::util::ignore::IgnoreRules;
+
+/// Counts the files below a local directory that aren't ignored, stopping early once the count exceeds the limit.
+///
+/// Symbolic links are counted as files and never followed.
+///
+/// # Arguments
+///
+/// * `root` - The local directory.
+/// * `ignore` - The files to leave out.
+/// * `limit` - The count after which to stop walking.
+///
+/// # Examples
+///
+/// ```ignore
+/// let count = count_files(Path::new("/path/to/project"), &IgnoreRules::default(), 1000).unwrap();
+/// ```
+pub fn count_files(root: &Path, ignore: &IgnoreRules, limit: usize) -> Result<usize, Error> {
+    let mut count = 0;
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in root.join(&directory).read_dir()? {
+            let entry = entry?;
+            let relative_path = directory.join(entry.file_name());
+            let is_dir = entry.file_type()?.is_dir();
+
+            if ignore.is_ignored(&relative_path, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                directories.push(relative_path);
+            } else {
+                count += 1;
+
+                if count > limit {
+                    return Ok(count);
+                }
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+/// Sums up the sizes of the files below a local directory that aren't ignored.
+///
+/// # Arguments
+///
+/// * `root` - The local directory.
+/// * `ignore` - The files to leave out.
+///
+/// # Examples
+///
+/// ```ignore
+/// let bytes = total_size(Path::new("/path/to/project"), &IgnoreRules::default()).unwrap();
+/// ```
+pub fn total_size(root: &Path, ignore: &IgnoreRules) -> Result<u64, Error> {
+    let mut size = 0;
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in root.join(&directory).read_dir()? {
+            let entry = entry?;
+            let relative_path = directory.join(entry.file_name());
+            let metadata = entry.path().metadata()?;
+
+            if ignore.is_ignored(&relative_path, metadata.is_dir()) {
+                continue;
+            }
+
+            if metadata.is_dir() {
+                directories.push(relative_path);
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// Fails if a local directory contains more files than allowed.
+///
+/// This catches a project root that accidentally points at a huge directory before anything is uploaded.
+///
+/// # Arguments
+///
+/// * `root` - The local directory.
+/// * `ignore` - The files to leave out.
+/// * `max_files` - The maximum number of files, or `0` for no limit.
+///
+/// # Examples
+///
+/// ```ignore
+/// check_file_limit(Path::new("/path/to/project"), &IgnoreRules::default(), 100_000).unwrap();
+/// ```
+pub fn check_file_limit(root: &Path, ignore: &IgnoreRules, max_files: usize) -> Result<(), Error> {
+    // A missing root is reported by the upload itself.
+    if max_files == 0 || !root.is_dir() {
+        return Ok(());
+    }
+
+    let count = count_files(root, ignore, max_files)?;
+    if count > max_files {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "The local path '{}' contains more than {} files (the max_files limit), is local_project_root correct?",
+            root.display(),
+            max_files,
+        )));
+    }
+
+    Ok(())
+}
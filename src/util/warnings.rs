@@ -0,0 +1,170 @@
+use std::io::{Error, ErrorKind};
+
+use log::warn;
+use regex::Regex;
+
+use crate::util::settings::Command;
+use crate::util::temp;
+
+/// The pattern that detects warnings when a command doesn't configure its own.
+pub const DEFAULT_WARNING_PATTERN: &str = r"(?im)^\s*warning\b";
+
+/// Gets the marker echoed ahead of a command, so its output can be told apart from the others'.
+///
+/// # Arguments
+///
+/// * `token` - A token unique to the run, so the marker can't be mistaken for real output.
+/// * `index` - The index of the command.
+pub fn marker(token: &str, index: usize) -> String {
+    format!("{}{}", marker_prefix(token), index)
+}
+
+/// Gets the part of the markers that precedes the index of the command, see [`marker`].
+///
+/// # Arguments
+///
+/// * `token` - The token the markers are made with.
+pub fn marker_prefix(token: &str) -> String {
+    format!("sbs-command-{}-", token)
+}
+
+/// Checks whether a script of the commands needs markers, because their output has to be told apart
+/// (to check for warnings) or the running command has to be known (to enforce its timeout).
+///
+/// # Arguments
+///
+/// * `commands` - The commands.
+pub fn needs_markers(commands: &[Command]) -> bool {
+    commands.iter().any(|command| command.warnings_as_errors || command.timeout_secs.is_some())
+}
+
+/// Splits the output of a script into the output of each command, removing the markers.
+///
+/// # Arguments
+///
+/// * `output` - The output of the whole script.
+/// * `token` - The token the markers were made with.
+///
+/// # Examples
+///
+/// ```ignore
+/// let (output, segments) = split_output(&output, temp::run_token());
+/// ```
+pub fn split_output(output: &str, token: &str) -> (String, Vec<(usize, String)>) {
+    let prefix = marker_prefix(token);
+
+    let mut cleaned = String::new();
+    let mut segments: Vec<(usize, String)> = Vec::new();
+    for line in output.split_inclusive('\n') {
+        if let Some(index) = line.trim_end().strip_prefix(&prefix).and_then(|index| index.parse().ok()) {
+            segments.push((index, String::new()));
+
+            continue;
+        }
+
+        cleaned.push_str(line);
+        if let Some((_, segment)) = segments.last_mut() {
+            segment.push_str(line);
+        }
+    }
+
+    (cleaned, segments)
+}
+
+/// Fails if a command that treats warnings as errors emitted one, and removes the markers from the output.
+///
+/// # Arguments
+///
+/// * `commands` - The commands that were run, in order.
+/// * `output` - The output of the script.
+///
+/// # Examples
+///
+/// ```ignore
+/// let output = enforce(&commands, output).unwrap();
+/// ```
+pub fn enforce(commands: &[Command], output: String) -> Result<String, Error> {
+    if !needs_markers(commands) {
+        return Ok(output);
+    }
+
+    let (cleaned, segments) = split_output(&output, temp::run_token());
+    for (index, segment) in segments {
+        let Some(command) = commands.get(index) else {
+            continue;
+        };
+
+        match check_warnings(command, &segment) {
+            Err(err) if command.continue_on_error => warn!("{} Continuing anyway.", err),
+            result => result?,
+        }
+    }
+
+    Ok(cleaned)
+}
+
+/// Fails if a command that treats warnings as errors emitted one.
+///
+/// # Arguments
+///
+/// * `command` - The command.
+/// * `output` - The output of the command.
+///
+/// # Examples
+///
+/// ```ignore
+/// check_warnings(&command, "warning: unused variable `x`\n").unwrap_err();
+/// ```
+pub fn check_warnings(command: &Command, output: &str) -> Result<(), Error> {
+    if !command.warnings_as_errors {
+        return Ok(());
+    }
+
+    let pattern = command.warning_pattern.as_deref().unwrap_or(DEFAULT_WARNING_PATTERN);
+    let regex = Regex::new(pattern)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("The warning pattern '{}' is invalid: {}", pattern, err)))?;
+
+    match regex.find(output) {
+        Some(found) => {
+            let line = output[found.start()..].lines().next().unwrap_or_default().trim();
+
+            Err(Error::other(format!("The command `{}` emitted a warning, which is treated as an error: {}", command.command, line)))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(warning_pattern: Option<&str>) -> Command {
+        Command {
+            warnings_as_errors: true,
+            warning_pattern: warning_pattern.map(String::from),
+            ..Command::new("cargo build", "Build the project.")
+        }
+    }
+
+    #[test]
+    fn split_output_attributes_lines_to_commands() {
+        let output = "sbs-command-t-0\nfirst\nsbs-command-t-1\nsecond\nthird\n";
+
+        let (cleaned, segments) = split_output(output, "t");
+
+        assert_eq!(cleaned, "first\nsecond\nthird\n");
+        assert_eq!(segments, vec![(0, "first\n".to_string()), (1, "second\nthird\n".to_string())]);
+    }
+
+    #[test]
+    fn default_pattern_matches_compiler_warnings() {
+        assert!(check_warnings(&command(None), "   Compiling app\nwarning: unused variable `x`\n").is_err());
+        assert!(check_warnings(&command(None), "   Compiling app\n    Finished release\n").is_ok());
+    }
+
+    #[test]
+    fn custom_pattern_is_used() {
+        assert!(check_warnings(&command(Some("deprecated")), "warning: unused\n").is_ok());
+        assert!(check_warnings(&command(Some("deprecated")), "note: `foo` is deprecated\n").is_err());
+    }
+}
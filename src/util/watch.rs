@@ -0,0 +1,169 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use log::debug;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::util::ignore::IgnoreRules;
+
+/// Watches a local project root for changes worth rebuilding for.
+///
+/// Changes to ignored files and below the excluded directories (like the local output directory, which every download
+/// writes to) don't count, and neither does merely reading files.
+pub struct ProjectWatcher {
+    filter: ChangeFilter,
+    debounce: Duration,
+    events: Receiver<notify::Result<Event>>,
+    // Watching stops once it's dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl ProjectWatcher {
+    /// Starts watching a local project root recursively.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The local project root.
+    /// * `ignore` - The files whose changes don't count, relative to the root.
+    /// * `excluded` - Directories whose changes don't count, whether or not they're inside the root.
+    /// * `debounce` - How long it has to stay quiet after a change before it's reported.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let watcher = ProjectWatcher::new(Path::new("/path/to/project"), IgnoreRules::parse([".git/"]), &[PathBuf::from("/path/to/project/out")], Duration::from_millis(500)).unwrap();
+    /// ```
+    pub fn new(root: &Path, ignore: IgnoreRules, excluded: &[PathBuf], debounce: Duration) -> Result<Self, Error> {
+        let root = root.canonicalize()?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver only goes away along with the watcher.
+            let _ = sender.send(event);
+        }).map_err(to_io_error)?;
+        watcher.watch(&root, RecursiveMode::Recursive).map_err(to_io_error)?;
+
+        Ok(Self {
+            filter: ChangeFilter {
+                excluded: excluded.iter().map(|path| absolute(path)).collect(),
+                root,
+                ignore,
+            },
+            debounce,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Waits for a change, then until nothing changed for the debounce interval, so a burst of saves triggers one rebuild.
+    ///
+    /// Returns the changed paths, relative to the root.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let changed = watcher.wait_for_changes().unwrap();
+    ///
+    /// println!("{} file(s) changed, rebuilding...", changed.len());
+    /// ```
+    pub fn wait_for_changes(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut changed = Vec::new();
+
+        while changed.is_empty() {
+            let event = self.events.recv().map_err(|_| Error::new(ErrorKind::BrokenPipe, "The file watcher stopped!"))?;
+            self.collect(event.map_err(to_io_error)?, &mut changed);
+        }
+
+        loop {
+            match self.events.recv_timeout(self.debounce) {
+                Ok(event) => self.collect(event.map_err(to_io_error)?, &mut changed),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::BrokenPipe, "The file watcher stopped!")),
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+
+        Ok(changed)
+    }
+
+    /// Adds the paths of an event that count as changes.
+    fn collect(&self, event: Event, changed: &mut Vec<PathBuf>) {
+        for path in &event.paths {
+            match self.filter.relevant_path(&event.kind, path) {
+                Some(relative_path) => changed.push(relative_path),
+                None => debug!("Ignoring the change of '{}'.", path.display()),
+            }
+        }
+    }
+}
+
+/// Decides which changes are worth rebuilding for.
+struct ChangeFilter {
+    root: PathBuf,
+    ignore: IgnoreRules,
+    excluded: Vec<PathBuf>,
+}
+
+impl ChangeFilter {
+    /// Gets the path relative to the root, if the change counts.
+    fn relevant_path(&self, kind: &EventKind, path: &Path) -> Option<PathBuf> {
+        if matches!(kind, EventKind::Access(_)) || self.excluded.iter().any(|excluded| path.starts_with(excluded)) {
+            return None;
+        }
+
+        let relative_path = path.strip_prefix(&self.root).ok()?;
+        if relative_path.as_os_str().is_empty() {
+            return None;
+        }
+
+        // A change deep inside an ignored directory is ignored as well.
+        let ignored = self.ignore.is_ignored(relative_path, path.is_dir()) || relative_path.ancestors()
+            .skip(1)
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .any(|directory| self.ignore.is_ignored(directory, true));
+
+        (!ignored).then(|| relative_path.to_path_buf())
+    }
+}
+
+/// Makes a path absolute, resolving symlinks if it exists already.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| std::env::current_dir().map(|directory| directory.join(path)).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+/// Converts an error of the file watcher.
+fn to_io_error(err: notify::Error) -> Error {
+    match err.kind {
+        notify::ErrorKind::Io(err) => err,
+        kind => Error::other(format!("{:?}", kind)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+    use super::*;
+
+    #[test]
+    fn only_relevant_changes_count() {
+        let filter = ChangeFilter {
+            root: PathBuf::from("/project"),
+            ignore: IgnoreRules::parse([".git/", "*.swp"]),
+            excluded: vec![PathBuf::from("/project/out")],
+        };
+        let modify = EventKind::Modify(ModifyKind::Any);
+
+        assert_eq!(filter.relevant_path(&modify, Path::new("/project/src/main.rs")), Some(PathBuf::from("src/main.rs")));
+        assert_eq!(filter.relevant_path(&EventKind::Create(CreateKind::File), Path::new("/project/build.rs")), Some(PathBuf::from("build.rs")));
+        assert_eq!(filter.relevant_path(&EventKind::Access(AccessKind::Any), Path::new("/project/src/main.rs")), None);
+        assert_eq!(filter.relevant_path(&modify, Path::new("/project/.git/objects/ab/cdef")), None);
+        assert_eq!(filter.relevant_path(&modify, Path::new("/project/src/.main.rs.swp")), None);
+        assert_eq!(filter.relevant_path(&modify, Path::new("/project/out/myapp")), None);
+        assert_eq!(filter.relevant_path(&modify, Path::new("/elsewhere/main.rs")), None);
+    }
+}
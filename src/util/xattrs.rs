@@ -0,0 +1,152 @@
+use std::io::Error;
+use std::path::Path;
+
+use crate::util::shell;
+
+/// An extended attribute as a name and its raw value.
+pub type ExtendedAttribute = (String, Vec<u8>);
+
+/// Reads all extended attributes of a local file.
+///
+/// # Arguments
+///
+/// * `path` - The local path.
+///
+/// # Examples
+///
+/// ```ignore
+/// for (name, value) in read_local(Path::new("/path/to/file")).unwrap() {
+///     println!("{} = {} bytes", name, value.len());
+/// }
+/// ```
+#[cfg(unix)]
+pub fn read_local(path: &Path) -> Result<Vec<ExtendedAttribute>, Error> {
+    let mut attributes = Vec::new();
+
+    for name in xattr::list(path)? {
+        if let Some(value) = xattr::get(path, &name)? {
+            attributes.push((name.to_string_lossy().into_owned(), value));
+        }
+    }
+
+    Ok(attributes)
+}
+
+/// Reads all extended attributes of a local file.
+///
+/// Extended attributes are only supported on Unix, so this always returns an empty list.
+#[cfg(not(unix))]
+pub fn read_local(_path: &Path) -> Result<Vec<ExtendedAttribute>, Error> {
+    Ok(Vec::new())
+}
+
+/// Applies extended attributes to a local file.
+///
+/// # Arguments
+///
+/// * `path` - The local path.
+/// * `attributes` - The attributes to set.
+///
+/// # Examples
+///
+/// ```ignore
+/// let attributes = vec![("user.comment".to_string(), b"hello".to_vec())];
+///
+/// write_local(Path::new("/path/to/file"), &attributes).unwrap();
+/// ```
+#[cfg(unix)]
+pub fn write_local(path: &Path, attributes: &[ExtendedAttribute]) -> Result<(), Error> {
+    for (name, value) in attributes {
+        xattr::set(path, name, value)?;
+    }
+
+    Ok(())
+}
+
+/// Applies extended attributes to a local file.
+///
+/// Extended attributes are only supported on Unix, so this does nothing.
+#[cfg(not(unix))]
+pub fn write_local(_path: &Path, _attributes: &[ExtendedAttribute]) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Builds a remote shell command that sets the given attributes with `setfattr`.
+///
+/// # Arguments
+///
+/// * `remote_path` - The remote path.
+/// * `attributes` - The attributes to set.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::Path;
+/// # use ssh_build_server::util::xattrs::set_command;
+/// let attributes = vec![("user.comment".to_string(), b"hi".to_vec())];
+///
+/// assert_eq!(
+///     set_command(Path::new("/tmp/file"), &attributes),
+///     "setfattr -n 'user.comment' -v 0x6869 '/tmp/file'",
+/// );
+/// ```
+pub fn set_command(remote_path: &Path, attributes: &[ExtendedAttribute]) -> String {
+    let path = shell::quote(&remote_path.to_string_lossy());
+
+    attributes.iter()
+        .map(|(name, value)| format!("setfattr -n {} -v 0x{} {}", shell::quote(name), to_hex(value), path))
+        .collect::<Vec<_>>()
+        .join(" && ")
+}
+
+/// Builds a remote shell command that dumps all attributes of a file with `getfattr`.
+///
+/// The output can be parsed with [`parse_get_output`].
+///
+/// # Arguments
+///
+/// * `remote_path` - The remote path.
+pub fn get_command(remote_path: &Path) -> String {
+    format!("getfattr --absolute-names -d -m - -e hex {}", shell::quote(&remote_path.to_string_lossy()))
+}
+
+/// Parses the output of a `getfattr -d -e hex` invocation.
+///
+/// # Arguments
+///
+/// * `output` - The command output.
+///
+/// # Examples
+///
+/// ```
+/// # use ssh_build_server::util::xattrs::parse_get_output;
+/// let output = "# file: /tmp/file\nuser.comment=0x6869\n";
+///
+/// assert_eq!(parse_get_output(output), vec![("user.comment".to_string(), b"hi".to_vec())]);
+/// ```
+pub fn parse_get_output(output: &str) -> Vec<ExtendedAttribute> {
+    output.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .filter_map(|(name, value)| {
+            let value = value.strip_prefix("0x")?;
+
+            Some((name.to_string(), from_hex(value)?))
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}